@@ -0,0 +1,322 @@
+//! Workspace configuration read from `dry-handlebars.toml` at the crate root
+//!
+//! The file sets defaults for every `directory!`, `file!` and `str!` call in the crate, an
+//! argument given to a macro takes precedence. Only a subset of TOML is read: top level
+//! `key = value` lines with string, boolean or string array values and `#` comments.
+//!
+//! ```toml
+//! # searched after DRY_HBS_TEMPLATE_ROOT, before the crate root
+//! template_roots = ["templates", "shared/templates"]
+//! # "html" (the default) or "none" to write `{{ }}` output unescaped
+//! escape = "html"
+//! # report template warnings as errors
+//! strict = true
+//! # drop whitespace containing a line break between tags
+//! minify = true
+//! newlines = "lf"
+//! partial_indent = true
+//! segments = false
+//! # report the type chosen for each variable as a warning
+//! verbose = false
+//! # document each template's struct with its render code, formatted with the `pretty` feature
+//! pretty = false
+//! # render through one non-generic function per template instead of one per instantiation
+//! compact = false
+//! # add nonce="{{nonce}}" to <script> and <style> tags
+//! csp_nonce = false
+//! # give unmapped `{{#if x}}` subjects the type bool, otherwise they must be mapped
+//! infer_bool = true
+//! # report a local declared with `as |name|` hiding another variable as an error
+//! deny_shadowing = false
+//! # warn about `id` attributes the rendered page would repeat
+//! check_ids = true
+//! # "order" of first use (the default), "sorted" by name or "named" for builders only
+//! arguments = "sorted"
+//! # `key = message` lines, keys of the `t` helper missing from it are errors
+//! i18n_catalog = "locales/en.properties"
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file
+const FILE_NAME: &str = "dry-handlebars.toml";
+
+/// Line endings templates can be normalized to
+#[derive(Clone, Copy)]
+pub enum Newlines {
+    Lf,
+    Crlf,
+}
+
+impl Newlines {
+    /// Parses `"lf"` or `"crlf"`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "lf" => Ok(Newlines::Lf),
+            "crlf" => Ok(Newlines::Crlf),
+            other => Err(format!(
+                "unknown newlines `{}`, expected \"lf\" or \"crlf\"",
+                other
+            )),
+        }
+    }
+
+    pub fn normalize(self, content: &str) -> String {
+        let lf = content.replace("\r\n", "\n");
+        match self {
+            Newlines::Lf => lf,
+            Newlines::Crlf => lf.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// Order of the arguments of a template's `new()` and function
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Arguments {
+    /// The order the variables are first used in the template
+    Order,
+    /// Sorted by name, so editing the template doesn't reorder them
+    Sorted,
+    /// No positional `new()` and function, the struct is built by field name
+    Named,
+}
+
+impl Arguments {
+    /// Parses `"order"`, `"sorted"` or `"named"`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "order" => Ok(Arguments::Order),
+            "sorted" => Ok(Arguments::Sorted),
+            "named" => Ok(Arguments::Named),
+            other => Err(format!(
+                "unknown arguments `{}`, expected \"order\", \"sorted\" or \"named\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Settings of a `dry-handlebars.toml`, the defaults when there is none
+#[derive(Clone)]
+pub struct Config {
+    /// Path of the file read, so changes to it trigger a recompilation
+    pub path: Option<PathBuf>,
+    /// Directories template paths are relative to, after `DRY_HBS_TEMPLATE_ROOT`
+    pub template_roots: Vec<PathBuf>,
+    /// HTML-escape `{{ }}` output
+    pub escape: bool,
+    /// Report template warnings as errors
+    pub strict: bool,
+    /// Drop whitespace containing a line break between tags
+    pub minify: bool,
+    pub newlines: Option<Newlines>,
+    pub partial_indent: bool,
+    pub segments: bool,
+    /// Report the type chosen for each variable and why
+    pub verbose: bool,
+    /// Document the generated structs with their render code
+    pub pretty: bool,
+    /// Render generic templates through a non-generic function
+    pub compact: bool,
+    /// Add the render-time nonce to `<script>` and `<style>` tags
+    pub csp_nonce: bool,
+    /// Infer `bool` for unmapped condition variables
+    pub infer_bool: bool,
+    /// Report block locals hiding variables as errors
+    pub deny_shadowing: bool,
+    /// Check the template's `id` attributes for duplicates
+    pub check_ids: bool,
+    /// Order of the constructor arguments
+    pub arguments: Arguments,
+    /// Keys of the translation catalog
+    pub translation_keys: Option<HashSet<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            path: None,
+            template_roots: Vec::new(),
+            escape: true,
+            strict: false,
+            minify: false,
+            newlines: None,
+            partial_indent: true,
+            segments: false,
+            verbose: false,
+            pretty: false,
+            compact: false,
+            csp_nonce: false,
+            infer_bool: true,
+            deny_shadowing: false,
+            check_ids: true,
+            arguments: Arguments::Order,
+            translation_keys: None,
+        }
+    }
+}
+
+/// Value of a configuration key
+enum Value {
+    String(String),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+/// Parses a basic TOML string, `literal` is the text starting with its opening quote
+fn parse_string(literal: &str) -> Option<(String, &str)> {
+    let mut text = String::new();
+    let mut chars = literal.strip_prefix('"')?.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '"' => return Some((text, &literal[pos + 2..])),
+            '\\' => match chars.next()?.1 {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                c @ ('"' | '\\') => text.push(c),
+                _ => return None,
+            },
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+/// Strips a trailing comment, failing on anything else left after a value
+fn end_of_value(rest: &str) -> Option<()> {
+    let rest = rest.trim_start();
+    (rest.is_empty() || rest.starts_with('#')).then_some(())
+}
+
+fn parse_value(value: &str) -> Option<Value> {
+    if value.starts_with('"') {
+        let (text, rest) = parse_string(value)?;
+        end_of_value(rest)?;
+        return Some(Value::String(text));
+    }
+    if let Some(mut rest) = value.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                end_of_value(after)?;
+                return Some(Value::Array(items));
+            }
+            let (item, after) = parse_string(rest)?;
+            items.push(item);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+    let (word, rest) = value.split_at(value.find([' ', '\t', '#']).unwrap_or(value.len()));
+    end_of_value(rest)?;
+    match word {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => None,
+    }
+}
+
+/// Reads the keys of a catalog of `key = message` lines, skipping blank and `#` lines
+fn catalog_keys(path: &Path) -> Result<HashSet<String>, String> {
+    let catalog = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read i18n catalog {:?}: {}", path, err))?;
+    Ok(catalog
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, _)| key.trim().to_string())
+        .collect())
+}
+
+impl Config {
+    /// Reads the configuration of the crate being compiled, if it has one
+    pub fn load() -> Result<Config, String> {
+        let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") else {
+            return Ok(Config::default());
+        };
+        let dir = Path::new(&manifest_dir);
+        let path = dir.join(FILE_NAME);
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let source = fs::read_to_string(&path)
+            .map_err(|err| format!("failed to read {:?}: {}", path, err))?;
+        let mut config = Config::parse(&source, dir)
+            .map_err(|(line, message)| format!("{:?} line {}: {}", path, line, message))?;
+        config.path = Some(path);
+        Ok(config)
+    }
+
+    /// Parses a configuration, paths in it are relative to `dir`
+    fn parse(source: &str, dir: &Path) -> Result<Config, (usize, String)> {
+        let mut config = Config::default();
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| (index + 1, message);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected key = value".to_string()))?;
+            let key = key.trim();
+            let value = parse_value(value.trim()).ok_or_else(|| {
+                error(format!(
+                    "unsupported value for `{}`, expected a string, boolean or array of strings",
+                    key
+                ))
+            })?;
+            let mismatch = |expected: &str| error(format!("`{}` must be {}", key, expected));
+            match (key, value) {
+                ("template_roots", Value::Array(roots)) => {
+                    config.template_roots = roots.iter().map(|root| dir.join(root)).collect()
+                }
+                ("template_roots", _) => return Err(mismatch("an array of strings")),
+                ("escape", Value::String(escape)) => {
+                    config.escape = match escape.as_str() {
+                        "html" => true,
+                        "none" => false,
+                        _ => return Err(mismatch("\"html\" or \"none\"")),
+                    }
+                }
+                ("escape", _) => return Err(mismatch("\"html\" or \"none\"")),
+                ("strict", Value::Bool(strict)) => config.strict = strict,
+                ("minify", Value::Bool(minify)) => config.minify = minify,
+                ("partial_indent", Value::Bool(indent)) => config.partial_indent = indent,
+                ("segments", Value::Bool(segments)) => config.segments = segments,
+                ("verbose", Value::Bool(verbose)) => config.verbose = verbose,
+                ("pretty", Value::Bool(pretty)) => config.pretty = pretty,
+                ("compact", Value::Bool(compact)) => config.compact = compact,
+                ("csp_nonce", Value::Bool(csp_nonce)) => config.csp_nonce = csp_nonce,
+                ("infer_bool", Value::Bool(infer_bool)) => config.infer_bool = infer_bool,
+                ("deny_shadowing", Value::Bool(deny)) => config.deny_shadowing = deny,
+                ("check_ids", Value::Bool(check_ids)) => config.check_ids = check_ids,
+                (
+                    "strict" | "minify" | "partial_indent" | "segments" | "verbose" | "pretty"
+                    | "compact" | "csp_nonce" | "infer_bool" | "deny_shadowing" | "check_ids",
+                    _,
+                ) => {
+                    return Err(mismatch("a boolean"));
+                }
+                ("newlines", Value::String(newlines)) => {
+                    config.newlines = Some(Newlines::parse(&newlines).map_err(error)?)
+                }
+                ("newlines", _) => return Err(mismatch("\"lf\" or \"crlf\"")),
+                ("arguments", Value::String(arguments)) => {
+                    config.arguments = Arguments::parse(&arguments).map_err(error)?
+                }
+                ("arguments", _) => return Err(mismatch("\"order\", \"sorted\" or \"named\"")),
+                ("i18n_catalog", Value::String(catalog)) => {
+                    config.translation_keys = Some(catalog_keys(&dir.join(catalog)).map_err(error)?)
+                }
+                ("i18n_catalog", _) => return Err(mismatch("a string")),
+                (key, _) => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,176 @@
+//! Template constants declared with `{{!-- @const brand = "Acme" --}}`
+//!
+//! A constant is a string, number or boolean literal. Anywhere in the template `{{brand}}`
+//! is replaced by the HTML-escaped literal and `{{{brand}}}` by the literal as is, so both
+//! end up in the static output, and a helper argument naming the constant is replaced by
+//! the quoted literal. Constants take precedence over variables of the same name.
+
+use dry_handlebars_parser::expression::{Expression, ExpressionType};
+use std::collections::HashMap;
+
+/// Returns the offset of a slice within the source it was taken from
+fn offset(src: &str, slice: &str) -> usize {
+    slice.as_ptr() as usize - src.as_ptr() as usize
+}
+
+/// Escapes text like the runtime's `as_display_html`
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            '`' => escaped.push_str("&#x60;"),
+            '=' => escaped.push_str("&#x3D;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Parses the literal of a declaration, returning its text
+fn parse_literal(literal: &str) -> Option<String> {
+    if let Some(quoted) = literal.strip_prefix('"') {
+        let quoted = quoted.strip_suffix('"')?;
+        let mut text = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    c @ ('"' | '\\') => text.push(c),
+                    _ => return None,
+                },
+                '"' => return None,
+                c => text.push(c),
+            }
+        }
+        return Some(text);
+    }
+    match literal {
+        "true" | "false" => Some(literal.to_string()),
+        _ if literal.parse::<f64>().is_ok() => Some(literal.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads the constants declared in a template's comments
+fn declarations(src: &str) -> Result<HashMap<String, String>, String> {
+    let mut constants = HashMap::new();
+    let mut expression = Expression::from(src).map_err(|err| err.to_string())?;
+    while let Some(expr) = expression {
+        expression = expr.next().map_err(|err| err.to_string())?;
+        let declaration = match expr.expression_type {
+            ExpressionType::Comment => {
+                let comment = expr.content.trim();
+                let comment = comment.strip_suffix("--").unwrap_or(comment);
+                match comment.trim().strip_prefix("@const") {
+                    Some(declaration) => declaration.trim(),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let expected = || format!("expected @const name = literal in {}", expr.raw);
+        let (name, literal) = declaration.split_once('=').ok_or_else(expected)?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(expected());
+        }
+        let value = parse_literal(literal.trim()).ok_or_else(expected)?;
+        if constants.insert(name.to_string(), value).is_some() {
+            return Err(format!("constant {} is declared twice", name));
+        }
+    }
+    Ok(constants)
+}
+
+/// Quotes text as a helper argument
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Replaces the helper arguments of an expression's content that name a constant
+fn replace_arguments(content: &str, constants: &HashMap<String, String>) -> String {
+    let mut replaced = String::with_capacity(content.len());
+    let mut first = true;
+    let mut rest = content;
+    while !rest.is_empty() {
+        let space = rest.len() - rest.trim_start().len();
+        replaced.push_str(&rest[..space]);
+        rest = &rest[space..];
+        // a quoted argument may contain spaces
+        let mut quoted = false;
+        let len = rest
+            .char_indices()
+            .find(|(_, c)| {
+                quoted ^= *c == '"';
+                c.is_whitespace() && !quoted
+            })
+            .map_or(rest.len(), |(pos, _)| pos);
+        let (token, tail) = rest.split_at(len);
+        let (key, value) = match token.split_once('=') {
+            Some((key, value)) => (Some(key), value),
+            None => (None, token),
+        };
+        match constants.get(value) {
+            Some(text) if !first => {
+                if let Some(key) = key {
+                    replaced.push_str(key);
+                    replaced.push('=');
+                }
+                replaced.push_str(&quote(text));
+            }
+            _ => replaced.push_str(token),
+        }
+        if !token.is_empty() {
+            first = false;
+        }
+        rest = tail;
+    }
+    replaced
+}
+
+/// Replaces the uses of a template's constants by their literals
+pub fn fold(src: &str) -> Result<String, String> {
+    let constants = declarations(src)?;
+    if constants.is_empty() {
+        return Ok(src.to_string());
+    }
+    let mut folded = String::with_capacity(src.len());
+    let mut copied = 0;
+    let mut expression = Expression::from(src).map_err(|err| err.to_string())?;
+    while let Some(expr) = expression {
+        expression = expr.next().map_err(|err| err.to_string())?;
+        let start = offset(src, expr.prefix) + expr.prefix.len();
+        let end = offset(src, expr.postfix);
+        let raw = &src[start..end];
+        let text = match expr.expression_type {
+            ExpressionType::HtmlEscaped | ExpressionType::Raw => constants.get(expr.content.trim()),
+            _ => continue,
+        };
+        let replacement = match (expr.expression_type, text) {
+            // the literal is static text, not the start of an expression
+            (ExpressionType::HtmlEscaped, Some(text)) => escape_html(text).replace("{{", "\\{{"),
+            (_, Some(text)) => text.replace("{{", "\\{{"),
+            (_, None) => {
+                let content = offset(src, expr.content) - start;
+                format!(
+                    "{}{}{}",
+                    &raw[..content],
+                    replace_arguments(expr.content, &constants),
+                    &raw[content + expr.content.len()..]
+                )
+            }
+        };
+        folded.push_str(&src[copied..start]);
+        folded.push_str(&replacement);
+        copied = end;
+    }
+    folded.push_str(&src[copied..]);
+    Ok(folded)
+}
@@ -0,0 +1,83 @@
+//! Checks of a template's variables against the fields of a struct given with
+//! `context = crate::CardData`
+//!
+//! A proc macro can't see the fields of another type, so every variable and path read from
+//! the root of the template becomes a field access on the context type in a function that is
+//! never called. A path that doesn't match fails to compile with rustc's `no field` error,
+//! the receiver being a wrapper type named after the template and the line and column of the
+//! path, e.g. ``no field `titel` on type `&card_line_3_column_12<CardData>` ``.
+
+use crate::lints::line_column;
+use dry_handlebars_parser::expression::{Expression, ExpressionType};
+use quote::{format_ident, quote_spanned};
+
+/// Finds the line and column, from 1, of the first expression reading a path
+fn locate(src: &str, path: &str) -> Option<(usize, usize)> {
+    let mut expression = Expression::from(src).ok().flatten();
+    while let Some(expr) = expression {
+        if let ExpressionType::HtmlEscaped | ExpressionType::Raw | ExpressionType::Open =
+            expr.expression_type
+        {
+            let found = expr
+                .content
+                .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '=')
+                .map(|token| token.replace("?.", "."))
+                .find(|token| {
+                    token == path
+                        || token.ends_with(&format!("/{}", path))
+                        || token.strip_prefix("@root.") == Some(path)
+                });
+            if found.is_some() {
+                return Some(line_column(src, expr.content));
+            }
+        }
+        expression = expr.next().ok().flatten();
+    }
+    None
+}
+
+/// Returns the checks of the given paths, each a `.` separated list of fields
+pub fn checks(
+    name: &str,
+    src: &str,
+    paths: &[String],
+    context: &syn::Type,
+    span: proc_macro2::Span,
+) -> proc_macro2::TokenStream {
+    let checks = paths.iter().map(|path| {
+        let wrapper = match locate(src, path) {
+            Some((line, column)) => format_ident!("{}_line_{}_column_{}", name, line, column),
+            None => format_ident!("{}_partial", name),
+        };
+        let mut fields = path
+            .split('.')
+            .map(|field| format_ident!("{}", field, span = span));
+        let first = fields.next();
+        let mut access = quote_spanned! {span=> at(context).#first };
+        for field in fields {
+            access = quote_spanned! {span=> at(&#access).#field };
+        }
+        quote_spanned! {span=>
+            #[allow(dead_code, non_camel_case_types)]
+            const _: () = {
+                // an enum so that rustc doesn't suggest fields of the wrapper
+                enum #wrapper<T: ?Sized> {
+                    Never(::std::marker::PhantomData<T>),
+                }
+                impl<T: ?Sized> ::std::ops::Deref for #wrapper<T> {
+                    type Target = T;
+                    fn deref(&self) -> &T {
+                        unreachable!()
+                    }
+                }
+                fn at<T: ?Sized>(_: &T) -> &#wrapper<T> {
+                    unreachable!()
+                }
+                fn check(context: &#context) {
+                    let _ = &#access;
+                }
+            };
+        }
+    });
+    quote_spanned! {span=> #(#checks)* }
+}
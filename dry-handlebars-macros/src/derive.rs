@@ -0,0 +1,308 @@
+//! `#[derive(Template)]`: a template compiled against the named fields of an existing struct
+//!
+//! ```ignore
+//! #[derive(dry_handlebars::Template)]
+//! #[template(path = "templates/page.hbs")]
+//! struct Page {
+//!     title: String,
+//!     items: Vec<String>,
+//! }
+//! ```
+//!
+//! The attribute takes `path = "..."` or `source = "..."` followed by the arguments of the
+//! other macros, except type mappings since the types are those of the fields. A variable
+//! that is not a field is a compile error. Derived templates have no `render_segments`, so
+//! `segments = true` is an error, the crate wide `segments` default doesn't apply to them.
+
+use crate::{
+    Prepared, RenderMethods, TemplateArgs, body_prelude, hash_arg_markers, into_response_impl,
+    load_config, prepare_content, register_helpers, render_methods, resolve_template_path,
+    template_errors, template_impl,
+};
+use dry_handlebars_parser::block::add_builtins;
+use dry_handlebars_parser::compiler::{Compiler, Options};
+use quote::quote;
+use std::collections::HashMap;
+use std::fs;
+use syn::{LitStr, Token, parse::ParseStream};
+
+/// Where the template comes from
+enum Source {
+    Path(LitStr),
+    Inline(LitStr),
+}
+
+impl Source {
+    fn lit(&self) -> &LitStr {
+        match self {
+            Source::Path(lit) | Source::Inline(lit) => lit,
+        }
+    }
+}
+
+/// Parses `path = "..."` or `source = "..."` and the arguments following it
+fn parse_attribute(input: ParseStream, args: TemplateArgs) -> syn::Result<(Source, TemplateArgs)> {
+    let key: syn::Ident = input.parse()?;
+    input.parse::<Token![=]>()?;
+    let lit: LitStr = input.parse()?;
+    let source = match key.to_string().as_str() {
+        "path" => Source::Path(lit),
+        "source" => Source::Inline(lit),
+        _ => {
+            return Err(syn::Error::new(
+                key.span(),
+                format!("unknown template `{}`, expected path or source", key),
+            ));
+        }
+    };
+    let mut args = args;
+    if input.peek(Token![,]) {
+        input.parse::<Token![,]>()?;
+        args = args.parse_into(input)?;
+    }
+    Ok((source, args))
+}
+
+pub fn template(input: syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let fields = match &input.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "Template can only be derived for structs with named fields",
+            ));
+        }
+    };
+    let attr = input
+        .attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("template"))
+        .ok_or_else(|| {
+            syn::Error::new_spanned(ident, "expected #[template(path = \"...\")] attribute")
+        })?;
+    let config = load_config()?;
+    let (source, args) = attr.parse_args_with(|input: ParseStream| {
+        parse_attribute(input, TemplateArgs::from(&config))
+    })?;
+    if let Some((name, _)) = args.mappings.first() {
+        return Err(syn::Error::new(
+            source.lit().span(),
+            format!(
+                "unexpected mapping of `{}`, the types are those of the fields",
+                name
+            ),
+        ));
+    }
+    if args.context.is_some() {
+        return Err(syn::Error::new(
+            source.lit().span(),
+            "unexpected context, the variables are checked against the fields",
+        ));
+    }
+    if args.segments && !config.segments {
+        return Err(syn::Error::new(
+            source.lit().span(),
+            "unexpected segments = true, derived templates aren't rendered as segments",
+        ));
+    }
+    let span = source.lit().span();
+
+    // errors of an inline template point into its literal
+    let inline = match &source {
+        Source::Inline(lit) => Some(lit.clone()),
+        Source::Path(_) => None,
+    };
+    let (content, path) = match &source {
+        Source::Inline(lit) => (lit.value(), None),
+        Source::Path(lit) => {
+            let path = resolve_template_path(lit, "File", &config)?;
+            let content = fs::read_to_string(&path).map_err(|err| {
+                syn::Error::new(span, format!("Failed to read template {:?}: {}", path, err))
+            })?;
+            (content, Some(path.to_string_lossy().to_string()))
+        }
+    };
+    let source = content;
+    let Prepared {
+        content,
+        partial_files,
+        warnings,
+    } = prepare_content(&source, path.as_deref(), &args, span)?;
+    let registered = register_helpers(&args);
+
+    let field_types: HashMap<String, String> = fields
+        .iter()
+        .map(|field| {
+            let ty = &field.ty;
+            (
+                field.ident.as_ref().unwrap().to_string(),
+                quote! { #ty }.to_string(),
+            )
+        })
+        .collect();
+    let mut block_map = HashMap::new();
+    add_builtins(&mut block_map);
+    let options = Options::default()
+        .root_var_name(Some(args.root_var_name))
+        .write_var_name(args.write_var_name)
+        .variable_types(field_types.clone())
+        .block_helpers(registered.block_helpers)
+        .helpers(registered.helpers)
+        .safe_helpers(registered.safe_helpers)
+        .escape_html(args.config.escape)
+        .translation_keys(args.config.translation_keys.clone())
+        .debug_in_release(args.debug_in_release);
+    let rust_code = Compiler::new(options, block_map)
+        .compile_all(&content)
+        .map_err(|errors| {
+            template_errors(
+                errors,
+                &source,
+                &content,
+                path.as_deref(),
+                inline.as_ref(),
+                span,
+            )
+        })?;
+
+    let mut missing: Vec<_> = rust_code
+        .top_level_vars()
+        .iter()
+        .map(|var| var.split('.').next().unwrap())
+        .filter(|root| !field_types.contains_key(*root))
+        .collect();
+    missing.sort();
+    missing.dedup();
+    if let Some(errors) = missing
+        .iter()
+        .map(|root| {
+            syn::Error::new(
+                span,
+                format!(
+                    "template references `{}`, which is not a field of `{}`",
+                    root, ident
+                ),
+            )
+        })
+        .reduce(|mut errors, err| {
+            errors.combine(err);
+            errors
+        })
+    {
+        return Err(errors);
+    }
+
+    let render_body: proc_macro2::TokenStream = rust_code
+        .code()
+        .parse()
+        .expect("Failed to parse generated code");
+    let prelude = body_prelude(&args, true);
+    let render_body = quote! { #prelude #render_body };
+    let uses = rust_code.uses("::dry_handlebars").to_string();
+    let uses: proc_macro2::TokenStream = if uses.is_empty() {
+        quote! {}
+    } else {
+        format!("{};", uses)
+            .parse()
+            .expect("Failed to parse generated uses")
+    };
+    let RenderMethods {
+        render_fn,
+        shorthands,
+        ..
+    } = render_methods(&args.vis, &uses, &render_body, &rust_code);
+
+    let helper_markers = &registered.helper_markers;
+    let hash_arg_markers = hash_arg_markers(&rust_code, span)?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let template_impl = template_impl(
+        &ident.to_string(),
+        path.as_deref(),
+        args.text,
+        &content,
+        &rust_code,
+        quote! { impl #impl_generics ::dry_handlebars::Template for #ident #ty_generics #where_clause },
+    );
+    let into_response_impl = into_response_impl(
+        args.axum.as_ref(),
+        quote! { #impl_generics },
+        quote! { #ident #ty_generics },
+        quote! { #where_clause },
+    );
+    let path = path.iter();
+    Ok(quote! {
+        #(const _: &[u8] = include_bytes!(#path);)*
+        #(const _: &[u8] = include_bytes!(#partial_files);)*
+        #(#warnings)*
+
+        // registered helpers must be annotated with #[dry_handlebars::helper]
+        #(const _: () = #helper_markers;)*
+        // hash arguments must name the parameter they are passed as
+        #(const _: () = #hash_arg_markers;)*
+
+        impl #impl_generics #ident #ty_generics #where_clause {
+            #(#shorthands)*
+            #render_fn
+        }
+
+        #template_impl
+        #into_response_impl
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::template;
+
+    fn error(input: syn::DeriveInput) -> String {
+        template(input).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert_eq!(
+            error(syn::parse_quote! {
+                #[template(source = "<h1>{{title}}</h1>{{#each tags}}{{this}}{{/each}}")]
+                struct Article {
+                    title: String,
+                }
+            }),
+            "template references `tags`, which is not a field of `Article`"
+        );
+        assert!(
+            template(syn::parse_quote! {
+                #[template(source = "<h1>{{title}}</h1>")]
+                struct Article {
+                    title: String,
+                }
+            })
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn segments_rejected() {
+        assert_eq!(
+            error(syn::parse_quote! {
+                #[template(source = "<h1>{{title}}</h1>", segments = true)]
+                struct Article {
+                    title: String,
+                }
+            }),
+            "unexpected segments = true, derived templates aren't rendered as segments"
+        );
+        assert!(
+            template(syn::parse_quote! {
+                #[template(source = "<h1>{{title}}</h1>", segments = false)]
+                struct Article {
+                    title: String,
+                }
+            })
+            .is_ok()
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Glob patterns selecting the templates of `directory!`
+//!
+//! `directory!("templates/**/*.hbs", exclude = ["**/drafts/**", "**/_*.hbs"])` walks the
+//! directory before the first component with a wildcard and keeps the files whose path
+//! relative to it matches the rest of the pattern and none of the excluded ones. `*` and `?`
+//! match within a path component, `**` matches any number of components.
+
+use regex::Regex;
+use std::path::Path;
+
+/// Checks if a path component contains a wildcard
+fn is_wildcard(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+/// Splits a pattern into the directory to walk and the pattern of the paths below it, `None`
+/// without wildcards
+pub fn split(pattern: &str) -> Option<(String, String)> {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let first = components
+        .iter()
+        .position(|component| is_wildcard(component))?;
+    Some((components[..first].join("/"), components[first..].join("/")))
+}
+
+/// Compiles a pattern matched against paths relative to the walked directory
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    let mut regex = String::from("^");
+    let mut rest = pattern.trim_start_matches("./");
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/") {
+            regex.push_str("(?:.*/)?");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**") {
+            regex.push_str(".*");
+            rest = after;
+            continue;
+        }
+        match c {
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    regex.push('$');
+    Regex::new(&regex).map_err(|err| format!("invalid pattern {:?}: {}", pattern, err))
+}
+
+/// Checks if a path relative to the walked directory matches a pattern
+pub fn matches(pattern: &Regex, relative: &Path) -> bool {
+    let relative = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    pattern.is_match(&relative)
+}
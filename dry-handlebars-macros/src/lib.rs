@@ -1,14 +1,28 @@
-mod parser;
+// the nightly feature tracks template directories so that new files trigger a recompilation
+#![cfg_attr(feature = "nightly", feature(proc_macro_tracked_path))]
 
-use crate::parser::block::add_builtins;
-use crate::parser::compiler::{Compiler, Options, Usage};
+mod config;
+mod constants;
+mod context;
+mod derive;
+mod glob;
+mod lints;
+mod partials;
+mod preformatted;
+
+use crate::config::{Arguments, Config, Newlines};
+use dry_handlebars_parser::block::add_builtins;
+use dry_handlebars_parser::compiler::{Compiler, Options, Rust, Usage};
+use dry_handlebars_parser::error::{ParseError, line_column};
 use proc_macro::TokenStream;
 use quote::{format_ident, quote};
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
-use syn::{LitStr, Token, parse::Parse, parse::ParseStream, parse_macro_input};
+use std::path::{Path, PathBuf};
+use syn::ext::IdentExt;
+use syn::{LitStr, Token, parse::Parse, parse::ParseStream, parse::Parser, parse_macro_input};
 use walkdir::WalkDir;
 
 fn to_snake_case(s: &str) -> String {
@@ -28,35 +42,1232 @@ fn to_snake_case(s: &str) -> String {
     result
 }
 
+/// Checks if a type names a struct, e.g. `Page` or `crate::Page` but not `Vec<Page>` or `u32`
+fn names_struct(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(path) => path.path.segments.last().is_some_and(|segment| {
+            segment.arguments.is_empty()
+                && segment
+                    .ident
+                    .to_string()
+                    .starts_with(|c: char| c.is_ascii_uppercase())
+        }),
+        _ => false,
+    }
+}
+
+/// Checks if a type is a primitive that is copied rather than borrowed, so conditions and
+/// casts read it as a value
+fn is_scalar(ty: &syn::Type) -> bool {
+    const SCALARS: [&str; 15] = [
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "usize",
+    ];
+    match ty {
+        syn::Type::Path(path) => path
+            .path
+            .get_ident()
+            .is_some_and(|ident| SCALARS.contains(&ident.to_string().as_str())),
+        _ => false,
+    }
+}
+
+/// Checks if a type is a primitive or a string, which has neither fields nor items
+fn is_plain_value(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Reference(reference) => is_plain_value(&reference.elem),
+        syn::Type::Path(path)
+            if path.path.segments.last().is_some_and(|segment| {
+                segment.arguments.is_empty()
+                    && matches!(segment.ident.to_string().as_str(), "String" | "str")
+            }) =>
+        {
+            true
+        }
+        _ => is_scalar(ty),
+    }
+}
+
+/// Checks the mappings of the variables a template reads against how it reads them, a
+/// primitive or string iterated by `each` or read properties of is an error at its mapping
+fn mismatched_mappings(
+    args: &TemplateArgs,
+    report: &[dry_handlebars_parser::compiler::VariableReport],
+) -> syn::Result<()> {
+    use dry_handlebars_parser::compiler::UsageKind;
+    use syn::spanned::Spanned;
+    let mut errors = Vec::new();
+    for (name, ty) in &args.mappings {
+        if !is_plain_value(ty) {
+            continue;
+        }
+        let property = format!("{}.", name);
+        let message = match report.iter().find(|variable| {
+            variable.path == *name && variable.kinds.contains(&UsageKind::Iterable)
+                || variable.path.starts_with(&property)
+        }) {
+            Some(variable) if variable.path == *name => format!(
+                "`{}` is mapped to `{}` but iterated by each, map it to a collection, e.g. (\"{}\", Vec<{}>)",
+                name,
+                quote! { #ty },
+                name,
+                quote! { #ty }
+            ),
+            Some(variable) => format!(
+                "`{}` is mapped to `{}` but the template reads `{}`, map it to a struct with that field",
+                name,
+                quote! { #ty },
+                variable.path
+            ),
+            None => continue,
+        };
+        errors.push(syn::Error::new(
+            ty.span(),
+            format!("dry-handlebars: {}", message),
+        ));
+    }
+    match errors.into_iter().reduce(|mut errors, err| {
+        errors.combine(err);
+        errors
+    }) {
+        Some(errors) => Err(errors),
+        None => Ok(()),
+    }
+}
+
+/// Reports the mappings no template reads as warnings at their type, errors when `strict`
+///
+/// `directory!` shares its mappings between its templates, so it reports those none of them
+/// reads.
+fn unused_mappings(
+    args: &TemplateArgs,
+    unused: &HashSet<String>,
+    reader: &str,
+) -> syn::Result<proc_macro2::TokenStream> {
+    use syn::spanned::Spanned;
+    let messages = args
+        .mappings
+        .iter()
+        .filter(|(name, _)| unused.contains(name))
+        .map(|(name, ty)| {
+            (
+                format!(
+                    "`{}` is mapped but never read by {}, remove the mapping",
+                    name, reader
+                ),
+                ty.span(),
+            )
+        });
+    match args.config.strict {
+        true => match messages
+            .map(|(message, span)| syn::Error::new(span, format!("dry-handlebars: {}", message)))
+            .reduce(|mut errors, err| {
+                errors.combine(err);
+                errors
+            }) {
+            Some(errors) => Err(errors),
+            None => Ok(quote! {}),
+        },
+        false => Ok(messages
+            .map(|(message, span)| lints::warning(&message, span))
+            .collect()),
+    }
+}
+
+/// Borrowed counterpart of an owned string or list type, `String` becoming `&'a str`, `Vec<T>`
+/// becoming `&'a [T]` and an `Option` of either an `Option` of the borrowed type
+fn borrowed(ty: &syn::Type) -> Option<syn::Type> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    let argument = match &segment.arguments {
+        syn::PathArguments::AngleBracketed(arguments) => match arguments.args.first() {
+            Some(syn::GenericArgument::Type(argument)) if arguments.args.len() == 1 => {
+                Some(argument)
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    match (segment.ident.to_string().as_str(), argument) {
+        ("String", None) => Some(syn::parse_quote! { &'a str }),
+        ("Vec", Some(item)) => Some(syn::parse_quote! { &'a [#item] }),
+        ("Option", Some(inner)) => {
+            borrowed(inner).map(|inner| syn::parse_quote! { Option<#inner> })
+        }
+        _ => None,
+    }
+}
+
+/// Whether tokens name a lifetime, such as `'a` in `pub title: &'a str`
+fn names_lifetime(tokens: &proc_macro2::TokenStream, name: &str) -> bool {
+    let mut after_quote = false;
+    tokens.clone().into_iter().any(|token| {
+        let found = match &token {
+            proc_macro2::TokenTree::Ident(ident) => after_quote && ident == name,
+            proc_macro2::TokenTree::Group(group) => names_lifetime(&group.stream(), name),
+            _ => false,
+        };
+        after_quote =
+            matches!(&token, proc_macro2::TokenTree::Punct(punct) if punct.as_char() == '\'');
+        found
+    })
+}
+
+/// Replaces the struct's lifetime `'a` in a type with `'static`, for bounds outside the impls
+/// declaring it
+struct StaticLifetime;
+
+impl syn::visit_mut::VisitMut for StaticLifetime {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut syn::Lifetime) {
+        if lifetime.ident == "a" {
+            *lifetime = syn::Lifetime::new("'static", lifetime.span());
+        }
+    }
+}
+
+/// Name of the render function taking the given render-time values, e.g. `render_with_nonce`
+/// or `render_into_with_nonce`
+fn render_fn_name<'a>(prefix: &str, values: impl Iterator<Item = &'a str>) -> syn::Ident {
+    let values = values.collect::<Vec<_>>();
+    match values.is_empty() {
+        true => format_ident!("{}", prefix),
+        false => format_ident!("{}_with_{}", prefix, values.join("_and_")),
+    }
+}
+
+/// The functions rendering a template to a `String` or into a writer: a pair taking every
+/// render-time value the body reads, such as the translator, and shorthands giving some of
+/// them their defaults
+struct RenderMethods {
+    full_name: syn::Ident,
+    try_name: syn::Ident,
+    into_name: syn::Ident,
+    full_params: Vec<proc_macro2::TokenStream>,
+    full_args: Vec<syn::Ident>,
+    render_fn: proc_macro2::TokenStream,
+    shorthands: Vec<proc_macro2::TokenStream>,
+}
+
+/// Binds the root and writer names given with `root_var_name` and `write_var_name` to `self`
+/// and the writer `f` of the generated functions, the root only when the body reads it
+fn body_prelude(args: &TemplateArgs, root: bool) -> proc_macro2::TokenStream {
+    let root = match args.root_var_name {
+        name if root && name != "self" => {
+            let name = format_ident!("{}", name);
+            quote! { #[allow(unused_variables)] let #name = self; }
+        }
+        _ => quote! {},
+    };
+    let writer = match args.write_var_name {
+        "f" => quote! {},
+        name => {
+            let name = format_ident!("{}", name);
+            quote! { #[allow(unused_variables)] let #name = &mut f; }
+        }
+    };
+    quote! { #root #writer }
+}
+
+/// Bytes a written value is expected to take in the output
+const VALUE_SIZE_HINT: usize = 16;
+
+/// Expected length of a template's output: its static text and a few bytes per value
+fn size_hint(rust_code: &Rust) -> usize {
+    rust_code.static_len() + rust_code.value_writes() * VALUE_SIZE_HINT
+}
+
+fn render_methods(
+    vis: &syn::Visibility,
+    uses: &proc_macro2::TokenStream,
+    render_body: &proc_macro2::TokenStream,
+    rust_code: &Rust,
+) -> RenderMethods {
+    let translated = rust_code.using().contains("Translated");
+    let nonced = rust_code.nonce();
+    let cached = rust_code.cached();
+    let holes = rust_code.using().contains("HoleWriter");
+    let size_hint = size_hint(rust_code);
+    // render-time values the body reads, with the value they take when not given
+    let mut render_params = Vec::new();
+    if translated {
+        render_params.push((
+            "translator",
+            quote! { translator: &dyn ::dry_handlebars::Translator },
+            quote! { &::dry_handlebars::NoTranslation },
+            "keys of the `t` helper written untranslated",
+        ));
+    }
+    if nonced {
+        render_params.push((
+            "nonce",
+            quote! { nonce: &str },
+            quote! { "" },
+            "an empty nonce",
+        ));
+    }
+    if cached {
+        render_params.push((
+            "cache",
+            quote! { cache: &dyn ::dry_handlebars::FragmentCache },
+            quote! { &::dry_handlebars::NoCache },
+            "every item of a cached each rendered",
+        ));
+    }
+    let full_name = render_fn_name("render", render_params.iter().map(|(name, ..)| *name));
+    let try_name = render_fn_name("try_render", render_params.iter().map(|(name, ..)| *name));
+    let into_name = render_fn_name("render_into", render_params.iter().map(|(name, ..)| *name));
+    let full_params = render_params
+        .iter()
+        .map(|(_, param, ..)| param.clone())
+        .collect::<Vec<_>>();
+    let full_args = render_params
+        .iter()
+        .map(|(name, ..)| format_ident!("{}", name))
+        .collect::<Vec<_>>();
+    // cached items and holes are read back from the writer, so such templates render into a
+    // String which is then written
+    let try_fn = match cached || holes {
+        true => quote! {
+            /// Renders the template, failing when a value's `Display` implementation does
+            #vis fn #try_name(&self, #(#full_params),*) -> Result<String, std::fmt::Error> {
+                use std::fmt::Write;
+                #uses
+                let mut f = String::with_capacity(self.size_hint());
+                #render_body
+                Ok(f)
+            }
+
+            /// Writes the rendered template into a writer
+            #vis fn #into_name(
+                &self,
+                f: &mut (impl std::fmt::Write + ?Sized),
+                #(#full_params),*
+            ) -> std::fmt::Result {
+                f.write_str(&self.#try_name(#(#full_args),*)?)
+            }
+        },
+        false => quote! {
+            /// Renders the template, failing when a value's `Display` implementation does
+            #vis fn #try_name(&self, #(#full_params),*) -> Result<String, std::fmt::Error> {
+                let mut f = String::with_capacity(self.size_hint());
+                self.#into_name(&mut f, #(#full_args),*)?;
+                Ok(f)
+            }
+
+            /// Writes the rendered template into a writer, such as a reused buffer, without
+            /// allocating a String for it
+            #vis fn #into_name(
+                &self,
+                f: &mut (impl std::fmt::Write + ?Sized),
+                #(#full_params),*
+            ) -> std::fmt::Result {
+                use std::fmt::Write;
+                #uses
+                // block helpers and a renamed writer borrow it mutably
+                #[allow(unused_mut)]
+                let mut f = f;
+                #render_body
+                Ok(())
+            }
+        },
+    };
+    let render_doc = format!(
+        " Renders the template, panicking when a value's `Display` implementation fails, see [`{0}`](Self::{0})",
+        try_name
+    );
+    let render_fn = quote! {
+        /// Expected length of the rendered output, the capacity `render` allocates: the
+        /// length of the template's static text and a few bytes per value written
+        #vis fn size_hint(&self) -> usize {
+            #size_hint
+        }
+
+        #[doc = #render_doc]
+        #vis fn #full_name(&self, #(#full_params),*) -> String {
+            self.#try_name(#(#full_args),*)
+                .expect("a Display implementation returned an error")
+        }
+
+        #try_fn
+    };
+    // one function per combination of the values given, the others take their defaults
+    let shorthands = (0..(1usize << render_params.len()) - 1)
+        .map(|given| {
+            let is_given = |index: usize| given & (1 << index) != 0;
+            let given_names = || {
+                render_params
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| is_given(*index))
+                    .map(|(_, (name, ..))| *name)
+            };
+            let name = render_fn_name("render", given_names());
+            let shorthand_try_name = render_fn_name("try_render", given_names());
+            let shorthand_into_name = render_fn_name("render_into", given_names());
+            let params = render_params
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| is_given(*index))
+                .map(|(_, (_, param, ..))| param)
+                .collect::<Vec<_>>();
+            let args = render_params
+                .iter()
+                .enumerate()
+                .map(|(index, (name, _, default, _))| match is_given(index) {
+                    true => {
+                        let name = format_ident!("{}", name);
+                        quote! { #name }
+                    }
+                    false => default.clone(),
+                })
+                .collect::<Vec<_>>();
+            let doc = format!(
+                " Renders with {}",
+                render_params
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| !is_given(*index))
+                    .map(|(_, (.., doc))| *doc)
+                    .collect::<Vec<_>>()
+                    .join(" and ")
+            );
+            quote! {
+                #[doc = #doc]
+                #vis fn #name(&self, #(#params),*) -> String {
+                    self.#full_name(#(#args),*)
+                }
+
+                #[doc = #doc]
+                #vis fn #shorthand_try_name(
+                    &self,
+                    #(#params),*
+                ) -> Result<String, std::fmt::Error> {
+                    self.#try_name(#(#args),*)
+                }
+
+                #[doc = #doc]
+                #vis fn #shorthand_into_name(
+                    &self,
+                    f: &mut (impl std::fmt::Write + ?Sized),
+                    #(#params),*
+                ) -> std::fmt::Result {
+                    self.#into_name(f, #(#args),*)
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    // streaming to an io::Write goes through the writer functions, for every combination
+    let io_fns = (0..1usize << render_params.len()).map(|given| {
+        let given = render_params
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| given & (1 << index) != 0)
+            .map(|(_, param)| param)
+            .collect::<Vec<_>>();
+        let name = render_fn_name("render_to_io", given.iter().map(|(name, ..)| *name));
+        let into_name = render_fn_name("render_into", given.iter().map(|(name, ..)| *name));
+        let params = given.iter().map(|(_, param, ..)| param);
+        let args = given.iter().map(|(name, ..)| format_ident!("{}", name));
+        quote! {
+            /// Streams the rendered template as UTF-8 into an I/O writer, such as a file or a
+            /// socket, without building the whole output first, each piece of output is one
+            /// `write_all` so unbuffered writers are best wrapped in a `BufWriter`
+            #vis fn #name(
+                &self,
+                out: &mut (impl std::io::Write + ?Sized),
+                #(#params),*
+            ) -> std::io::Result<()> {
+                let mut writer = ::dry_handlebars::IoWriter::new(out);
+                let result = self.#into_name(&mut writer, #(#args),*);
+                writer.finish(result)
+            }
+        }
+    });
+    let shorthands = shorthands.into_iter().chain(io_fns).collect();
+    RenderMethods {
+        full_name,
+        try_name,
+        into_name,
+        full_params,
+        full_args,
+        render_fn,
+        shorthands,
+    }
+}
+
+/// A field of a template set through its builder
+struct BuilderField {
+    name: syn::Ident,
+    ty: proc_macro2::TokenStream,
+    /// For generic fields, the type parameters the setter takes as its own and their bounds,
+    /// such as `T0` and `T0: std::fmt::Display`
+    generic: Option<(proc_macro2::TokenStream, proc_macro2::TokenStream)>,
+    /// For mapped fields with a default, its value, the field starting out set
+    default: Option<syn::Expr>,
+}
+
+/// Generates `builder()` and a builder setting the fields of a template by name, each field
+/// once, `build()` only being callable once all of them are set
+fn builder(
+    vis: &syn::Visibility,
+    struct_name: &str,
+    fields: &[BuilderField],
+    lifetime: Option<&proc_macro2::TokenStream>,
+) -> proc_macro2::TokenStream {
+    let builder_name = format_ident!("{}_builder", struct_name);
+    let struct_name = format_ident!("{}", struct_name);
+    // the builder's type parameters are `()` until their field is set
+    let states = (0..fields.len())
+        .map(|index| format_ident!("F{}", index))
+        .collect::<Vec<_>>();
+    let names = fields.iter().map(|field| &field.name).collect::<Vec<_>>();
+    let types = fields.iter().map(|field| &field.ty).collect::<Vec<_>>();
+    let type_params = fields
+        .iter()
+        .filter(|field| field.generic.is_some())
+        .map(|field| &field.ty)
+        .collect::<Vec<_>>();
+    let (params, bounds): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .filter_map(|field| field.generic.clone())
+        .unzip();
+    let unit_params = type_params.iter().map(|_| quote! { () });
+    // the struct's lifetime comes first, the setters declare it for borrowed fields
+    let lifetime_param = lifetime
+        .iter()
+        .map(|lifetime| quote! { #lifetime, })
+        .collect::<Vec<_>>();
+    let initial = fields.iter().map(|field| match field.default {
+        Some(_) => field.ty.clone(),
+        None => quote! { () },
+    });
+    let initial_values = fields.iter().map(|field| match &field.default {
+        Some(default) => quote! { #default },
+        None => quote! { () },
+    });
+    let setters = fields.iter().enumerate().map(
+        |(
+            index,
+            BuilderField {
+                name,
+                ty,
+                generic,
+                default,
+            },
+        )| {
+            // a defaulted field can be set again, replacing its default
+            let state = match default {
+                Some(_) => ty.clone(),
+                None => quote! { () },
+            };
+            let others = states
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, state)| state);
+            let lifetime = lifetime.iter();
+            let before = &states[..index];
+            let after = &states[index + 1..];
+            let (param, bound) = generic.clone().unzip();
+            let param = param.iter();
+            let bound = bound.iter();
+            let inits = names
+                .iter()
+                .enumerate()
+                .map(|(other, other_name)| match other == index {
+                    true => quote! { #other_name },
+                    false => quote! { #other_name: self.#other_name },
+                });
+            quote! {
+                impl<#(#lifetime,)* #(#others),*> #builder_name<#(#before,)* #state #(, #after)*> {
+                    #vis fn #name<#(#param)*>(
+                        self,
+                        #name: #ty,
+                    ) -> #builder_name<#(#before,)* #ty #(, #after)*>
+                    where
+                        #(#bound)*
+                    {
+                        #builder_name {
+                            #(#inits),*
+                        }
+                    }
+                }
+            }
+        },
+    );
+    quote! {
+        /// Builder of the template's fields, see `builder()`
+        #[allow(non_camel_case_types)]
+        #vis struct #builder_name<#(#states),*> {
+            #(#names: #states),*
+        }
+
+        impl<#lifetime> #struct_name<#(#lifetime_param)* #(#unit_params),*> {
+            /// Starts building the template with its fields set by name, `build()` is
+            /// callable once every field without a default is set
+            #vis fn builder() -> #builder_name<#(#initial),*> {
+                #builder_name {
+                    #(#names: #initial_values),*
+                }
+            }
+        }
+
+        #(#setters)*
+
+        impl<#(#lifetime_param)* #(#params),*> #builder_name<#(#types),*>
+        where
+            #(#bounds),*
+        {
+            #vis fn build(self) -> #struct_name<#(#lifetime_param)* #(#type_params),*> {
+                #struct_name {
+                    #(#names: self.#names),*
+                }
+            }
+        }
+    }
+}
+
+/// Helpers given in the macro arguments, as the compiler takes them
+struct Registered {
+    block_helpers: HashMap<String, String>,
+    helpers: HashMap<String, String>,
+    safe_helpers: HashSet<String>,
+    /// Markers checking that helpers registered without a name are annotated with `#[helper]`
+    helper_markers: Vec<syn::Path>,
+}
+
+fn register_helpers(args: &TemplateArgs) -> Registered {
+    let block_helpers: HashMap<String, String> = args
+        .block_helpers
+        .iter()
+        .map(|(name, path)| (name.clone(), quote! { #path }.to_string()))
+        .collect();
+    let mut helpers = HashMap::new();
+    let mut safe_helpers = HashSet::new();
+    let mut helper_markers = Vec::new();
+    for HelperArg { name, path, safe } in &args.helpers {
+        let marked = name.is_none();
+        let name = name
+            .clone()
+            .unwrap_or_else(|| path.segments.last().unwrap().ident.to_string());
+        helpers.insert(name.clone(), quote! { #path }.to_string());
+        if *safe {
+            safe_helpers.insert(name.clone());
+        }
+        if !marked {
+            continue;
+        }
+        let mut marker = path.clone();
+        marker.segments.last_mut().unwrap().ident = helper_marker(&name);
+        helper_markers.push(marker);
+    }
+    Registered {
+        block_helpers,
+        helpers,
+        safe_helpers,
+        helper_markers,
+    }
+}
+
+/// A template's source once its partials, constants, line endings, minification and nonces
+/// are applied, with the warnings of its lints
+struct Prepared {
+    content: String,
+    /// Files the template was built from besides its own, so changes to them trigger a
+    /// recompilation
+    partial_files: Vec<String>,
+    warnings: Vec<proc_macro2::TokenStream>,
+}
+
+/// FNV-1a hash of a template's compiled source, the same on every build and compiler
+fn content_hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Implements `dry_handlebars::Template`, `header` being the `impl .. for ..` line
+fn template_impl(
+    name: &str,
+    path: Option<&str>,
+    text: bool,
+    content: &str,
+    rust_code: &Rust,
+    header: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let source_path = match path {
+        Some(path) => quote! { Some(#path) },
+        None => quote! { None },
+    };
+    let mime_type = match text {
+        true => "text/plain; charset=utf-8",
+        false => "text/html; charset=utf-8",
+    };
+    let content_hash = content_hash(content);
+    let static_len = rust_code.static_len();
+    quote! {
+        #header {
+            const NAME: &'static str = #name;
+            const SOURCE_PATH: Option<&'static str> = #source_path;
+            const MIME_TYPE: &'static str = #mime_type;
+            const CONTENT_HASH: u64 = #content_hash;
+            const COMPILED_STATIC_LEN: usize = #static_len;
+
+            fn render_into(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+                self.render_into(f)
+            }
+        }
+    }
+}
+
+/// `IntoResponse` of the axum crate at `axum` for a template given `axum = true` (or the path
+/// of the crate): the rendered template with its `MIME_TYPE` as `Content-Type`, or a 500 when a
+/// value's `Display` implementation fails
+///
+/// `generics`, `ty` and `where_clause` are those of the template's `Template` impl.
+fn into_response_impl(
+    axum: Option<&syn::Path>,
+    generics: proc_macro2::TokenStream,
+    ty: proc_macro2::TokenStream,
+    where_clause: proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let Some(axum) = axum else {
+        return quote! {};
+    };
+    quote! {
+        impl #generics #axum::response::IntoResponse for #ty #where_clause {
+            fn into_response(self) -> #axum::response::Response {
+                let mut body =
+                    String::with_capacity(<Self as ::dry_handlebars::Template>::COMPILED_STATIC_LEN);
+                match ::dry_handlebars::Template::render_into(&self, &mut body) {
+                    Ok(()) => #axum::response::IntoResponse::into_response((
+                        [(
+                            #axum::http::header::CONTENT_TYPE,
+                            <Self as ::dry_handlebars::Template>::MIME_TYPE,
+                        )],
+                        body,
+                    )),
+                    Err(_) => #axum::response::IntoResponse::into_response(
+                        #axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Turns the errors compiling a template into compile errors, see [`template_error`]
+fn template_errors(
+    errors: Vec<ParseError>,
+    source: &str,
+    prepared: &str,
+    path: Option<&str>,
+    literal: Option<&LitStr>,
+    span: proc_macro2::Span,
+) -> syn::Error {
+    errors
+        .into_iter()
+        .map(|err| template_error(err, source, prepared, path, literal, span))
+        .reduce(|mut errors, err| {
+            errors.combine(err);
+            errors
+        })
+        .unwrap_or_else(|| syn::Error::new(span, "failed to compile template"))
+}
+
+/// Turns an error compiling a template into one naming its file, line and column, looked
+/// up in the source as written when preparing it expanded partials or rewrote it
+///
+/// The error of a template given as a string `literal` points at the expression within it
+/// where the compiler can locate part of a literal (nightly), at the literal with the line
+/// and column otherwise.
+fn template_error(
+    err: ParseError,
+    source: &str,
+    prepared: &str,
+    path: Option<&str>,
+    literal: Option<&LitStr>,
+    span: proc_macro2::Span,
+) -> syn::Error {
+    let bom = source.len();
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+    let bom = bom - source.len();
+    let offset = err.offset().and_then(|offset| {
+        if source == prepared {
+            return Some(offset);
+        }
+        // the expression is found again by its text, counting the same ones before it
+        let rest = &prepared[offset..];
+        let expression = &rest[..rest.find("}}").map_or(rest.len(), |end| end + 2)];
+        let nth = prepared[..offset].matches(expression).count();
+        source
+            .match_indices(expression)
+            .nth(nth)
+            .map(|(found, _)| found)
+    });
+    let subspan = literal.zip(offset).and_then(|(literal, offset)| {
+        let rest = &source[offset..];
+        let end = offset + rest.find("}}").map_or(rest.len(), |end| end + 2);
+        let token = literal.token();
+        let text = token.to_string();
+        token.subspan(literal_offset(&text, bom + offset)?..literal_offset(&text, bom + end)?)
+    });
+    if let Some(subspan) = subspan {
+        return syn::Error::new(subspan, err.to_string());
+    }
+    let message = match (offset.map(|offset| line_column(source, offset)), path) {
+        (Some((line, column)), Some(path)) => format!("{}:{}:{}: {}", path, line, column, err),
+        (Some((line, column)), None) => {
+            format!("line {}, column {} of the template: {}", line, column, err)
+        }
+        (None, Some(path)) => format!("{}: {}", path, err),
+        (None, None) => err.to_string(),
+    };
+    syn::Error::new(span, message)
+}
+
+/// Returns the offset in the text of a string literal token, such as `"a\"b"` or `r#"a"b"#`,
+/// of the given offset in its value, `None` past its end
+fn literal_offset(text: &str, offset: usize) -> Option<usize> {
+    let quote = text.find('"')?;
+    if text.starts_with('r') {
+        return (offset < text.len() - 2 * quote).then_some(quote + 1 + offset);
+    }
+    let mut value_len = 0;
+    let mut chars = text.char_indices().skip(quote + 1).peekable();
+    while let Some((at, c)) = chars.next() {
+        if c == '\\' && matches!(chars.peek(), Some((_, '\n' | '\r'))) {
+            // a line continuation, the line break and the indent after it are skipped
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            continue;
+        }
+        if value_len >= offset {
+            return (value_len == offset).then_some(at);
+        }
+        value_len += match (c, chars.peek().map(|&(_, next)| next)) {
+            ('"', _) => return None,
+            ('\\', Some('u')) => {
+                let digits: String = chars
+                    .by_ref()
+                    .take_while(|&(_, c)| c != '}')
+                    .filter_map(|(_, c)| c.is_ascii_hexdigit().then_some(c))
+                    .collect();
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .map_or(1, char::len_utf8)
+            }
+            ('\\', Some('x')) => {
+                chars.nth(2);
+                1
+            }
+            ('\\', Some(_)) => {
+                chars.next();
+                1
+            }
+            (c, _) => c.len_utf8(),
+        };
+    }
+    None
+}
+
+/// Applies the source transformations shared by every macro, in order
+fn prepare_content(
+    content: &str,
+    path_for_include: Option<&str>,
+    args: &TemplateArgs,
+    span: proc_macro2::Span,
+) -> syn::Result<Prepared> {
+    // a byte order mark left by an editor would otherwise end up in the output
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    // checked before partials are expanded so that positions are those of the template
+    let shadowing = lints::deny_shadowing(lints::shadowing(content), args.deny_shadowing, span)?;
+    let partials::Expanded {
+        content,
+        files: partial_files,
+    } = partials::expand(
+        content,
+        path_for_include.map(Path::new),
+        args.partial_indent,
+        &args.config.template_roots,
+        &args.partials,
+    )
+    .map_err(|message| syn::Error::new(span, message))?;
+    let partial_files = partial_files
+        .iter()
+        .chain(&args.config.path)
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    let content = constants::fold(&content).map_err(|message| syn::Error::new(span, message))?;
+    let content = match args.newlines {
+        Some(newlines) => newlines.normalize(&content),
+        None => content,
+    };
+    let mut content = match args.config.minify {
+        true => {
+            let between_tags = Regex::new(r">\s*\n\s*<").unwrap();
+            preformatted::map_outside(&content, |text| {
+                between_tags.replace_all(text, "><").to_string()
+            })
+        }
+        false => content,
+    };
+    // tags given a nonce in the template keep it
+    if args.csp_nonce {
+        content = Regex::new(r"(?i)<(script|style)\b([^>]*)>")
+            .unwrap()
+            .replace_all(&content, |caps: &regex::Captures| match &caps[2] {
+                attrs if attrs.to_ascii_lowercase().contains("nonce") => caps[0].to_string(),
+                attrs => format!("<{}{} nonce=\"{{{{nonce}}}}\">", &caps[1], attrs),
+            })
+            .to_string();
+    }
+    let mut lint_messages = shadowing;
+    if args.check_ids {
+        lint_messages.extend(lints::duplicate_ids(&content));
+    }
+    if args.config.strict
+        && let Some(errors) = lint_messages
+            .iter()
+            .map(|message| syn::Error::new(span, format!("dry-handlebars: {}", message)))
+            .reduce(|mut errors, err| {
+                errors.combine(err);
+                errors
+            })
+    {
+        return Err(errors);
+    }
+    let warnings = lint_messages
+        .iter()
+        .map(|message| lints::warning(message, span))
+        .collect::<Vec<_>>();
+    Ok(Prepared {
+        content,
+        partial_files,
+        warnings,
+    })
+}
+
+/// Reads `{{!-- @variant "b" of "pricing" --}}`, making a template of a directory the
+/// variant `b` of the experiment `pricing`
+fn experiment_variant(source: &str) -> Option<(String, String)> {
+    let re = Regex::new(r#"\{\{!(?:--)?\s*@variant\s+"([^"]+)"\s+of\s+"([^"]+)"\s*(?:--)?\}\}"#)
+        .unwrap();
+    re.captures(source)
+        .map(|cap| (cap[1].to_string(), cap[2].to_string()))
+}
+
+/// Reads a leading `{{!-- @doc Renders the page header --}}` comment into the lines of the
+/// generated struct's and function's documentation, dedented
+fn template_doc(source: &str) -> Option<Vec<String>> {
+    // a `{{!-- --}}` comment ends at `--}}` and may contain `}}`, a `{{! }}` one at `}}`
+    let re = Regex::new(r"^\s*\{\{!(?:--\s*@doc\b((?s).*?)--\}\}|\s*@doc\b((?s).*?)\}\})").unwrap();
+    let captures = re.captures(source)?;
+    let doc = captures.get(1).or_else(|| captures.get(2))?.as_str();
+    let mut lines: Vec<&str> = doc.lines().collect();
+    let first = lines.first()?.trim();
+    // text following `@doc` starts the first line, the others keep their relative indent
+    let indent = lines[1..]
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let mut doc = vec![first.to_string()];
+    doc.extend(
+        lines
+            .drain(1..)
+            .map(|line| line.get(indent..).unwrap_or("").trim_end().to_string()),
+    );
+    while doc.last().is_some_and(String::is_empty) {
+        doc.pop();
+    }
+    if doc.first().is_some_and(String::is_empty) {
+        doc.remove(0);
+    }
+    Some(doc)
+}
+
+/// A template of a directory declared a variant of an experiment
+struct ExperimentVariant {
+    variant: String,
+    /// Module of the template below the directory
+    module: Vec<String>,
+    struct_name: syn::Ident,
+    context: Option<(String, syn::Type)>,
+}
+
+/// Generates the module of an experiment, with `Variant` and `render_variant` dispatching
+/// to its templates rendered from their shared context struct
+fn experiment_module(
+    experiment: &str,
+    variants: &[ExperimentVariant],
+    vis: &syn::Visibility,
+    span: proc_macro2::Span,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let experiment_ident = syn::parse_str::<syn::Ident>(experiment).map_err(|_| {
+        syn::Error::new(
+            span,
+            format!("experiment \"{}\" is not a valid module name", experiment),
+        )
+    })?;
+    let first = &variants[0];
+    let Some((root, ty)) = &first.context else {
+        return Err(syn::Error::new(
+            span,
+            format!(
+                "`{}` is a variant of \"{}\" but doesn't read everything from one mapped \
+                 struct, map its root, e.g. (\"page\", crate::Page)",
+                first.struct_name, experiment
+            ),
+        ));
+    };
+    let mut names = Vec::new();
+    let mut idents = Vec::new();
+    let mut render_calls = Vec::new();
+    for variant in variants {
+        let same_context = variant
+            .context
+            .as_ref()
+            .is_some_and(|(other_root, other_ty)| {
+                other_root == root && quote! { #other_ty }.to_string() == quote! { #ty }.to_string()
+            });
+        if !same_context {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "`{}` is a variant of \"{}\" but doesn't read everything from `{}: {}` \
+                     like `{}`",
+                    variant.struct_name,
+                    experiment,
+                    root,
+                    quote! { #ty },
+                    first.struct_name
+                ),
+            ));
+        }
+        if variant.module != first.module {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "the variants of \"{}\" must be in the same directory, `{}` and `{}` aren't",
+                    experiment, first.struct_name, variant.struct_name
+                ),
+            ));
+        }
+        let ident = variant
+            .variant
+            .split(|c: char| !c.is_alphanumeric())
+            .map(|part| {
+                let mut chars = part.chars();
+                chars.next().map_or(String::new(), |first| {
+                    first.to_uppercase().chain(chars).collect()
+                })
+            })
+            .collect::<String>();
+        let ident = syn::parse_str::<syn::Ident>(&ident).map_err(|_| {
+            syn::Error::new(
+                span,
+                format!(
+                    "variant \"{}\" of \"{}\" is not a valid name",
+                    variant.variant, experiment
+                ),
+            )
+        })?;
+        if idents.contains(&ident) {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "\"{}\" has more than one variant \"{}\"",
+                    experiment, variant.variant
+                ),
+            ));
+        }
+        let struct_name = &variant.struct_name;
+        let render_name = format_ident!("render_{}", root);
+        render_calls.push(quote! { super::#struct_name::#render_name(context) });
+        names.push(variant.variant.clone());
+        idents.push(ident);
+    }
+    let count = idents.len();
+    Ok(quote! {
+        /// Templates of an experiment, compiled together and rendered from the same context
+        #vis mod #experiment_ident {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// A variant of the experiment, chosen by the caller
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            #vis enum Variant {
+                #(#idents),*
+            }
+
+            impl Variant {
+                /// Every variant, by name
+                #vis const ALL: [Variant; #count] = [#(Variant::#idents),*];
+
+                /// The name the variant is declared with
+                #vis fn name(self) -> &'static str {
+                    match self {
+                        #(Variant::#idents => #names),*
+                    }
+                }
+
+                /// The variant declared with a name, such as one stored in a cookie
+                #vis fn from_name(name: &str) -> Option<Self> {
+                    match name {
+                        #(#names => Some(Variant::#idents),)*
+                        _ => None,
+                    }
+                }
+            }
+
+            /// Renders a variant of the experiment from its context
+            #vis fn render_variant(variant: Variant, context: &#ty) -> String {
+                match variant {
+                    #(Variant::#idents => #render_calls),*
+                }
+            }
+        }
+    })
+}
+
+/// Code generated for a template
+struct Generated {
+    /// The template's struct and its impls
+    struct_def: proc_macro2::TokenStream,
+    /// The function building the struct
+    function_def: proc_macro2::TokenStream,
+    struct_name: syn::Ident,
+    /// A `fixture()` constructor was generated
+    fixture: bool,
+    /// Types that must implement `Fixture` for `fixture()` to be callable
+    fixture_bounds: Vec<syn::Type>,
+    /// Name and type of the mapped struct the template reads everything from, rendered from
+    /// it by `render_{name}`
+    context: Option<(String, syn::Type)>,
+    /// Variant and experiment named by `{{!-- @variant "b" of "pricing" --}}`
+    variant: Option<(String, String)>,
+    /// Type arguments of the struct with its generic fields boxed as trait objects, unless a
+    /// field's bounds are given in its mapping
+    erased_params: Option<Vec<proc_macro2::TokenStream>>,
+    /// Mapped variables the template never reads
+    unused_mappings: HashSet<String>,
+}
+
+/// The render function of the compiled code formatted with prettyplease
+#[cfg(feature = "pretty")]
+fn pretty(rust_code: &Rust, span: proc_macro2::Span) -> syn::Result<String> {
+    let function = rust_code.to_function("render", "&self, f: &mut impl std::fmt::Write");
+    let file = syn::parse_file(&function).map_err(|e| {
+        syn::Error::new(span, format!("generated render code doesn't parse: {}", e))
+    })?;
+    Ok(prettyplease::unparse(&file))
+}
+
+#[cfg(not(feature = "pretty"))]
+fn pretty(_rust_code: &Rust, span: proc_macro2::Span) -> syn::Result<String> {
+    Err(syn::Error::new(
+        span,
+        "`pretty = true` needs the `pretty` feature of dry-handlebars",
+    ))
+}
+
 fn generate_code_for_content(
     name: &str,
     content: &str,
     path_for_include: Option<&str>,
-    mut mappings: HashMap<String, syn::Type>,
-) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    literal: Option<&LitStr>,
+    args: &TemplateArgs,
+    span: proc_macro2::Span,
+) -> syn::Result<Generated> {
+    let mut mappings: HashMap<String, syn::Type> = args.mappings.iter().cloned().collect();
+    let mut defaults: HashMap<String, syn::Expr> = args.defaults.iter().cloned().collect();
+    // why each mapped variable got its type, reported with `verbose = true`
+    let mut reasons: HashMap<String, String> = args
+        .mappings
+        .iter()
+        .map(|(name, _)| (name.clone(), "mapped in the macro arguments".to_string()))
+        .collect();
+    let mut decisions = Vec::new();
+    if args.borrow {
+        for (name, ty) in mappings.iter_mut() {
+            if let Some(borrowed) = borrowed(ty) {
+                if let Some(reason) = reasons.get_mut(name) {
+                    reason.push_str(&format!(", borrowed instead of {}", quote! { #ty }));
+                }
+                *ty = borrowed;
+            }
+        }
+    }
+    let vis = &args.vis;
+    let Registered {
+        block_helpers,
+        helpers,
+        safe_helpers,
+        helper_markers,
+    } = register_helpers(args);
     let struct_name_str = name.replace("-", "_");
     let struct_name = format_ident!("{}", struct_name_str);
 
-    let mut content = content.to_string();
+    let source = content;
+    let Prepared {
+        mut content,
+        partial_files,
+        mut warnings,
+    } = prepare_content(content, path_for_include, args, span)?;
 
     let mut block_map = HashMap::new();
     add_builtins(&mut block_map);
 
-    let temp_options = Options {
-        root_var_name: None,
-        write_var_name: "f",
-        variable_types: HashMap::new(),
-    };
+    let temp_options = Options::default().root_var_name(None);
     let temp_compiler = Compiler::new(temp_options, block_map.clone());
     let usages = temp_compiler.scan(&content).unwrap_or_default();
+    mismatched_mappings(
+        args,
+        &temp_compiler.scan_report(&content).unwrap_or_default(),
+    )?;
 
     for (name, usage) in &usages {
-        if !mappings.contains_key(name)
-            && let Usage::Boolean = usage
-        {
-            let bool_ty: syn::Type = syn::parse_quote! { bool };
-            mappings.insert(name.clone(), bool_ty);
+        if mappings.contains_key(name) || matches!(usage, Usage::Display | Usage::Iterated) {
+            continue;
+        }
+        let example = match usage {
+            Usage::Both => "Option<String>",
+            _ => "bool",
+        };
+        if !args.infer_bool {
+            return Err(syn::Error::new(
+                span,
+                format!(
+                    "`{}` is used as a condition but has no mapping and infer_bool = false, \
+                     map it, e.g. (\"{}\", {})",
+                    name, name, example
+                ),
+            ));
+        }
+        if *usage == Usage::Both {
+            let message = format!(
+                "`{}` is used as a condition and as a value, so it is inferred as bool; \
+                 map it to choose its type, e.g. (\"{}\", {})",
+                name, name, example
+            );
+            if args.config.strict {
+                return Err(syn::Error::new(
+                    span,
+                    format!("dry-handlebars: {}", message),
+                ));
+            }
+            warnings.push(lints::warning(&message, span));
         }
+        let bool_ty: syn::Type = syn::parse_quote! { bool };
+        mappings.insert(name.clone(), bool_ty);
+        reasons.insert(
+            name.clone(),
+            "used as the condition of if, unless or class".to_string(),
+        );
     }
 
     // Detect variables used in {{#if var}}
@@ -66,14 +1277,30 @@ fn generate_code_for_content(
         if_vars.insert(cap[1].to_string());
     }
 
-    // Update mappings for if_vars to be Option<T>
+    // Update mappings for if_vars to be Option<T>, collections iterated by each are tested
+    // for being empty instead
     for var in &if_vars {
+        if usages
+            .iter()
+            .any(|(name, usage)| name == var && *usage == Usage::Iterated)
+        {
+            continue;
+        }
         if let Some(ty) = mappings.get(var) {
             // Check if already Option
             let ty_str = quote! { #ty }.to_string();
-            if !ty_str.contains("Option") && ty_str != "bool" {
+            if !ty_str.contains("Option")
+                && ty_str != "bool"
+                && !matches!(ty, syn::Type::ImplTrait(_))
+            {
                 let new_ty: syn::Type = syn::parse_quote! { Option<#ty> };
                 mappings.insert(var.clone(), new_ty);
+                if let Some(default) = defaults.get_mut(var) {
+                    *default = syn::parse_quote! { Some(#default) };
+                }
+                if let Some(reason) = reasons.get_mut(var) {
+                    reason.push_str(", wrapped in Option as it is tested by {{#if}}");
+                }
             }
         }
     }
@@ -93,6 +1320,10 @@ fn generate_code_for_content(
             }
 
             let new_var_name = var_name.replace(".", "_");
+            decisions.push(format!(
+                "`{}` is flattened to the variable `{}` as `{}` has no mapping",
+                var_name, new_var_name, root
+            ));
             mapping.insert(new_var_name.clone(), var_name.to_string());
             full_match.replace(var_name, &new_var_name)
         })
@@ -105,24 +1336,91 @@ fn generate_code_for_content(
     }
 
     // Compile template
-    let options = Options {
-        root_var_name: Some("self"),
-        write_var_name: "f",
-        variable_types,
-    };
-    let compiler = Compiler::new(options, block_map);
-    let rust_code = compiler
-        .compile(&content)
-        .expect("Failed to compile template");
+    let options = Options::default()
+        .root_var_name(Some(args.root_var_name))
+        .write_var_name(args.write_var_name)
+        .variable_types(variable_types)
+        .block_helpers(block_helpers)
+        .helpers(helpers)
+        .safe_helpers(safe_helpers)
+        .escape_html(args.config.escape)
+        .translation_keys(args.config.translation_keys.clone())
+        .debug_in_release(args.debug_in_release)
+        .fragments(args.fragments.iter().cloned().collect());
+    let context_options = options.clone().root_var_name(None);
+    let segments_options = options.clone().segmented(true);
+    let compiler = Compiler::new(options, block_map.clone());
+    let rust_code = compiler.compile_all(&content).map_err(|errors| {
+        template_errors(errors, source, &content, path_for_include, literal, span)
+    })?;
+    let output_size = size_hint(&rust_code);
+    let hash_arg_markers = hash_arg_markers(&rust_code, span)?;
     let render_body: proc_macro2::TokenStream = rust_code
-        .code
+        .code()
         .parse()
         .expect("Failed to parse generated code");
+    let prelude = body_prelude(args, true);
+    let render_body = quote! { #prelude #render_body };
+    let uses = rust_code.uses("::dry_handlebars").to_string();
+    let uses: proc_macro2::TokenStream = if uses.is_empty() {
+        quote! {}
+    } else {
+        format!("{};", uses)
+            .parse()
+            .expect("Failed to parse generated uses")
+    };
+
+    // templates using the t helper are rendered with a translator in scope
+    let translated = rust_code.using().contains("Translated");
+    let translator_default = if translated {
+        quote! { let translator: &dyn ::dry_handlebars::Translator = &::dry_handlebars::NoTranslation; }
+    } else {
+        quote! {}
+    };
+    // templates writing {{nonce}} are rendered with a nonce in scope
+    let nonced = rust_code.nonce();
+    let translator_default = if nonced {
+        quote! { #translator_default let nonce = ""; }
+    } else {
+        translator_default
+    };
+    // templates with cached each items are rendered with a fragment cache in scope
+    let cached = rust_code.cached();
+    let translator_default = if cached {
+        quote! {
+            #translator_default
+            let cache: &dyn ::dry_handlebars::FragmentCache = &::dry_handlebars::NoCache;
+        }
+    } else {
+        translator_default
+    };
+
+    // paths read from the root must be fields of the context type, flattened ones as written
+    let context_checks = match &args.context {
+        Some(context) => {
+            let mut paths: Vec<String> = rust_code
+                .top_level_vars()
+                .iter()
+                .chain(&if_vars)
+                .map(|var| mapping.get(var).unwrap_or(var).clone())
+                .filter(|path| {
+                    path.split('.').all(|field| {
+                        field.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                            && field.chars().all(|c| c.is_alphanumeric() || c == '_')
+                    })
+                })
+                .collect();
+            paths.sort();
+            paths.dedup();
+            context::checks(&struct_name_str, source, &paths, context, span)
+        }
+        None => quote! {},
+    };
 
     // Extract variables
     // Use top_level_vars from compiler
     let mut vars_set = HashSet::new();
-    for var in rust_code.top_level_vars {
+    for var in rust_code.top_level_vars() {
         let root = var.split('.').next().unwrap();
         vars_set.insert(root.to_string());
     }
@@ -151,32 +1449,251 @@ fn generate_code_for_content(
         .collect();
     remaining_vars.sort();
     sorted_vars.extend(remaining_vars);
+    if args.arguments != Arguments::Order {
+        sorted_vars.sort();
+    }
+
+    let unused_mappings = args
+        .mappings
+        .iter()
+        .map(|(name, _)| name.clone())
+        .filter(|name| !sorted_vars.contains(name))
+        .collect::<HashSet<_>>();
 
     let mut type_params = Vec::new();
+    // type parameters of the impls, with the item types of generic lists
+    let mut impl_params = Vec::new();
+    // bounds of the type parameters, in where clauses as each's bound a reference to its list
+    let mut where_predicates = Vec::new();
     let mut field_defs = Vec::new();
     let mut new_args = Vec::new();
     let mut field_inits = Vec::new();
     let mut method_args = Vec::new();
     let mut call_args = Vec::new();
+    // each field with its type, the type parameter for generic ones, set through the builder
+    let mut builder_fields = Vec::new();
 
     let mut generic_param_index: usize = 0;
+    // fixtures fill every field with a placeholder, generic fields become strings
+    let mut fixture_inits = Vec::new();
+    let mut fixture_types = Vec::new();
+    let mut fixture_possible = true;
+    let mut fixture_bounds = Vec::new();
+    // fields of the non-generic struct rendering in compact mode, by reference unless copied
+    let mut erased_fields = Vec::new();
+    let mut erased_inits = Vec::new();
+    let mut erased_params = Vec::new();
+    // every generic field can be read as `&dyn Display` by compact mode
+    let mut erasable = true;
+    // every generic field can be boxed in the variants of templates_enum
+    let mut boxable = true;
+    // `render_value` reads every field from JSON, generic fields become strings like fixtures
+    let mut json_inits = Vec::new();
+    let mut json_possible = !args.borrow;
+    // fields the hot reload interpreter can read: booleans and text
+    let mut hot_values = Vec::new();
 
     for v in &sorted_vars {
         let name = format_ident!("{}", v);
+        fixture_inits.push(quote! { #name: ::dry_handlebars::Fixture::fixture(#v) });
 
-        if let Some(mapped_type) = mappings.get(v) {
+        if let Some(mapped_type) = mappings
+            .get(v)
+            .filter(|ty| !matches!(ty, syn::Type::ImplTrait(_)))
+        {
+            // trait objects are owned through a box
+            let boxed_type: syn::Type;
+            let mapped_type = if let syn::Type::TraitObject(_) = mapped_type {
+                fixture_possible = false;
+                boxed_type = syn::parse_quote! { Box<#mapped_type> };
+                &boxed_type
+            } else {
+                mapped_type
+            };
+            let mut fixture_bound = mapped_type.clone();
+            syn::visit_mut::VisitMut::visit_type_mut(&mut StaticLifetime, &mut fixture_bound);
+            fixture_bounds.push(fixture_bound);
+            // a missing field is read from null, so options become None
+            let missing = match defaults.get(v) {
+                Some(default) => quote! { #default },
+                None => quote! {
+                    ::serde_json::from_value(::serde_json::Value::Null)
+                        .map_err(|_| ::dry_handlebars::RenderError::Missing(#v.to_string()))?
+                },
+            };
+            json_inits.push(quote! {
+                #name: match value.get(#v) {
+                    Some(found) => ::serde_json::from_value(found.clone()).map_err(|err| {
+                        ::dry_handlebars::RenderError::Invalid(#v.to_string(), err.to_string())
+                    })?,
+                    None => #missing,
+                }
+            });
+            let text = matches!(
+                mapped_type,
+                syn::Type::Path(path) if path.path.is_ident("String")
+            ) || matches!(
+                mapped_type,
+                syn::Type::Reference(reference)
+                    if matches!(&*reference.elem, syn::Type::Path(path) if path.path.is_ident("str"))
+            );
+            if matches!(mapped_type, syn::Type::Path(path) if path.path.is_ident("bool")) {
+                hot_values
+                    .push(quote! { (#v, ::dry_handlebars::hot_reload::Value::Bool(self.#name)) });
+            } else if text || is_scalar(mapped_type) {
+                hot_values
+                    .push(quote! { (#v, ::dry_handlebars::hot_reload::Value::Text(&self.#name)) });
+            }
+            if is_scalar(mapped_type) {
+                erased_fields.push(quote! { #name: #mapped_type });
+                erased_inits.push(quote! { #name: self.#name });
+            } else {
+                erased_fields.push(quote! { #name: &'a #mapped_type });
+                erased_inits.push(quote! { #name: &self.#name });
+            }
+            decisions.push(format!(
+                "`{}`: {}, {}",
+                v,
+                quote! { #mapped_type },
+                reasons.get(v).map_or("mapped", String::as_str)
+            ));
             field_defs.push(quote! { pub #name: #mapped_type });
-            new_args.push(quote! { #name: #mapped_type });
-            field_inits.push(quote! { #name });
-            method_args.push(quote! { #name: #mapped_type });
-            call_args.push(quote! { #name });
+            let default = defaults.get(v).cloned();
+            builder_fields.push(BuilderField {
+                name: name.clone(),
+                ty: quote! { #mapped_type },
+                generic: None,
+                default: default.clone(),
+            });
+            // defaulted fields are left out of the arguments, set through the field or builder
+            match default {
+                Some(default) => field_inits.push(quote! { #name: #default }),
+                None => {
+                    new_args.push(quote! { #name: #mapped_type });
+                    field_inits.push(quote! { #name });
+                    method_args.push(quote! { #name: #mapped_type });
+                    call_args.push(quote! { #name });
+                }
+            }
         } else {
             let t_param = format_ident!("T{}", generic_param_index);
             generic_param_index += 1;
 
             type_params.push(t_param.clone());
+            let mut params = quote! { #t_param };
+            let debugged = rust_code
+                .debug_vars()
+                .iter()
+                .any(|var| var.split('.').next() == Some(v.as_str()));
+            let iterated = usages
+                .iter()
+                .any(|(name, usage)| name == v && *usage == Usage::Iterated);
+            // bounds given as `("rows", impl Trait)`, otherwise from how the variable is used
+            let predicate = match (mappings.get(v), iterated) {
+                (Some(syn::Type::ImplTrait(given)), _) => {
+                    let bounds = &given.bounds;
+                    decisions.push(format!(
+                        "`{}`: generic {}: {}, mapped to impl{}",
+                        v,
+                        t_param,
+                        quote! { #bounds },
+                        if iterated {
+                            ", the bounds apply to a reference as each iterates one"
+                        } else {
+                            ""
+                        }
+                    ));
+                    erasable = false;
+                    boxable = false;
+                    fixture_possible = false;
+                    json_possible = false;
+                    fixture_types.push(quote! { String });
+                    match iterated {
+                        true => quote! { for<'x> &'x #t_param: #bounds },
+                        false => quote! { #t_param: #bounds },
+                    }
+                }
+                (_, true) => {
+                    decisions.push(format!(
+                        "`{}`: generic {} iterated by reference to Display items, it has no \
+                         mapping and is iterated by each",
+                        v, t_param
+                    ));
+                    erasable = false;
+                    erased_params.push(quote! { Vec<Box<dyn std::fmt::Display>> });
+                    fixture_types.push(quote! { Vec<String> });
+                    json_inits.push(quote! {
+                        #name: match value.get(#v) {
+                            Some(::serde_json::Value::Array(items)) => {
+                                items.iter().map(text).collect()
+                            }
+                            Some(::serde_json::Value::Null) | None => Vec::new(),
+                            Some(_) => {
+                                return Err(::dry_handlebars::RenderError::Invalid(
+                                    #v.to_string(),
+                                    "expected an array".to_string(),
+                                ));
+                            }
+                        }
+                    });
+                    // the item type is a parameter of the impls, bounds on an associated type
+                    // under a binder aren't inferred
+                    let item = format_ident!("I{}", generic_param_index - 1);
+                    params = quote! { #t_param, #item };
+                    quote! {
+                        for<'x> &'x #t_param: IntoIterator<Item = &'x #item>,
+                        #item: std::fmt::Display
+                    }
+                }
+                _ => {
+                    decisions.push(format!(
+                        "`{}`: generic {}: Display{}, it has no mapping and isn't used as a \
+                         condition",
+                        v,
+                        t_param,
+                        if debugged {
+                            " + Debug (debug helper)"
+                        } else {
+                            ""
+                        }
+                    ));
+                    erased_fields.push(match debugged {
+                        true => quote! { #name: &'a dyn ::dry_handlebars::DisplayDebug },
+                        false => quote! { #name: &'a dyn std::fmt::Display },
+                    });
+                    erased_params.push(match debugged {
+                        true => quote! { Box<dyn ::dry_handlebars::DisplayDebug> },
+                        false => quote! { Box<dyn std::fmt::Display> },
+                    });
+                    erased_inits.push(quote! { #name: &self.#name });
+                    hot_values.push(
+                        quote! { (#v, ::dry_handlebars::hot_reload::Value::Text(&self.#name)) },
+                    );
+                    fixture_types.push(quote! { String });
+                    json_inits.push(quote! {
+                        #name: match value.get(#v) {
+                            Some(found) => text(found),
+                            None => {
+                                return Err(::dry_handlebars::RenderError::Missing(#v.to_string()));
+                            }
+                        }
+                    });
+                    match debugged {
+                        true => quote! { #t_param: std::fmt::Display + std::fmt::Debug },
+                        false => quote! { #t_param: std::fmt::Display },
+                    }
+                }
+            };
+            where_predicates.push(predicate.clone());
+            impl_params.push(params.clone());
 
             field_defs.push(quote! { pub #name: #t_param });
+            builder_fields.push(BuilderField {
+                name: name.clone(),
+                ty: quote! { #t_param },
+                generic: Some((params, predicate)),
+                default: None,
+            });
             new_args.push(quote! { #name: #t_param });
             field_inits.push(quote! { #name });
             method_args.push(quote! { #name: #t_param });
@@ -184,159 +1701,1734 @@ fn generate_code_for_content(
         }
     }
 
-    let method_name_str = to_snake_case(&struct_name_str);
-    let method_name = format_ident!("{}", method_name_str);
+    // borrowed fields share the struct's lifetime, declared ahead of its type parameters,
+    // a struct none of whose fields borrow has none
+    let generic = !type_params.is_empty();
+    let lifetime = (args.borrow && field_defs.iter().any(|field| names_lifetime(field, "a")))
+        .then(|| quote! { 'a });
+    let type_params = lifetime
+        .iter()
+        .cloned()
+        .chain(type_params.iter().map(|param| quote! { #param }))
+        .collect::<Vec<_>>();
+    let impl_params = lifetime
+        .iter()
+        .cloned()
+        .chain(impl_params)
+        .collect::<Vec<_>>();
+    let where_clause = quote! { where #(#where_predicates),* };
 
-    let function_def = quote! {
-        pub fn #method_name<#(#type_params: std::fmt::Display),*>(#(#method_args),*) -> #struct_name<#(#type_params),*> {
-            #struct_name::new(#(#call_args),*)
+    // a template reading everything from one mapped struct can be built from, and rendered
+    // with, that struct directly
+    let context = match sorted_vars.as_slice() {
+        [root]
+            if args.mappings.iter().any(|(name, _)| name == root)
+                && names_struct(&mappings[root]) =>
+        {
+            Some((root.clone(), mappings[root].clone()))
         }
+        _ => None,
     };
+    let context_impl = match &context {
+        Some((root, ty)) => {
+            let name = format_ident!("{}", root);
+            let render_name = format_ident!("render_{}", root);
+            let try_render_name = format_ident!("try_render_{}", root);
+            let render_doc = format!(
+                " Renders the template from `{0}`, panicking when a value's `Display` implementation fails, see [`{1}`](Self::{1})",
+                root, try_render_name
+            );
+            let try_render_doc = format!(
+                " Renders the template from `{}`, failing when a value's `Display` implementation does",
+                root
+            );
+            let context_body: proc_macro2::TokenStream =
+                Compiler::new(context_options, block_map.clone())
+                    .compile(&content)
+                    .expect("Failed to compile template")
+                    .code()
+                    .parse()
+                    .expect("Failed to parse generated code");
+            let prelude = body_prelude(args, false);
+            quote! {
+                impl<#(#type_params),*> From<#ty> for #struct_name<#(#type_params),*> {
+                    fn from(#name: #ty) -> Self {
+                        Self { #name }
+                    }
+                }
 
-    let include_bytes_stmt = if let Some(path_str) = path_for_include {
-        quote! {
-            // ensure the compiler is aware the output is linked to the source so that any changes
-            // to the hbs file will trigger a recompilation
-            const _: &[u8] = include_bytes!(#path_str);
+                impl<#(#type_params),*> #struct_name<#(#type_params),*> {
+                    #[doc = #render_doc]
+                    #vis fn #render_name(#name: &#ty) -> String {
+                        Self::#try_render_name(#name)
+                            .expect("a Display implementation returned an error")
+                    }
+
+                    #[doc = #try_render_doc]
+                    #vis fn #try_render_name(#name: &#ty) -> Result<String, std::fmt::Error> {
+                        use std::fmt::Write;
+                        #uses
+                        #translator_default
+                        let mut f = String::with_capacity(#output_size);
+                        let mut render_inner = || -> std::fmt::Result {
+                            #prelude
+                            #context_body
+                            Ok(())
+                        };
+                        render_inner()?;
+                        Ok(f)
+                    }
+                }
+            }
         }
-    } else {
-        quote! {}
+        None => quote! {},
     };
 
-    let struct_def = quote! {
-        #include_bytes_stmt
+    // static text is kept as borrowed segments for vectored writes
+    let segments_impl = if args.segments {
+        let segments_body: proc_macro2::TokenStream =
+            Compiler::new(segments_options, block_map.clone())
+                .compile(&content)
+                .expect("Failed to compile template")
+                .code()
+                .parse()
+                .expect("Failed to parse generated code");
+        quote! {
+            /// Renders the template as segments borrowing its static text, panicking when a
+            /// value's `Display` implementation fails, see
+            /// [`try_render_segments`](Self::try_render_segments)
+            #vis fn render_segments(&self) -> ::dry_handlebars::Segments {
+                self.try_render_segments()
+                    .expect("a Display implementation returned an error")
+            }
 
-        pub struct #struct_name<#(#type_params),*> {
-            #(#field_defs),*
+            /// Renders the template as segments borrowing its static text, failing when a
+            /// value's `Display` implementation does
+            #vis fn try_render_segments(
+                &self,
+            ) -> Result<::dry_handlebars::Segments, std::fmt::Error> {
+                use std::fmt::Write;
+                #uses
+                #translator_default
+                let mut f = ::dry_handlebars::Segments::new();
+                let mut render_inner = || -> std::fmt::Result {
+                    #prelude
+                    #segments_body
+                    Ok(())
+                };
+                render_inner()?;
+                Ok(f)
+            }
         }
+    } else {
+        quote! {}
+    };
 
-        impl<#(#type_params: std::fmt::Display),*> #struct_name<#(#type_params),*> {
-            pub fn new(#(#new_args),*) -> Self {
-                Self {
-                    #(#field_inits),*
+    // debug builds of file templates interpret the file first, so edits show without a
+    // rebuild, templates whose source is rewritten before compiling or that render with more
+    // than their fields always use the compiled body
+    let hot_body = match path_for_include {
+        Some(path)
+            if cfg!(feature = "hot-reload")
+                && !translated
+                && !nonced
+                && !cached
+                && !rust_code.using().contains("HoleWriter")
+                && args.newlines.is_none()
+                && !args.config.minify
+                && !args.csp_nonce =>
+        {
+            let escape = args.config.escape;
+            quote! {
+                #[cfg(debug_assertions)]
+                if let Some(hot) = ::dry_handlebars::hot_reload::render(
+                    #path,
+                    &[#(#hot_values),*],
+                    #escape,
+                ) {
+                    return f.write_str(&hot);
                 }
+                #render_body
+            }
+        }
+        _ => render_body.clone(),
+    };
+    let RenderMethods {
+        full_name,
+        try_name,
+        into_name,
+        full_params,
+        full_args,
+        render_fn,
+        shorthands,
+    } = render_methods(vis, &uses, &hot_body, &rust_code);
+    let render_fns = quote! {
+        #(#shorthands)*
+        #render_fn
+    };
+
+    // templates with holes can also be rendered as their shell or one of their holes
+    let holes_impl = if rust_code.using().contains("HoleWriter") {
+        quote! {
+            /// Renders the template with the content of its holes left out, panicking when a
+            /// value's `Display` implementation fails, see
+            /// [`try_render_shell`](Self::try_render_shell)
+            #vis fn render_shell(&self) -> ::dry_handlebars::Shell {
+                self.try_render_shell()
+                    .expect("a Display implementation returned an error")
+            }
+
+            /// Renders the template with the content of its holes left out, failing when a
+            /// value's `Display` implementation does
+            #vis fn try_render_shell(&self) -> Result<::dry_handlebars::Shell, std::fmt::Error> {
+                use std::fmt::Write;
+                #uses
+                #translator_default
+                let mut f = ::dry_handlebars::Shell::new();
+                let mut render_inner = || -> std::fmt::Result {
+                    #render_body
+                    Ok(())
+                };
+                render_inner()?;
+                Ok(f)
             }
 
-            pub fn render(&self) -> String {
+            /// Renders the content of the holes of a name, empty when there is none, panicking
+            /// when a value's `Display` implementation fails, see
+            /// [`try_render_hole`](Self::try_render_hole)
+            #vis fn render_hole(&self, name: &str) -> String {
+                self.try_render_hole(name)
+                    .expect("a Display implementation returned an error")
+            }
+
+            /// Renders the content of the holes of a name, empty when there is none, failing
+            /// when a value's `Display` implementation does
+            #vis fn try_render_hole(&self, name: &str) -> Result<String, std::fmt::Error> {
                 use std::fmt::Write;
-                let mut f = String::new();
+                #uses
+                #translator_default
+                let mut f = ::dry_handlebars::HoleFragment::new(name);
                 let mut render_inner = || -> std::fmt::Result {
                     #render_body
                     Ok(())
                 };
-                render_inner().unwrap();
-                f
+                render_inner()?;
+                Ok(f.into_string())
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    if args.verbose {
+        decisions.push(
+            match args.config.escape {
+                true => "{{ }} output is HTML-escaped, {{{ }}} and safe helpers are written as is",
+                false => "output is written unescaped, as text or with escape = \"none\"",
+            }
+            .to_string(),
+        );
+        let mut reported = HashSet::new();
+        warnings.extend(
+            decisions
+                .iter()
+                .filter(|decision| reported.insert(*decision))
+                .map(|decision| lints::warning(&format!("{}: {}", name, decision), span)),
+        );
+    }
+
+    // the generic impl only forwards to the non-generic one, whose code is generated once
+    let (render_fns, erased_def) = if args.compact && generic && erasable {
+        let erased_name = format_ident!("{}_erased", struct_name_str);
+        let erased = quote! { #erased_name { #(#erased_inits),* } };
+        let forwarding = quote! {
+            #(#shorthands)*
+
+            #vis fn size_hint(&self) -> usize {
+                #erased.size_hint()
+            }
+
+            #vis fn #full_name(&self, #(#full_params),*) -> String {
+                #erased.#full_name(#(#full_args),*)
+            }
+
+            #vis fn #try_name(&self, #(#full_params),*) -> Result<String, std::fmt::Error> {
+                #erased.#try_name(#(#full_args),*)
+            }
+
+            #vis fn #into_name(
+                &self,
+                f: &mut (impl std::fmt::Write + ?Sized),
+                #(#full_params),*
+            ) -> std::fmt::Result {
+                #erased.#into_name(f, #(#full_args),*)
+            }
+        };
+        let erased_def = quote! {
+            #[doc(hidden)]
+            #[allow(non_camel_case_types)]
+            #vis struct #erased_name<'a> {
+                #(#erased_fields),*
+            }
+
+            impl #erased_name<'_> {
+                #render_fn
+            }
+        };
+        (forwarding, erased_def)
+    } else {
+        (render_fns, quote! {})
+    };
+
+    // previews of a catalog render from fixtures, whether or not tests are being built
+    let fixture = (cfg!(feature = "test-fixtures") || args.catalog.is_some()) && fixture_possible;
+    let preview_fn = match args.catalog {
+        Some(_) => quote! {
+            /// Renders the template built with placeholder values, as listed by `catalog()`
+            #vis fn preview() -> String
+            where
+                #(for<'fixture> #fixture_bounds: ::dry_handlebars::Fixture,)*
+            {
+                Self::fixture().render()
+            }
+        },
+        None => quote! {},
+    };
+    let fixture_lifetime = lifetime.iter().map(|_| quote! { 'static, });
+    let fixture_impl = if fixture {
+        quote! {
+            impl #struct_name<#(#fixture_lifetime)* #(#fixture_types),*> {
+                /// Builds the template with deterministic placeholder values, callable once
+                /// the mapped types implement `dry_handlebars::Fixture`
+                #vis fn fixture() -> Self
+                where
+                    // bounds under a binder aren't checked until the function is called
+                    #(for<'fixture> #fixture_bounds: ::dry_handlebars::Fixture,)*
+                {
+                    Self {
+                        #(#fixture_inits),*
+                    }
+                }
+
+                #preview_fn
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    if let Some(json_span) = args.json
+        && !json_possible
+    {
+        return Err(syn::Error::new(
+            json_span,
+            format!(
+                "{} can't be rendered from JSON, it has an `impl` mapping or borrows its fields",
+                name
+            ),
+        ));
+    }
+    // the impl names the serde crates of the using crate, so only calls asking for it get one
+    let json_impl = if args.json.is_some() {
+        quote! {
+            impl #struct_name<#(#fixture_types),*> {
+                /// Renders the template with its variables looked up in JSON data at runtime,
+                /// mapped fields are deserialized and generic ones written as text
+                #[allow(unused_variables)]
+                #vis fn render_value(
+                    value: &::serde_json::Value,
+                ) -> Result<String, ::dry_handlebars::RenderError>
+                where
+                    // bounds under a binder aren't checked until the function is called
+                    #(for<'json> #fixture_bounds: ::serde::de::DeserializeOwned,)*
+                {
+                    // strings are written without their quotes and null as nothing
+                    #[allow(dead_code)]
+                    fn text(value: &::serde_json::Value) -> String {
+                        match value {
+                            ::serde_json::Value::String(text) => text.clone(),
+                            ::serde_json::Value::Null => String::new(),
+                            other => other.to_string(),
+                        }
+                    }
+                    let template = Self {
+                        #(#json_inits),*
+                    };
+                    let mut out = String::with_capacity(template.size_hint());
+                    std::fmt::Write::write_fmt(&mut out, format_args!("{}", template))?;
+                    Ok(out)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let builder_impl = match builder_fields.is_empty() {
+        true => quote! {},
+        false => builder(vis, &struct_name_str, &builder_fields, lifetime.as_ref()),
+    };
+
+    let method_name_str = to_snake_case(&struct_name_str);
+    let method_name = format_ident!("{}", method_name_str);
+
+    // a leading `@doc` comment documents the struct and function, followed by the variables
+    let doc_attrs = match template_doc(source) {
+        Some(mut lines) => {
+            if !sorted_vars.is_empty() {
+                lines.push(String::new());
+                lines.push(format!(
+                    "Variables: {}",
+                    sorted_vars
+                        .iter()
+                        .map(|v| format!("`{}`", v))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+            lines
+                .iter()
+                .map(|line| match line.is_empty() {
+                    true => quote! { #[doc = ""] },
+                    false => {
+                        let line = format!(" {}", line);
+                        quote! { #[doc = #line] }
+                    }
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+
+    // with named arguments the struct is built with the builder or a struct literal
+    let (new_fn, function_def) = match args.arguments {
+        Arguments::Named => (quote! {}, quote! {}),
+        _ => (
+            quote! {
+                #vis fn new(#(#new_args),*) -> Self {
+                    Self {
+                        #(#field_inits),*
+                    }
+                }
+            },
+            quote! {
+                #(#doc_attrs)*
+                #vis fn #method_name<#(#impl_params),*>(#(#method_args),*) -> #struct_name<#(#type_params),*>
+                #where_clause
+                {
+                    #struct_name::new(#(#call_args),*)
+                }
+            },
+        ),
+    };
+
+    let include_bytes_stmt = if let Some(path_str) = path_for_include {
+        quote! {
+            // ensure the compiler is aware the output is linked to the source so that any changes
+            // to the hbs file will trigger a recompilation
+            const _: &[u8] = include_bytes!(#path_str);
+            #(const _: &[u8] = include_bytes!(#partial_files);)*
+        }
+    } else {
+        quote! {
+            #(const _: &[u8] = include_bytes!(#partial_files);)*
+        }
+    };
+
+    // the render body is a single line of tokens in the expansion, the doc lays it out
+    let code_doc = match args.pretty {
+        true => ["", " Render code:", "", " ```text"]
+            .into_iter()
+            .skip(usize::from(doc_attrs.is_empty()))
+            .map(str::to_string)
+            .chain(
+                pretty(&rust_code, span)?
+                    .lines()
+                    .map(|line| format!(" {}", line)),
+            )
+            .chain([" ```".to_string()])
+            .map(|line| quote! { #[doc = #line] })
+            .collect(),
+        false => Vec::new(),
+    };
+
+    let template_impl = template_impl(
+        &struct_name_str,
+        path_for_include,
+        args.text,
+        &content,
+        &rust_code,
+        quote! {
+            impl<#(#impl_params),*> ::dry_handlebars::Template for #struct_name<#(#type_params),*>
+            #where_clause
+        },
+    );
+    let into_response_impl = into_response_impl(
+        args.axum.as_ref(),
+        quote! { <#(#impl_params),*> },
+        quote! { #struct_name<#(#type_params),*> },
+        quote! { #where_clause },
+    );
+
+    let struct_def = quote! {
+        #include_bytes_stmt
+        #(#warnings)*
+
+        // registered helpers must be annotated with #[dry_handlebars::helper]
+        #(const _: () = #helper_markers;)*
+        // hash arguments must name the parameter they are passed as
+        #(const _: () = #hash_arg_markers;)*
+
+        #(#doc_attrs)*
+        #(#code_doc)*
+        #vis struct #struct_name<#(#type_params),*> {
+            #(#field_defs),*
+        }
+
+        impl<#(#impl_params),*> #struct_name<#(#type_params),*> #where_clause {
+            #new_fn
+
+            #render_fns
+
+            #segments_impl
+
+            #holes_impl
+        }
+
+        // formatting renders straight into the formatter, so a template nests in another
+        // as a value written with {{{ }}}
+        impl<#(#impl_params),*> std::fmt::Display for #struct_name<#(#type_params),*>
+        #where_clause
+        {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.render_into(f)
             }
         }
+
+        #builder_impl
+        #template_impl
+        #into_response_impl
+        #erased_def
+        #context_impl
+        #fixture_impl
+        #json_impl
+        #context_checks
     };
 
-    (struct_def, function_def)
+    Ok(Generated {
+        struct_def,
+        function_def,
+        struct_name,
+        fixture,
+        fixture_bounds,
+        context,
+        variant: experiment_variant(source),
+        unused_mappings,
+        // enum variants hold templates borrowing nothing
+        erased_params: boxable.then(|| {
+            lifetime
+                .iter()
+                .map(|_| quote! { 'static })
+                .chain(erased_params)
+                .collect()
+        }),
+    })
+}
+
+/// Makes the compiler expand the macro again when a file is added to or removed from a
+/// directory, which needs the `nightly` feature, on stable only the files read are tracked
+/// and a build script prints `cargo:rerun-if-changed` for the directory
+fn track_directory(path: &Path) {
+    #[cfg(feature = "nightly")]
+    proc_macro::tracked::path(path);
+    #[cfg(not(feature = "nightly"))]
+    let _ = path;
 }
 
-fn generate_code_for_file(path: &Path) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    let file_stem = path.file_stem().unwrap().to_string_lossy();
+/// Generates code for a template file, a file that can't be read is reported as an error
+/// spanned to the macro's path argument
+fn generate_code_for_file(
+    lit: &LitStr,
+    path: &Path,
+    name: &str,
+    args: &TemplateArgs,
+) -> syn::Result<Generated> {
     let path_str = path.to_string_lossy();
-    let content = fs::read_to_string(path).expect("Failed to read file");
-    generate_code_for_content(&file_stem, &content, Some(&path_str), HashMap::new())
+    let bytes = fs::read(path).map_err(|err| {
+        syn::Error::new(
+            lit.span(),
+            format!("Failed to read template {:?}: {}", path, err),
+        )
+    })?;
+    let content = String::from_utf8(bytes).map_err(|err| {
+        syn::Error::new(
+            lit.span(),
+            format!(
+                "Template {:?} is not valid UTF-8: invalid byte at offset {}",
+                path,
+                err.utf8_error().valid_up_to()
+            ),
+        )
+    })?;
+    generate_code_for_content(name, &content, Some(&path_str), None, args, lit.span())
 }
 
-struct StrInput {
-    name: LitStr,
-    content: LitStr,
+/// Arguments following the template source, shared by all macros
+#[derive(Clone)]
+struct TemplateArgs {
+    /// Settings of the crate's `dry-handlebars.toml`, the defaults of the other arguments
+    config: Config,
+    /// Type mappings: `("person", Person)`
     mappings: Vec<(String, syn::Type)>,
+    /// Values of the mapped variables `new` and the builder don't take:
+    /// `("subtitle", Option<String>, default = None)`
+    defaults: Vec<(String, syn::Expr)>,
+    /// Mapped variables written raw as a `RenderableFragment`: `("sidebar", Markup => fragment)`
+    fragments: Vec<String>,
+    /// Block helpers registered from user code: `blocks = [("card", crate::helpers::card)]`
+    block_helpers: Vec<(String, syn::Path)>,
+    /// Inline helpers annotated with `#[helper]`: `helpers = [crate::helpers::shout]`, or
+    /// any function given a name: `helpers = [("uuid", uuid::Uuid::new_v4)]`
+    helpers: Vec<HelperArg>,
+    /// Line ending normalization: `newlines = "lf"` or `newlines = "crlf"`, kept as is by default
+    newlines: Option<Newlines>,
+    /// Order of the arguments of `new()` and the function, `arguments = "sorted"` sorts them by
+    /// name and `arguments = "named"` leaves both out for the builder and struct literals
+    arguments: Arguments,
+    /// Indent standalone partials like their tag, disabled with `partial_indent = false`
+    partial_indent: bool,
+    /// Partials of the directory being compiled, files whose name starts with `_`
+    partials: std::rc::Rc<partials::Registry>,
+    /// Also generate `render_segments`, enabled with `segments = true`
+    segments: bool,
+    /// Render `{{debug value}}` in release builds, enabled with `debug_in_release = true`
+    debug_in_release: bool,
+    /// Span of `render_all_with_defaults = true`, which makes `directory!` emit a test
+    /// rendering every template from fixtures
+    render_all: Option<proc_macro2::Span>,
+    /// Span of `catalog = true`, which makes `directory!` generate a `preview()` per template
+    /// and a `catalog()` of them from fixtures
+    catalog: Option<proc_macro2::Span>,
+    /// Span of `json = true`, which generates `render_value` reading the fields from a
+    /// `serde_json::Value`, with the `json` feature
+    json: Option<proc_macro2::Span>,
+    /// Path of the axum crate `IntoResponse` is implemented with, `::axum` for `axum = true`,
+    /// with the `axum` feature
+    axum: Option<syn::Path>,
+    /// Report the type chosen for each variable as a warning, enabled with `verbose = true`
+    verbose: bool,
+    /// Document the struct with its render function formatted by prettyplease, as `cargo expand`
+    /// and rustdoc show it, enabled with `pretty = true` and the `pretty` feature
+    pretty: bool,
+    /// Render generic templates through a non-generic function taking their generic fields as
+    /// `&dyn Display`, enabled with `compact = true`
+    compact: bool,
+    /// Add `nonce="{{nonce}}"` to `<script>` and `<style>` tags, enabled with `csp_nonce = true`
+    csp_nonce: bool,
+    /// Give unmapped condition variables the type `bool`, disabled with `infer_bool = false`
+    infer_bool: bool,
+    /// Map `String` and `Vec<T>` to `&'a str` and `&'a [T]` borrowed for the struct's lifetime
+    /// `'a`, which mappings can also name, enabled with `borrow = true`
+    borrow: bool,
+    /// Struct whose fields the template's variables are checked against:
+    /// `context = crate::CardData`
+    context: Option<syn::Type>,
+    /// Name the generated code reads the template's fields from: `root_var_name = ctx`
+    root_var_name: &'static str,
+    /// Name the generated code writes to: `write_var_name = out`
+    write_var_name: &'static str,
+    /// Visibility of the generated structs and functions: `vis = pub(crate)`, `pub` by default
+    vis: syn::Visibility,
+    /// Report a block local hiding a variable as an error, enabled with `deny_shadowing = true`
+    deny_shadowing: bool,
+    /// Warn about `id` attributes repeated in the rendered page, disabled with
+    /// `check_ids = false`
+    check_ids: bool,
+    /// Name of the struct generated by `file!` instead of the file stem: `as = "AdminIndex"`
+    rename: Option<LitStr>,
+    /// Prepended to the names generated by `directory!`: `prefix = "admin_"`
+    prefix: Option<LitStr>,
+    /// Span of `name_by_path = true`, which makes `directory!` name templates after their path
+    /// in the directory, `admin/index.hbs` becoming `admin_index`
+    name_by_path: Option<proc_macro2::Span>,
+    /// The template is text rather than HTML, such as `email.txt.hbs` in `directory!`
+    text: bool,
+    /// Span of `templates_enum = true`, which makes `directory!` emit `enum Templates` and
+    /// `render_by_name`
+    templates_enum: Option<proc_macro2::Span>,
+    /// Patterns of the files `directory!` skips: `exclude = ["**/drafts/**"]`
+    exclude: Vec<LitStr>,
+    /// Extensions of the files `directory!` compiles, `hbs` by default:
+    /// `extensions = ["hbs", "html.hbs"]`
+    extensions: Vec<LitStr>,
+}
+
+impl From<&Config> for TemplateArgs {
+    fn from(config: &Config) -> Self {
+        TemplateArgs {
+            config: config.clone(),
+            mappings: Vec::new(),
+            defaults: Vec::new(),
+            fragments: Vec::new(),
+            block_helpers: Vec::new(),
+            helpers: Vec::new(),
+            newlines: config.newlines,
+            arguments: config.arguments,
+            partial_indent: config.partial_indent,
+            partials: Default::default(),
+            segments: config.segments,
+            debug_in_release: false,
+            render_all: None,
+            catalog: None,
+            verbose: config.verbose,
+            pretty: config.pretty,
+            compact: config.compact,
+            json: None,
+            axum: None,
+            csp_nonce: config.csp_nonce,
+            infer_bool: config.infer_bool,
+            borrow: false,
+            context: None,
+            root_var_name: "self",
+            write_var_name: "f",
+            vis: syn::parse_quote! { pub },
+            deny_shadowing: config.deny_shadowing,
+            check_ids: config.check_ids,
+            rename: None,
+            prefix: None,
+            name_by_path: None,
+            text: false,
+            templates_enum: None,
+            exclude: Vec::new(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+/// A registered inline helper, followed by `=> safe` when it returns already escaped HTML
+/// or `=> text` (the default) when its output is escaped like any other value
+#[derive(Clone)]
+struct HelperArg {
+    /// Name given in the registration, otherwise the function name
+    name: Option<String>,
+    path: syn::Path,
+    safe: bool,
 }
 
-impl Parse for StrInput {
+impl Parse for HelperArg {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let name: LitStr = input.parse()?;
-        input.parse::<Token![,]>()?;
-        let content: LitStr = input.parse()?;
+        let (name, path) = if input.peek(syn::token::Paren) {
+            let pair;
+            syn::parenthesized!(pair in input);
+            let name: LitStr = pair.parse()?;
+            pair.parse::<Token![,]>()?;
+            (Some(name.value()), pair.parse()?)
+        } else {
+            (None, input.parse()?)
+        };
+        let mut safe = false;
+        if input.peek(Token![=>]) {
+            input.parse::<Token![=>]>()?;
+            let kind: syn::Ident = input.parse()?;
+            safe = match kind.to_string().as_str() {
+                "safe" => true,
+                "text" => false,
+                _ => {
+                    return Err(syn::Error::new(
+                        kind.span(),
+                        format!("unknown helper output `{}`, expected safe or text", kind),
+                    ));
+                }
+            };
+        }
+        Ok(HelperArg { name, path, safe })
+    }
+}
 
-        let mut mappings = Vec::new();
-        if input.peek(Token![,]) {
-            input.parse::<Token![,]>()?;
-            while !input.is_empty() {
+impl TemplateArgs {
+    /// Parses the arguments given to a macro, overriding the configured ones
+    fn parse_into(mut self, input: ParseStream) -> syn::Result<Self> {
+        let args = &mut self;
+        while !input.is_empty() {
+            if input.peek(syn::token::Paren) {
                 let content;
                 syn::parenthesized!(content in input);
                 let key: LitStr = content.parse()?;
                 content.parse::<Token![,]>()?;
                 let ty: syn::Type = content.parse()?;
-                mappings.push((key.value(), ty));
-
-                if input.peek(Token![,]) {
-                    input.parse::<Token![,]>()?;
+                if content.peek(Token![=>]) {
+                    content.parse::<Token![=>]>()?;
+                    let kind: syn::Ident = content.parse()?;
+                    if kind != "fragment" {
+                        return Err(syn::Error::new(
+                            kind.span(),
+                            format!("unknown mapping output `{}`, expected fragment", kind),
+                        ));
+                    }
+                    args.fragments.push(key.value());
                 }
+                if content.peek(Token![,]) {
+                    content.parse::<Token![,]>()?;
+                    let option: syn::Ident = content.parse()?;
+                    if option != "default" {
+                        return Err(syn::Error::new(
+                            option.span(),
+                            format!("unknown mapping option `{}`, expected default", option),
+                        ));
+                    }
+                    content.parse::<Token![=]>()?;
+                    args.defaults.push((key.value(), content.parse()?));
+                }
+                args.mappings.push((key.value(), ty));
+            } else {
+                // `as` is a keyword
+                let key = input.call(syn::Ident::parse_any)?;
+                input.parse::<Token![=]>()?;
+                match key.to_string().as_str() {
+                    "blocks" => {
+                        let content;
+                        syn::bracketed!(content in input);
+                        while !content.is_empty() {
+                            let pair;
+                            syn::parenthesized!(pair in content);
+                            let name: LitStr = pair.parse()?;
+                            pair.parse::<Token![,]>()?;
+                            let path: syn::Path = pair.parse()?;
+                            args.block_helpers.push((name.value(), path));
+                            if !content.is_empty() {
+                                content.parse::<Token![,]>()?;
+                            }
+                        }
+                    }
+                    "helpers" => {
+                        let content;
+                        syn::bracketed!(content in input);
+                        let helpers = content.parse_terminated(HelperArg::parse, Token![,])?;
+                        args.helpers.extend(helpers);
+                    }
+                    "segments" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.segments = value.value;
+                    }
+                    "minify" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.config.minify = value.value;
+                    }
+                    "csp_nonce" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.csp_nonce = value.value;
+                    }
+                    "context" => {
+                        args.context = Some(input.parse()?);
+                    }
+                    "as" => {
+                        let value: LitStr = input.parse()?;
+                        if syn::parse_str::<syn::Ident>(&value.value()).is_err() {
+                            return Err(syn::Error::new(
+                                value.span(),
+                                format!("`{}` is not a valid struct name", value.value()),
+                            ));
+                        }
+                        args.rename = Some(value);
+                    }
+                    "prefix" => {
+                        let value: LitStr = input.parse()?;
+                        args.prefix = Some(value);
+                    }
+                    "name_by_path" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.name_by_path = value.value.then(|| key.span());
+                    }
+                    "templates_enum" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.templates_enum = value.value.then(|| key.span());
+                    }
+                    "extensions" => {
+                        let content;
+                        syn::bracketed!(content in input);
+                        let extensions =
+                            content.parse_terminated(<LitStr as Parse>::parse, Token![,])?;
+                        args.extensions.extend(extensions);
+                    }
+                    "exclude" => {
+                        let content;
+                        syn::bracketed!(content in input);
+                        let patterns =
+                            content.parse_terminated(<LitStr as Parse>::parse, Token![,])?;
+                        args.exclude.extend(patterns);
+                    }
+                    "vis" => {
+                        args.vis = input.parse()?;
+                    }
+                    "root_var_name" | "write_var_name" => {
+                        let name: syn::Ident = input.parse()?;
+                        // the compiler options are shared by every template, a macro
+                        // invocation only lives as long as the compilation
+                        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+                        match key.to_string().as_str() {
+                            "root_var_name" => args.root_var_name = name,
+                            _ => args.write_var_name = name,
+                        }
+                    }
+                    "escape" => {
+                        let value: LitStr = input.parse()?;
+                        args.config.escape = match value.value().as_str() {
+                            "html" => true,
+                            "none" => false,
+                            other => {
+                                return Err(syn::Error::new(
+                                    value.span(),
+                                    format!(
+                                        "unknown escape `{}`, expected \"html\" or \"none\"",
+                                        other
+                                    ),
+                                ));
+                            }
+                        };
+                    }
+                    "deny_shadowing" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.deny_shadowing = value.value;
+                    }
+                    "check_ids" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.check_ids = value.value;
+                    }
+                    "infer_bool" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.infer_bool = value.value;
+                    }
+                    "compact" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.compact = value.value;
+                    }
+                    "borrow" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.borrow = value.value;
+                    }
+                    "pretty" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.pretty = value.value;
+                    }
+                    "verbose" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.verbose = value.value;
+                    }
+                    "debug_in_release" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.debug_in_release = value.value;
+                    }
+                    "render_all_with_defaults" => {
+                        let value: syn::LitBool = input.parse()?;
+                        if value.value && !cfg!(feature = "test-fixtures") {
+                            return Err(syn::Error::new(
+                                key.span(),
+                                "render_all_with_defaults requires the test-fixtures feature",
+                            ));
+                        }
+                        args.render_all = value.value.then(|| key.span());
+                    }
+                    "json" => {
+                        let value: syn::LitBool = input.parse()?;
+                        if value.value && !cfg!(feature = "json") {
+                            return Err(syn::Error::new(
+                                key.span(),
+                                "json requires the json feature",
+                            ));
+                        }
+                        args.json = value.value.then(|| key.span());
+                    }
+                    "axum" => {
+                        if !cfg!(feature = "axum") {
+                            return Err(syn::Error::new(
+                                key.span(),
+                                "axum requires the axum feature",
+                            ));
+                        }
+                        args.axum = match input.peek(syn::LitBool) {
+                            true => input
+                                .parse::<syn::LitBool>()?
+                                .value
+                                .then(|| syn::parse_quote! { ::axum }),
+                            false => Some(input.parse()?),
+                        };
+                    }
+                    "catalog" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.catalog = value.value.then(|| key.span());
+                    }
+                    "partial_indent" => {
+                        let value: syn::LitBool = input.parse()?;
+                        args.partial_indent = value.value;
+                    }
+                    "newlines" => {
+                        let value: LitStr = input.parse()?;
+                        args.newlines = Some(
+                            Newlines::parse(&value.value())
+                                .map_err(|message| syn::Error::new(value.span(), message))?,
+                        );
+                    }
+                    "arguments" => {
+                        let value: LitStr = input.parse()?;
+                        args.arguments = Arguments::parse(&value.value())
+                            .map_err(|message| syn::Error::new(value.span(), message))?;
+                    }
+                    _ => {
+                        return Err(syn::Error::new(
+                            key.span(),
+                            format!("unknown argument `{}`", key),
+                        ));
+                    }
+                }
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
             }
         }
+        Ok(self)
+    }
+}
+
+/// Input of `file!` and `directory!`: a path followed by optional arguments
+struct PathInput {
+    path: LitStr,
+    args: TemplateArgs,
+}
+
+impl PathInput {
+    fn parse(input: ParseStream, config: &Config) -> syn::Result<Self> {
+        let path: LitStr = input.parse()?;
+        let mut args = TemplateArgs::from(config);
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            args = args.parse_into(input)?;
+        }
+        Ok(PathInput { path, args })
+    }
+}
+
+struct StrInput {
+    name: LitStr,
+    content: LitStr,
+    args: TemplateArgs,
+}
+
+impl StrInput {
+    fn parse(input: ParseStream, config: &Config) -> syn::Result<Self> {
+        let name: LitStr = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let content: LitStr = input.parse()?;
+
+        let mut args = TemplateArgs::from(config);
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            args = args.parse_into(input)?;
+        }
         Ok(StrInput {
             name,
             content,
-            mappings,
+            args,
         })
     }
 }
 
-#[proc_macro]
-pub fn dry_handlebars_directory(input: TokenStream) -> TokenStream {
-    let dir_lit = parse_macro_input!(input as LitStr);
-    let dir_str = dir_lit.value();
+/// Name of the hidden marker emitted next to a `#[helper]` function
+fn helper_marker(name: &str) -> syn::Ident {
+    format_ident!("__dry_handlebars_helper_{}", name)
+}
+
+/// Marker of the parameter of a `#[helper]` function at an index, naming it
+fn helper_arg_marker(name: &str, index: usize, key: &str) -> syn::Ident {
+    format_ident!("__dry_handlebars_helper_{}_arg{}_{}", name, index, key)
+}
+
+/// Markers checking that each hash argument given to a helper function names the parameter
+/// it is passed as, the function must be annotated with `#[dry_handlebars::helper]`
+fn hash_arg_markers(rust_code: &Rust, span: proc_macro2::Span) -> syn::Result<Vec<syn::Path>> {
+    rust_code
+        .hash_args()
+        .iter()
+        .map(|(path, index, key)| {
+            let invalid =
+                || syn::Error::new(span, format!("{}= can't name a parameter of {}", key, path));
+            let mut marker: syn::Path = syn::parse_str(path).map_err(|_| invalid())?;
+            let key = syn::parse_str::<syn::Ident>(key)
+                .map_err(|_| invalid())?
+                .unraw()
+                .to_string();
+            // a misnamed argument is reported at the template
+            let last = marker.segments.last_mut().unwrap();
+            let ident = helper_arg_marker(&last.ident.unraw().to_string(), *index, &key);
+            last.ident = syn::Ident::new(&ident.to_string(), span);
+            Ok(marker)
+        })
+        .collect()
+}
+
+#[proc_macro_attribute]
+pub fn dry_handlebars_helper(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            proc_macro2::TokenStream::from(attr)
+                .into_iter()
+                .next()
+                .unwrap()
+                .span(),
+            "helper does not take arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let function = parse_macro_input!(item as syn::ItemFn);
+    if let Some(receiver) = function.sig.receiver() {
+        return syn::Error::new_spanned(receiver, "helpers must be free functions")
+            .to_compile_error()
+            .into();
+    }
+    let vis = &function.vis;
+    let name = function.sig.ident.unraw().to_string();
+    let marker = helper_marker(&name);
+    // a marker per named parameter, which hash arguments are checked against
+    let arg_markers =
+        function
+            .sig
+            .inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, input)| match input {
+                syn::FnArg::Typed(syn::PatType { pat, .. }) => match pat.as_ref() {
+                    syn::Pat::Ident(ident) => Some(helper_arg_marker(
+                        &name,
+                        index,
+                        &ident.ident.unraw().to_string(),
+                    )),
+                    _ => None,
+                },
+                syn::FnArg::Receiver(_) => None,
+            });
+
+    let expanded = quote! {
+        #function
+
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        #vis const #marker: () = ();
+        #(
+            #[doc(hidden)]
+            #[allow(non_upper_case_globals)]
+            #vis const #arg_markers: () = ();
+        )*
+    };
+
+    TokenStream::from(expanded)
+}
+
+/// Candidate locations for a template path, in order of precedence: an absolute path as is,
+/// then relative to `DRY_HBS_TEMPLATE_ROOT`, the configured template roots, the crate's
+/// manifest dir and the workspace root
+fn template_path_candidates(path: &str, roots: &[PathBuf]) -> Vec<PathBuf> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return vec![path.to_path_buf()];
+    }
+    let mut candidates = Vec::new();
+    if let Ok(root) = std::env::var("DRY_HBS_TEMPLATE_ROOT") {
+        candidates.push(Path::new(&root).join(path));
+    }
+    candidates.extend(roots.iter().map(|root| root.join(path)));
+    if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
+        let manifest_dir = Path::new(&manifest_dir);
+        candidates.push(manifest_dir.join(path));
+        let workspace_root = manifest_dir.ancestors().skip(1).find(|dir| {
+            fs::read_to_string(dir.join("Cargo.toml"))
+                .is_ok_and(|manifest| manifest.lines().any(|line| line.trim() == "[workspace]"))
+        });
+        if let Some(workspace_root) = workspace_root {
+            candidates.push(workspace_root.join(path));
+        }
+    }
+    candidates
+}
+
+/// Resolves a template path to the first candidate that exists, or an error listing the paths tried
+fn resolve_template_path(lit: &LitStr, kind: &str, config: &Config) -> syn::Result<PathBuf> {
+    let candidates = template_path_candidates(&lit.value(), &config.template_roots);
+    match candidates.iter().find(|candidate| candidate.exists()) {
+        Some(found) => Ok(found.clone()),
+        None => Err(syn::Error::new(
+            lit.span(),
+            format!(
+                "{} not found: {:?}, tried: {}",
+                kind,
+                lit.value(),
+                candidates
+                    .iter()
+                    .map(|candidate| format!("{:?}", candidate))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )),
+    }
+}
+
+/// Returns an error for the first argument only `directory!` supports
+fn directory_only(args: &TemplateArgs) -> Option<syn::Error> {
+    let given = [
+        (args.render_all, "render_all_with_defaults"),
+        (args.catalog, "catalog"),
+        (args.prefix.as_ref().map(LitStr::span), "prefix"),
+        (args.name_by_path, "name_by_path"),
+        (args.templates_enum, "templates_enum"),
+        (args.exclude.first().map(LitStr::span), "exclude"),
+        (args.extensions.first().map(LitStr::span), "extensions"),
+    ];
+    let (span, name) = given
+        .into_iter()
+        .find_map(|(span, name)| Some((span?, name)))?;
+    Some(syn::Error::new(
+        span,
+        format!("{} is only supported by directory!", name),
+    ))
+}
 
-    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
-    let root_path = Path::new(&manifest_dir).join(&dir_str);
+/// Reads the crate's `dry-handlebars.toml`, an invalid one is reported at the macro call
+fn load_config() -> syn::Result<Config> {
+    Config::load().map_err(|message| syn::Error::new(proc_macro2::Span::call_site(), message))
+}
 
-    if !root_path.exists() {
+#[proc_macro]
+pub fn dry_handlebars_directory(input: TokenStream) -> TokenStream {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let PathInput {
+        path: dir_lit,
+        mut args,
+    } = match (|input: ParseStream| PathInput::parse(input, &config)).parse(input) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    if let Some(rename) = &args.rename {
         return syn::Error::new(
-            dir_lit.span(),
-            format!("Directory not found: {:?}", root_path),
+            rename.span(),
+            "as is only supported by file!, use prefix or name_by_path",
         )
         .to_compile_error()
         .into();
     }
+    // a pattern walks the directory before its first wildcard
+    let (dir_lit, pattern) = match glob::split(&dir_lit.value()) {
+        Some((root, pattern)) => match glob::compile(&pattern) {
+            Ok(pattern) => {
+                let root = if root.is_empty() { "." } else { &root };
+                (LitStr::new(root, dir_lit.span()), Some(pattern))
+            }
+            Err(message) => {
+                return syn::Error::new(dir_lit.span(), message)
+                    .to_compile_error()
+                    .into();
+            }
+        },
+        None => (dir_lit, None),
+    };
+    let extensions: Vec<String> = match args.extensions.is_empty() {
+        true => vec!["hbs".to_string()],
+        false => args.extensions.iter().map(LitStr::value).collect(),
+    };
+    let exclude = match args
+        .exclude
+        .iter()
+        .map(|pattern| {
+            glob::compile(&pattern.value())
+                .map_err(|message| syn::Error::new(pattern.span(), message))
+        })
+        .collect::<syn::Result<Vec<_>>>()
+    {
+        Ok(exclude) => exclude,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let root_path = match resolve_template_path(&dir_lit, "Directory", &config) {
+        Ok(path) => path,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    // files starting with `_` are partials any template of the directory includes, read once
+    let registry = match partials::registry(&root_path, &extensions) {
+        Ok(registry) => registry,
+        Err(message) => {
+            return syn::Error::new(dir_lit.span(), message)
+                .to_compile_error()
+                .into();
+        }
+    };
+    args.partials = std::rc::Rc::new(registry);
 
-    let mut structs = Vec::new();
-    let mut functions = Vec::new();
+    // the items of each module by its path below the root, a module per subdirectory
+    let mut modules: BTreeMap<Vec<String>, Vec<proc_macro2::TokenStream>> = BTreeMap::new();
+    let mut generated_names: HashMap<(Vec<String>, String), PathBuf> = HashMap::new();
+    // variant, name and type of each template in `Templates`
+    let mut variants = Vec::new();
+    let mut fixtures = Vec::new();
+    let mut fixture_bounds = Vec::new();
+    let mut smoke_renders = Vec::new();
+    let mut catalog_entries = Vec::new();
+    // mappings are shared by the templates, those none of them reads are reported
+    let mut unused: Option<HashSet<String>> = None;
+    // templates declaring themselves variants, by experiment
+    let mut experiments: BTreeMap<String, Vec<ExperimentVariant>> = BTreeMap::new();
+    // failures are collected so one bad file doesn't hide the rest of the directory
+    let mut errors: Option<syn::Error> = None;
+    let mut report = |err: syn::Error| match errors.as_mut() {
+        Some(errors) => errors.combine(err),
+        None => errors = Some(err),
+    };
 
     for entry in WalkDir::new(&root_path) {
         let entry = match entry {
             Ok(e) => e,
-            Err(_) => continue,
+            Err(err) => {
+                report(syn::Error::new(
+                    dir_lit.span(),
+                    format!("Failed to read template directory entry: {}", err),
+                ));
+                continue;
+            }
         };
 
         let path = entry.path();
-        if path.is_file() && path.extension().is_some_and(|ext| ext == "hbs") {
-            let (struct_def, function_def) = generate_code_for_file(path);
-            structs.push(struct_def);
-            functions.push(function_def);
+        if entry.file_type().is_dir() {
+            track_directory(path);
+        }
+        let relative = path.strip_prefix(&root_path).unwrap_or(path);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let selected = match &pattern {
+            Some(pattern) => glob::matches(pattern, relative),
+            None => extensions.iter().any(|extension| {
+                file_name
+                    .strip_suffix(extension.as_str())
+                    .and_then(|stem| stem.strip_suffix('.'))
+                    .is_some_and(|stem| !stem.is_empty())
+            }),
+        };
+        if path.is_file()
+            && selected
+            && !file_name.starts_with('_')
+            && !exclude
+                .iter()
+                .any(|pattern| glob::matches(pattern, relative))
+        {
+            let module = match args.name_by_path {
+                Some(_) => Vec::new(),
+                None => match module_path(&root_path, path) {
+                    Ok(module) => module,
+                    Err(message) => {
+                        report(syn::Error::new(dir_lit.span(), message));
+                        continue;
+                    }
+                },
+            };
+            // the name ends at the first dot, the extensions after it select the output mode
+            let (stem, compound) = file_name.split_once('.').unwrap_or((&file_name, ""));
+            let text = compound
+                .split('.')
+                .any(|extension| extension == "txt" || extension == "text");
+            let name = match args.name_by_path {
+                Some(_) => relative
+                    .parent()
+                    .into_iter()
+                    .flat_map(Path::components)
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .chain([stem.into()])
+                    .collect::<Vec<_>>()
+                    .join("_"),
+                None => stem.to_string(),
+            };
+            let name = match &args.prefix {
+                Some(prefix) => format!("{}{}", prefix.value(), name),
+                None => name,
+            };
+            // `user-card.hbs` and `user_card.hbs` would both generate `user_card`
+            let function = to_snake_case(&name.replace('-', "_"));
+            match generated_names.entry((module.clone(), function)) {
+                Entry::Occupied(first) => {
+                    report(syn::Error::new(
+                        dir_lit.span(),
+                        format!(
+                            "Templates {:?} and {:?} both generate `{}`, rename one of them",
+                            first.get(),
+                            relative,
+                            module
+                                .iter()
+                                .chain([&first.key().1])
+                                .cloned()
+                                .collect::<Vec<_>>()
+                                .join("::")
+                        ),
+                    ));
+                    continue;
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(relative.to_path_buf());
+                }
+            }
+            let generated = match text {
+                true => {
+                    // text isn't HTML, it's written unescaped and as is
+                    let mut args = args.clone();
+                    args.config.escape = false;
+                    args.config.minify = false;
+                    args.csp_nonce = false;
+                    args.text = true;
+                    generate_code_for_file(&dir_lit, path, &name, &args)
+                }
+                false => generate_code_for_file(&dir_lit, path, &name, &args),
+            };
+            match generated {
+                Ok(generated) => {
+                    unused = Some(match unused.take() {
+                        Some(unused) => unused
+                            .intersection(&generated.unused_mappings)
+                            .cloned()
+                            .collect(),
+                        None => generated.unused_mappings.clone(),
+                    });
+                    let module_idents = module.iter().map(|module| format_ident!("{}", module));
+                    let struct_name = &generated.struct_name;
+                    let struct_name = quote! { #(#module_idents::)* #struct_name };
+                    if args.templates_enum.is_some()
+                        && let Some(erased_params) = &generated.erased_params
+                    {
+                        let path = module
+                            .iter()
+                            .cloned()
+                            .chain([to_snake_case(&generated.struct_name.to_string())])
+                            .collect::<Vec<_>>();
+                        let variant = format_ident!("{}", path.join("_"));
+                        variants.push((
+                            variant,
+                            path.join("/"),
+                            quote! { #struct_name<#(#erased_params),*> },
+                        ));
+                    }
+                    if generated.fixture {
+                        let name = module
+                            .iter()
+                            .cloned()
+                            .chain([generated.struct_name.to_string()])
+                            .collect::<Vec<_>>()
+                            .join("::");
+                        fixtures.push(quote! { (#name, #struct_name::fixture().render()) });
+                        smoke_renders.push(quote! {
+                            if ::std::panic::catch_unwind(|| #struct_name::fixture().render())
+                                .is_err()
+                            {
+                                panicked.push(#name);
+                            }
+                        });
+                        fixture_bounds.extend(generated.fixture_bounds);
+                    }
+                    if generated.fixture && args.catalog.is_some() {
+                        let name = module
+                            .iter()
+                            .cloned()
+                            .chain([to_snake_case(&generated.struct_name.to_string())])
+                            .collect::<Vec<_>>()
+                            .join("/");
+                        let source_path = path.to_string_lossy();
+                        let mime_type = match text {
+                            true => "text/plain; charset=utf-8",
+                            false => "text/html; charset=utf-8",
+                        };
+                        catalog_entries.push(quote! {
+                            ::dry_handlebars::CatalogEntry {
+                                name: #name,
+                                source_path: #source_path,
+                                mime_type: #mime_type,
+                                preview: #struct_name::preview,
+                            }
+                        });
+                    }
+                    if let Some((variant, experiment)) = generated.variant {
+                        experiments
+                            .entry(experiment)
+                            .or_default()
+                            .push(ExperimentVariant {
+                                variant,
+                                module: module.clone(),
+                                struct_name: generated.struct_name.clone(),
+                                context: generated.context,
+                            });
+                    }
+                    let items = modules.entry(module).or_default();
+                    items.push(generated.struct_def);
+                    items.push(generated.function_def);
+                }
+                Err(err) => report(err),
+            }
+        }
+    }
+
+    for (experiment, variants) in &mut experiments {
+        // files are walked in no particular order
+        variants.sort_by(|a, b| a.variant.cmp(&b.variant));
+        if let Some(template) =
+            generated_names.get(&(variants[0].module.clone(), experiment.clone()))
+        {
+            report(syn::Error::new(
+                dir_lit.span(),
+                format!(
+                    "experiment \"{}\" has the name of the template {:?}, name them apart",
+                    experiment, template
+                ),
+            ));
+            continue;
+        }
+        match experiment_module(experiment, variants, &args.vis, dir_lit.span()) {
+            Ok(module) => modules
+                .entry(variants[0].module.clone())
+                .or_default()
+                .push(module),
+            Err(err) => report(err),
         }
     }
 
+    let unused_warnings = match unused_mappings(
+        &args,
+        &unused.unwrap_or_default(),
+        "any template of the directory",
+    ) {
+        Ok(warnings) => warnings,
+        Err(err) => {
+            report(err);
+            quote! {}
+        }
+    };
+
+    let errors = errors.map(|errors| errors.to_compile_error());
+    let vis = &args.vis;
+    let items = nest_modules(&modules, &[], vis);
+    let fixtures_fn = if cfg!(feature = "test-fixtures") {
+        quote! {
+            /// Renders every template of the directory built with placeholder values, by name,
+            /// callable once the mapped types implement `dry_handlebars::Fixture`
+            #vis fn fixtures() -> Vec<(&'static str, String)>
+            where
+                #(for<'fixture> #fixture_bounds: ::dry_handlebars::Fixture,)*
+            {
+                vec![#(#fixtures),*]
+            }
+        }
+    } else {
+        quote! {}
+    };
+    let catalog_fn = match args.catalog {
+        Some(_) => quote! {
+            /// Every template of the directory that can be built from fixtures, by name, for a
+            /// style guide listing and rendering them, see `dry_handlebars::catalog_index`
+            #vis fn catalog() -> Vec<::dry_handlebars::CatalogEntry>
+            where
+                #(for<'fixture> #fixture_bounds: ::dry_handlebars::Fixture,)*
+            {
+                vec![#(#catalog_entries),*]
+            }
+        },
+        None => quote! {},
+    };
+    let render_all_fn = match args.render_all {
+        Some(_) => quote! {
+            /// Renders every template of the directory built with placeholder values, failing
+            /// with the names of the templates that panicked
+            #[cfg(test)]
+            #[test]
+            fn render_all_with_defaults() {
+                let mut panicked: Vec<&'static str> = Vec::new();
+                #(#smoke_renders)*
+                assert!(
+                    panicked.is_empty(),
+                    "templates panicked while rendering: {:?}",
+                    panicked
+                );
+            }
+        },
+        None => quote! {},
+    };
+    let templates_enum = match args.templates_enum {
+        Some(_) => {
+            let variant = variants
+                .iter()
+                .map(|(variant, ..)| variant)
+                .collect::<Vec<_>>();
+            let names = variants.iter().map(|(_, name, _)| name).collect::<Vec<_>>();
+            let types = variants.iter().map(|(.., ty)| ty).collect::<Vec<_>>();
+            quote! {
+                /// Every template of the directory with its data, for templates chosen at
+                /// runtime such as from a route or a database column
+                #[allow(non_camel_case_types)]
+                #vis enum Templates {
+                    #(#variant(#types),)*
+                }
+
+                impl Templates {
+                    /// Names of the templates, their path in the directory without extension
+                    #vis const NAMES: &'static [&'static str] = &[#(#names),*];
+
+                    /// Returns the name of the template
+                    #vis fn name(&self) -> &'static str {
+                        match self {
+                            #(Self::#variant(_) => #names,)*
+                        }
+                    }
+
+                    /// Renders the template
+                    #vis fn render(&self) -> String {
+                        match self {
+                            #(Self::#variant(template) => template.render(),)*
+                        }
+                    }
+                }
+
+                #(
+                    impl From<#types> for Templates {
+                        fn from(template: #types) -> Self {
+                            Self::#variant(template)
+                        }
+                    }
+                )*
+
+                /// Renders the template with the given name from its data, a template struct
+                /// with its generic fields boxed, `None` when there is no such template or the
+                /// data is of another type
+                #vis fn render_by_name(name: &str, data: &dyn ::std::any::Any) -> Option<String> {
+                    match name {
+                        #(#names => data.downcast_ref::<#types>().map(|template| template.render()),)*
+                        _ => None,
+                    }
+                }
+            }
+        }
+        None => quote! {},
+    };
     let expanded = quote! {
-        #(#structs)*
-        #(#functions)*
+        #items
+        #templates_enum
+        #fixtures_fn
+        #catalog_fn
+        #render_all_fn
+        #unused_warnings
+        #errors
     };
 
     TokenStream::from(expanded)
 }
 
-#[proc_macro]
-pub fn dry_handlebars_file(input: TokenStream) -> TokenStream {
-    let file_lit = parse_macro_input!(input as LitStr);
-    let file_str = file_lit.value();
+/// Returns the modules of a template below the root of `directory!`, one per subdirectory
+fn module_path(root_path: &Path, path: &Path) -> Result<Vec<String>, String> {
+    let relative = path.strip_prefix(root_path).unwrap_or(path);
+    relative
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+        .map(|component| {
+            let name = to_snake_case(&component.as_os_str().to_string_lossy()).replace('-', "_");
+            match syn::parse_str::<syn::Ident>(&name) {
+                Ok(_) => Ok(name),
+                Err(_) => Err(format!(
+                    "Template directory {:?} is not a valid module name",
+                    component.as_os_str()
+                )),
+            }
+        })
+        .collect()
+}
 
-    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
-    let path = Path::new(&manifest_dir).join(&file_str);
+/// Emits the items of a module and the modules below it
+fn nest_modules(
+    modules: &BTreeMap<Vec<String>, Vec<proc_macro2::TokenStream>>,
+    path: &[String],
+    vis: &syn::Visibility,
+) -> proc_macro2::TokenStream {
+    let items = modules.get(path).into_iter().flatten();
+    let mut children: Vec<&String> = modules
+        .keys()
+        .filter(|key| key.len() > path.len() && key.starts_with(path))
+        .map(|key| &key[path.len()])
+        .collect();
+    children.dedup();
+    let children = children.into_iter().map(|child| {
+        let child_path = [path, std::slice::from_ref(child)].concat();
+        let inner = nest_modules(modules, &child_path, vis);
+        let child = format_ident!("{}", child);
+        quote! {
+            #vis mod #child {
+                // mapped types and helpers in scope at the macro call stay in scope
+                #[allow(unused_imports)]
+                use super::*;
+                #inner
+            }
+        }
+    });
+    quote! {
+        #(#items)*
+        #(#children)*
+    }
+}
 
-    if !path.exists() {
-        return syn::Error::new(file_lit.span(), format!("File not found: {:?}", path))
-            .to_compile_error()
-            .into();
+#[proc_macro_derive(Template, attributes(template))]
+pub fn dry_handlebars_derive_template(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as syn::DeriveInput);
+    derive::template(input)
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+#[proc_macro]
+pub fn dry_handlebars_file(input: TokenStream) -> TokenStream {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let PathInput {
+        path: file_lit,
+        args,
+    } = match (|input: ParseStream| PathInput::parse(input, &config)).parse(input) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    if let Some(err) = directory_only(&args) {
+        return err.to_compile_error().into();
     }
+    let path = match resolve_template_path(&file_lit, "File", &config) {
+        Ok(path) => path,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
-    let (struct_def, function_def) = generate_code_for_file(&path);
+    let Generated {
+        struct_def,
+        function_def,
+        unused_mappings: unused,
+        ..
+    } = match generate_code_for_file(
+        &file_lit,
+        &path,
+        &args.rename.as_ref().map_or_else(
+            || path.file_stem().unwrap().to_string_lossy().to_string(),
+            LitStr::value,
+        ),
+        &args,
+    ) {
+        Ok(code) => code,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let warnings = match unused_mappings(&args, &unused, "the template") {
+        Ok(warnings) => warnings,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     let expanded = quote! {
         #struct_def
         #function_def
+        #warnings
     };
 
     TokenStream::from(expanded)
@@ -344,19 +3436,321 @@ pub fn dry_handlebars_file(input: TokenStream) -> TokenStream {
 
 #[proc_macro]
 pub fn dry_handlebars_str(input: TokenStream) -> TokenStream {
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(err) => return err.to_compile_error().into(),
+    };
     let StrInput {
         name,
         content,
-        mappings,
-    } = parse_macro_input!(input as StrInput);
-    let mappings_map: HashMap<String, syn::Type> = mappings.into_iter().collect();
-    let (struct_def, function_def) =
-        generate_code_for_content(&name.value(), &content.value(), None, mappings_map);
+        args,
+    } = match (|input: ParseStream| StrInput::parse(input, &config)).parse(input) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    if let Some(rename) = &args.rename {
+        return syn::Error::new(
+            rename.span(),
+            "as is only supported by file!, str! is given its name",
+        )
+        .to_compile_error()
+        .into();
+    }
+    if let Some(err) = directory_only(&args) {
+        return err.to_compile_error().into();
+    }
+    let Generated {
+        struct_def,
+        function_def,
+        unused_mappings: unused,
+        ..
+    } = match generate_code_for_content(
+        &name.value(),
+        &content.value(),
+        None,
+        Some(&content),
+        &args,
+        content.span(),
+    ) {
+        Ok(code) => code,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let warnings = match unused_mappings(&args, &unused, "the template") {
+        Ok(warnings) => warnings,
+        Err(err) => return err.to_compile_error().into(),
+    };
 
     let expanded = quote! {
         #struct_def
         #function_def
+        #warnings
     };
 
     TokenStream::from(expanded)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dry_handlebars_parser::compiler::BlockMap;
+
+    #[test]
+    fn content_hash_is_fnv1a() {
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(content_hash("<p>{{title}}</p>"), 0xdf47_7fbe_3865_aac6);
+    }
+
+    fn mapped(mappings: &[(&str, syn::Type)]) -> TemplateArgs {
+        let mut args = TemplateArgs::from(&Config::default());
+        args.mappings = mappings
+            .iter()
+            .map(|(name, ty)| (name.to_string(), ty.clone()))
+            .collect();
+        args
+    }
+
+    #[test]
+    fn unused_mapping_warning() {
+        let args = mapped(&[
+            ("author", syn::parse_quote! { Option<Author> }),
+            ("title", syn::parse_quote! { String }),
+        ]);
+        let unused = HashSet::from(["author".to_string()]);
+        let warnings = unused_mappings(&args, &unused, "the template")
+            .unwrap()
+            .to_string();
+        assert!(warnings.contains("deprecated"));
+        assert!(warnings.contains("`author` is mapped but never read by the template"));
+        assert!(!warnings.contains("title"));
+        assert!(
+            unused_mappings(&args, &HashSet::new(), "the template")
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut strict = args.clone();
+        strict.config.strict = true;
+        let error = unused_mappings(&strict, &unused, "any template").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "dry-handlebars: `author` is mapped but never read by any template, remove the mapping"
+        );
+    }
+
+    #[test]
+    fn mismatched_mapping_error() {
+        use dry_handlebars_parser::compiler::{UsageKind, VariableReport};
+        let report = |path: &str, kind| VariableReport {
+            path: path.to_string(),
+            kinds: vec![kind],
+        };
+        let args = mapped(&[
+            ("items", syn::parse_quote! { String }),
+            ("user", syn::parse_quote! { &str }),
+            ("count", syn::parse_quote! { u32 }),
+            ("rows", syn::parse_quote! { Vec<String> }),
+        ]);
+        let errors = mismatched_mappings(
+            &args,
+            &[
+                report("items", UsageKind::Iterable),
+                report("user.name", UsageKind::Display),
+                report("count", UsageKind::Display),
+                report("rows", UsageKind::Iterable),
+            ],
+        )
+        .unwrap_err()
+        .into_iter()
+        .map(|error| error.to_string())
+        .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "dry-handlebars: `items` is mapped to `String` but iterated by each, map it to a collection, e.g. (\"items\", Vec<String>)",
+                "dry-handlebars: `user` is mapped to `& str` but the template reads `user.name`, map it to a struct with that field",
+            ]
+        );
+        assert!(mismatched_mappings(&args, &[report("count", UsageKind::Display)]).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "pretty")]
+    fn pretty_render_code() {
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let rust = Compiler::new(Options::default(), block_map)
+            .compile("<ul>{{#each items}}<li>{{#if this}}'{{this}}'{{else}}{{this}}{{/if}}</li>{{/each}}</ul>")
+            .unwrap();
+        assert!(!rust.code().contains('\n'));
+        let pretty = pretty(&rust, proc_macro2::Span::call_site()).unwrap();
+        let lines: Vec<&str> = pretty.lines().collect();
+        assert_eq!(
+            lines[0],
+            "fn render(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {"
+        );
+        assert!(lines.contains(&"    write!(f, \"<ul>\")?;"));
+        assert!(
+            lines
+                .iter()
+                .any(|line| line.starts_with("    for ") && line.ends_with(" {"))
+        );
+        assert!(lines.contains(&"        write!(f, \"<li>\")?;"));
+        assert!(lines.contains(&"        } else {"));
+        assert_eq!(lines[lines.len() - 1], "}");
+    }
+
+    #[test]
+    #[cfg(not(feature = "pretty"))]
+    fn pretty_needs_feature() {
+        let rust = Compiler::new(Options::default(), BlockMap::new())
+            .compile("<p>hi</p>")
+            .unwrap();
+        assert_eq!(
+            pretty(&rust, proc_macro2::Span::call_site())
+                .unwrap_err()
+                .to_string(),
+            "`pretty = true` needs the `pretty` feature of dry-handlebars"
+        );
+    }
+
+    #[test]
+    fn verbose_decisions() {
+        let notes = |verbose: bool| {
+            let mut args = mapped(&[("title", syn::parse_quote! { String })]);
+            args.verbose = verbose;
+            let generated = generate_code_for_content(
+                "card",
+                "<h1>{{title}}</h1>{{#if published}}{{author.name}}{{/if}}{{count}}",
+                None,
+                None,
+                &args,
+                proc_macro2::Span::call_site(),
+            )
+            .unwrap();
+            generated
+                .struct_def
+                .to_string()
+                .split("note = \"")
+                .skip(1)
+                .map(|note| note.split('"').next().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            notes(true),
+            [
+                "dry-handlebars: card: `author.name` is flattened to the variable `author_name` as `author` has no mapping",
+                "dry-handlebars: card: `title`: String, mapped in the macro arguments",
+                "dry-handlebars: card: `published`: bool, used as the condition of if, unless or class",
+                "dry-handlebars: card: `count`: generic T0: Display, it has no mapping and isn't used as a condition",
+                "dry-handlebars: card: `author_name`: generic T1: Display, it has no mapping and isn't used as a condition",
+                "dry-handlebars: card: {{ }} output is HTML-escaped, {{{ }}} and safe helpers are written as is",
+            ]
+        );
+        assert!(notes(false).is_empty());
+    }
+
+    #[test]
+    fn template_docs() {
+        assert_eq!(
+            template_doc("{{!-- @doc Renders the page header --}}<h1>{{title}}</h1>").unwrap(),
+            ["Renders the page header"]
+        );
+        assert_eq!(
+            template_doc("{{! @doc Renders the footer }}<footer></footer>").unwrap(),
+            ["Renders the footer"]
+        );
+        assert_eq!(
+            template_doc("{{!-- @doc Renders {{title}} in a header\n  with `{{name}}`\n    indented --}}<h1>{{title}}</h1>")
+                .unwrap(),
+            ["Renders {{title}} in a header", "with `{{name}}`", "  indented"]
+        );
+        assert_eq!(
+            template_doc("\n{{!--\n  @doc\n  Renders the page\n--}}").unwrap(),
+            ["Renders the page"]
+        );
+        assert_eq!(template_doc("<h1>{{title}}</h1>{{!-- @doc late --}}"), None);
+        assert_eq!(template_doc("{{!-- a comment --}}"), None);
+    }
+
+    #[test]
+    fn literal_offsets() {
+        let text = r#""<p>{{a}}</p>""#;
+        assert_eq!(literal_offset(text, 0), Some(1));
+        assert_eq!(literal_offset(text, 3), Some(4));
+        assert_eq!(literal_offset(text, 12), Some(13));
+        assert_eq!(literal_offset(text, 13), None);
+        // escapes are one character of the value
+        let text = r#""a\"b\n{{c}}\u{e9}{{d}}\x41""#;
+        assert_eq!(&text[literal_offset(text, 4).unwrap()..][..5], "{{c}}");
+        assert_eq!(&text[literal_offset(text, 11).unwrap()..][..5], "{{d}}");
+        assert_eq!(literal_offset(text, 17), Some(text.len() - 1));
+        // a line continuation is no part of the value
+        let text = "\"<p>\\\n    {{a}}</p>\"";
+        assert_eq!(&text[literal_offset(text, 3).unwrap()..][..5], "{{a}}");
+        let text = r###"r#"<p>"{{a}}"</p>"#"###;
+        assert_eq!(&text[literal_offset(text, 4).unwrap()..][..5], "{{a}}");
+        assert_eq!(literal_offset(text, 14), Some(text.len() - 2));
+        assert_eq!(literal_offset(text, 15), None);
+    }
+
+    #[test]
+    fn literal_template_error() {
+        let literal: LitStr = syn::parse_quote! { "<p>\n{{#each}}</p>" };
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let source = literal.value();
+        let error = Compiler::new(Options::default(), block_map)
+            .compile(&source)
+            .unwrap_err();
+        let message = error.to_string();
+        let error = template_error(
+            error,
+            &source,
+            &source,
+            None,
+            Some(&literal),
+            literal.span(),
+        );
+        // outside a nightly compiler the literal can't be split and the position is named
+        match literal.token().subspan(1..2) {
+            Some(_) => assert_eq!(error.to_string(), message),
+            None => assert_eq!(
+                error.to_string(),
+                format!("line 2, column 1 of the template: {}", message)
+            ),
+        }
+    }
+
+    #[test]
+    fn hash_arg_marker_paths() {
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let options = Options::default().helpers(HashMap::from([(
+            "toggle".to_string(),
+            "crate :: helpers :: toggle".to_string(),
+        )]));
+        let compiler = Compiler::new(options, block_map);
+        let span = proc_macro2::Span::call_site();
+        let rust = compiler
+            .compile("{{toggle label expanded=false}}{{greet r#type=null}}")
+            .unwrap();
+        let markers = hash_arg_markers(&rust, span)
+            .unwrap()
+            .into_iter()
+            .map(|marker| quote! { #marker }.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            markers,
+            [
+                "crate :: helpers :: __dry_handlebars_helper_toggle_arg1_expanded",
+                "__dry_handlebars_helper_greet_arg0_type",
+            ]
+        );
+        let rust = compiler.compile("{{toggle data-id=1}}").unwrap();
+        assert_eq!(
+            hash_arg_markers(&rust, span).err().unwrap().to_string(),
+            "data-id= can't name a parameter of crate :: helpers :: toggle"
+        );
+    }
+}
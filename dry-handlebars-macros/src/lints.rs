@@ -0,0 +1,383 @@
+//! Lints run over a template's source, reported as compiler warnings
+//!
+//! Stable proc macros can't emit warnings directly, so each one is reported through the
+//! use of a deprecated item whose note carries the message.
+
+use dry_handlebars_parser::expression::{Expression, ExpressionType};
+use quote::quote_spanned;
+use regex::Regex;
+
+/// Marks an interpolated value inside static text
+const VALUE: char = '\u{1}';
+/// Marks an interpolated value using `@index`
+const INDEXED_VALUE: char = '\u{2}';
+
+/// Returns a warning for the given message, pointing at the span
+pub fn warning(message: &str, span: proc_macro2::Span) -> proc_macro2::TokenStream {
+    let note = format!("dry-handlebars: {}", message);
+    quote_spanned! {span=>
+        const _: () = {
+            #[deprecated(note = #note)]
+            struct DryHandlebarsWarning;
+            let _ = DryHandlebarsWarning;
+        };
+    }
+}
+
+/// Checks the `id` attributes of a template's static HTML: the same id given twice, a static
+/// id inside `each` and an id interpolated inside `each` without `@index` all end up as
+/// duplicate ids in the rendered page. Ids in different arms of the same block, such as the
+/// two branches of an `{{#if}}..{{else}}`, never render together and aren't compared
+pub fn duplicate_ids(src: &str) -> Vec<String> {
+    // static text with interpolated values replaced by markers, plus the open blocks at
+    // every change of block
+    let mut skeleton = String::with_capacity(src.len());
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut changes = vec![(0, Vec::new())];
+    let mut opened = 0;
+    let mut expression = Expression::from(src).ok().flatten();
+    let mut rest = src;
+    while let Some(expr) = expression {
+        skeleton.push_str(expr.prefix);
+        rest = expr.postfix;
+        let first = expr.content.split_whitespace().next().unwrap_or("");
+        match expr.expression_type {
+            ExpressionType::HtmlEscaped | ExpressionType::Raw if first == "else" => {
+                if let Some(block) = blocks.last_mut() {
+                    block.arm += 1;
+                }
+            }
+            ExpressionType::HtmlEscaped | ExpressionType::Raw
+                if expr.content.contains("@index") =>
+            {
+                skeleton.push(INDEXED_VALUE)
+            }
+            ExpressionType::HtmlEscaped | ExpressionType::Raw => skeleton.push(VALUE),
+            ExpressionType::Escaped => skeleton.push_str(expr.content),
+            ExpressionType::Open => {
+                opened += 1;
+                blocks.push(Block {
+                    number: opened,
+                    arm: 0,
+                    each: first == "each",
+                });
+            }
+            ExpressionType::Close => {
+                blocks.pop();
+            }
+            _ => (),
+        }
+        if changes.last().is_none_or(|(_, last)| *last != blocks) {
+            changes.push((skeleton.len(), blocks.clone()));
+        }
+        expression = expr.next().ok().flatten();
+    }
+    skeleton.push_str(rest);
+
+    let blocks_at = |pos: usize| {
+        changes
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= pos)
+            .map_or(&[][..], |(_, blocks)| blocks.as_slice())
+    };
+    let id = Regex::new(r#"\sid\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let mut seen: Vec<(&str, &[Block])> = Vec::new();
+    let mut warnings = Vec::new();
+    for captures in id.captures_iter(&skeleton) {
+        let value = captures.get(1).or_else(|| captures.get(2)).unwrap();
+        let blocks = blocks_at(value.start());
+        // only the each arm of an each repeats, its else renders once
+        let in_each = blocks.iter().any(|block| block.each && block.arm == 0);
+        let text = value.as_str();
+        if text.contains(INDEXED_VALUE) {
+            continue;
+        }
+        if text.contains(VALUE) {
+            if in_each {
+                warnings.push(format!(
+                    "id \"{}\" is interpolated inside each without @index",
+                    text.replace(VALUE, "{{..}}")
+                ));
+            }
+            continue;
+        }
+        if in_each {
+            warnings.push(format!("id \"{}\" is repeated by each", text));
+        } else {
+            if seen
+                .iter()
+                .any(|(other, others)| *other == text && !exclusive(blocks, others))
+            {
+                warnings.push(format!("duplicate id \"{}\"", text));
+            }
+            seen.push((text, blocks));
+        }
+    }
+    warnings
+}
+
+/// A block open around a position of a template, numbered in the order blocks are opened, with
+/// the arm the position is in: 0 before the first `{{else}}`, 1 after it and so on
+#[derive(Clone, PartialEq)]
+struct Block {
+    number: usize,
+    arm: usize,
+    each: bool,
+}
+
+/// Whether two positions are in different arms of the same block, so only one renders
+fn exclusive(first: &[Block], second: &[Block]) -> bool {
+    first.iter().any(|block| {
+        second
+            .iter()
+            .any(|other| other.number == block.number && other.arm != block.arm)
+    })
+}
+
+/// Returns the line and column, from 1, at which a slice of a source starts
+pub fn line_column(src: &str, slice: &str) -> (usize, usize) {
+    let before = &src[..slice.as_ptr() as usize - src.as_ptr() as usize];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |pos| pos + 1) + 1;
+    (line, column)
+}
+
+/// Returns the names declared by `as name` or `as |key value|` in a block's content
+fn declared_locals(content: &str) -> Vec<&str> {
+    let Some((_, locals)) = content.rsplit_once(" as ") else {
+        return Vec::new();
+    };
+    let locals = locals.trim();
+    match locals
+        .strip_prefix('|')
+        .and_then(|rest| rest.strip_suffix('|'))
+    {
+        Some(names) => names.split_whitespace().collect(),
+        None => vec![locals],
+    }
+}
+
+/// Returns the variables read by an expression: its only token, or the arguments following
+/// a helper or block name, `key=value` arguments by their value
+fn read_variables(content: &str, open: bool) -> Vec<&str> {
+    let content = content.split(" as ").next().unwrap_or(content);
+    let tokens = content
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| !token.is_empty())
+        .collect::<Vec<_>>();
+    let skip = usize::from(open || tokens.len() > 1);
+    tokens
+        .into_iter()
+        .skip(skip)
+        .map(|token| token.split_once('=').map_or(token, |(_, value)| value))
+        .filter(|token| {
+            token.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                && !matches!(*token, "this" | "true" | "false" | "null" | "else")
+        })
+        .collect()
+}
+
+/// A name with the line and column it appears at
+type Site<'a> = (&'a str, (usize, usize));
+
+/// Checks the locals declared with `as |name|` against the variables of the root and the
+/// locals of enclosing blocks they hide, both sites are given as line and column
+pub fn shadowing(src: &str) -> Vec<String> {
+    // open blocks: whether they change the context, and their locals with their site
+    let mut open: Vec<(bool, Vec<Site>)> = Vec::new();
+    let mut roots: Vec<Site> = Vec::new();
+    let mut declared: Vec<Site> = Vec::new();
+    let mut warnings = Vec::new();
+    let mut expression = Expression::from(src).ok().flatten();
+    while let Some(expr) = expression {
+        let site = line_column(src, expr.content);
+        let is_open = matches!(expr.expression_type, ExpressionType::Open);
+        if let ExpressionType::HtmlEscaped | ExpressionType::Raw | ExpressionType::Open =
+            expr.expression_type
+        {
+            let in_context = open.iter().any(|(context, _)| *context);
+            for var in read_variables(expr.content, is_open) {
+                let root = var.split(['.', '[']).next().unwrap_or(var);
+                let local = open
+                    .iter()
+                    .any(|(_, locals)| locals.iter().any(|(name, _)| *name == root));
+                if !local && !in_context && !roots.iter().any(|(name, _)| *name == root) {
+                    roots.push((root, site));
+                }
+            }
+        }
+        match expr.expression_type {
+            ExpressionType::Open => {
+                let locals = declared_locals(expr.content);
+                let block = expr.content.split_whitespace().next().unwrap_or("");
+                for local in &locals {
+                    let outer = open
+                        .iter()
+                        .flat_map(|(_, locals)| locals)
+                        .find(|(name, _)| name == local);
+                    if let Some((_, (line, column))) = outer {
+                        warnings.push(format!(
+                            "`{}` declared at line {} column {} shadows the local `{}` declared at line {} column {}",
+                            local, site.0, site.1, local, line, column
+                        ));
+                    } else {
+                        declared.push((local, site));
+                    }
+                }
+                let context = locals.is_empty() && matches!(block, "each" | "with");
+                open.push((
+                    context,
+                    locals.into_iter().map(|name| (name, site)).collect(),
+                ));
+            }
+            ExpressionType::Close => {
+                open.pop();
+            }
+            _ => (),
+        }
+        expression = expr.next().ok().flatten();
+    }
+    // a root variable may be read after the block hiding it
+    for (local, (line, column)) in declared {
+        if let Some((_, (root_line, root_column))) = roots.iter().find(|(name, _)| *name == local) {
+            warnings.push(format!(
+                "`{}` declared at line {} column {} shadows the variable `{}` read at line {} column {}",
+                local, line, column, local, root_line, root_column
+            ));
+        }
+    }
+    warnings
+}
+
+/// Turns the findings of [`shadowing`] into errors when `deny` is set, `deny_shadowing = true`,
+/// otherwise they are returned to be reported as warnings
+pub fn deny_shadowing(
+    shadowing: Vec<String>,
+    deny: bool,
+    span: proc_macro2::Span,
+) -> syn::Result<Vec<String>> {
+    match shadowing
+        .iter()
+        .filter(|_| deny)
+        .map(|message| syn::Error::new(span, format!("dry-handlebars: {}", message)))
+        .reduce(|mut errors, err| {
+            errors.combine(err);
+            errors
+        }) {
+        Some(errors) => Err(errors),
+        None => Ok(shadowing),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deny_shadowing, duplicate_ids, shadowing};
+
+    #[test]
+    fn duplicate_ids_in_branches() {
+        assert!(
+            duplicate_ids(r#"{{#if ok}}<p id="msg">ok</p>{{else}}<p id="msg">no</p>{{/if}}"#)
+                .is_empty()
+        );
+        assert!(
+            duplicate_ids(
+                r#"{{#if a}}<p id="m">a</p>{{else if b}}<p id="m">b</p>{{else}}<p id="m"></p>{{/if}}"#
+            )
+            .is_empty()
+        );
+        assert!(
+            duplicate_ids(r#"{{#each items}}<li>{{this}}</li>{{else}}<p id="empty"></p>{{/each}}"#)
+                .is_empty()
+        );
+        assert_eq!(
+            duplicate_ids(r#"{{#if ok}}<p id="msg">ok</p>{{/if}}<p id="msg">always</p>"#),
+            ["duplicate id \"msg\""]
+        );
+        assert_eq!(
+            duplicate_ids(
+                r#"{{#if ok}}<p id="msg">{{#if more}}<b id="msg"></b>{{/if}}</p>{{else}}<p id="msg">no</p>{{/if}}"#
+            ),
+            ["duplicate id \"msg\""]
+        );
+        assert_eq!(
+            duplicate_ids(
+                r#"{{#if a}}<p id="x"></p>{{/if}}{{#if b}}<p id="x"></p>{{else}}{{/if}}"#
+            ),
+            ["duplicate id \"x\""]
+        );
+    }
+
+    #[test]
+    fn duplicate_ids_in_each() {
+        assert_eq!(
+            duplicate_ids(r#"{{#each items}}<li id="item">{{this}}</li>{{/each}}"#),
+            ["id \"item\" is repeated by each"]
+        );
+        assert_eq!(
+            duplicate_ids(r#"{{#each items}}<li id="item-{{id}}">{{this}}</li>{{/each}}"#),
+            ["id \"item-{{..}}\" is interpolated inside each without @index"]
+        );
+        assert!(
+            duplicate_ids(r#"{{#each items}}<li id="item-{{@index}}">{{this}}</li>{{/each}}"#)
+                .is_empty()
+        );
+        assert!(duplicate_ids(r#"<p id="a"></p><p id="b"></p><p id="a-{{x}}"></p>"#).is_empty());
+    }
+
+    #[test]
+    fn shadowing_locals_and_roots() {
+        assert_eq!(
+            shadowing("{{name}}\n{{#each people as |name|}}{{name}}{{/each}}"),
+            [
+                "`name` declared at line 2 column 4 shadows the variable `name` read at line 1 column 3"
+            ]
+        );
+        assert_eq!(
+            shadowing(
+                "{{#each rows as |row|}}{{#each row.cells as |row|}}{{row}}{{/each}}{{/each}}"
+            ),
+            [
+                "`row` declared at line 1 column 27 shadows the local `row` declared at line 1 column 4"
+            ]
+        );
+        // read after the block hiding it
+        assert_eq!(
+            shadowing("{{#with user as |title|}}{{title}}{{/with}}{{title}}").len(),
+            1
+        );
+        assert!(
+            shadowing("{{#each people as |person|}}{{person.name}}{{/each}}{{name}}").is_empty()
+        );
+        // inside each without locals names are read from the item, not the root
+        assert!(
+            shadowing("{{#each people}}{{name}}{{/each}}{{#with a as |name|}}{{name}}{{/with}}")
+                .is_empty()
+        );
+        assert!(
+            shadowing("{{#each a as |x|}}{{x}}{{/each}}{{#each b as |x|}}{{x}}{{/each}}")
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn deny_shadowing_errors() {
+        let span = proc_macro2::Span::call_site();
+        let found = shadowing("{{x}}{{#each xs as |x|}}{{x}}{{/each}}{{#each ys as |x|}}{{/each}}");
+        assert_eq!(found.len(), 2);
+        assert_eq!(deny_shadowing(found.clone(), false, span).unwrap(), found);
+        let errors = deny_shadowing(found, true, span)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                "dry-handlebars: `x` declared at line 1 column 9 shadows the variable `x` read at line 1 column 3",
+                "dry-handlebars: `x` declared at line 1 column 42 shadows the variable `x` read at line 1 column 3",
+            ]
+        );
+        assert!(deny_shadowing(Vec::new(), true, span).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,415 @@
+//! Partials included with `{{> name}}`
+//!
+//! A partial is read from `name.hbs` next to the including template (for `str!` templates it
+//! is found like the path of `file!`) and expanded into the template source before it is
+//! compiled, so it shares the template's variables. As in Handlebars, a partial on a line of
+//! its own is indented like that line unless `partial_indent = false` is given, except in
+//! `<pre>`, `<textarea>` and raw blocks where it is expanded as is.
+//!
+//! A partial including itself, directly or through others, is reported with the chain of
+//! inclusions instead of being expanded forever.
+//!
+//! In a `directory!`, files with one of its extensions whose name starts with `_` are partials
+//! rather than templates. They are read once into a [`Registry`] by their path below the
+//! directory without the extension, so any template of the directory includes `_header.hbs`
+//! with `{{> _header}}` and `admin/_nav.html.hbs` with `{{> admin/_nav}}`, or `{{> _nav}}`
+//! next to it.
+
+use crate::preformatted;
+use dry_handlebars_parser::expression::{Expression, ExpressionType};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A partial of a directory, read once for all the templates including it
+#[derive(Clone)]
+pub struct Partial {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// The partials of a directory by their name, such as `_header` or `admin/_nav`
+pub type Registry = BTreeMap<String, Partial>;
+
+/// Reads the partials below a directory, the files whose name starts with `_` and ends with
+/// one of the extensions, such as `hbs` or `html.hbs`
+pub fn registry(root: &Path, extensions: &[String]) -> Result<Registry, String> {
+    let mut registry = Registry::new();
+    for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+        let path = entry.path();
+        let Some(stem) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| {
+                extensions
+                    .iter()
+                    .find_map(|extension| name.strip_suffix(extension.as_str())?.strip_suffix('.'))
+            })
+            .filter(|stem| stem.starts_with('_'))
+        else {
+            continue;
+        };
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let name = relative
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|component| component.as_os_str().to_string_lossy())
+            .chain([stem.into()])
+            .collect::<Vec<_>>()
+            .join("/");
+        let content = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read partial {:?}: {}", path, err))?;
+        registry.insert(
+            name,
+            Partial {
+                path: path.to_path_buf(),
+                content,
+            },
+        );
+    }
+    Ok(registry)
+}
+
+/// A template with its partials expanded
+pub struct Expanded {
+    /// Template source with the partials' content in place of their tags
+    pub content: String,
+    /// Partial files read, so changes to them trigger a recompilation
+    pub files: Vec<PathBuf>,
+}
+
+/// Returns the offset of a slice within the source it was taken from
+fn offset(src: &str, slice: &str) -> usize {
+    slice.as_ptr() as usize - src.as_ptr() as usize
+}
+
+/// Finds a partial, relative to the including template's directory if it has one, then in
+/// the registry of the directory being compiled
+fn find_partial(
+    name: &str,
+    file: Option<&Path>,
+    roots: &[PathBuf],
+    registry: &Registry,
+) -> Result<Partial, String> {
+    let file_name = format!("{}.hbs", name);
+    let candidates = match file.and_then(Path::parent) {
+        Some(dir) => vec![dir.join(&file_name)],
+        None => crate::template_path_candidates(&file_name, roots),
+    };
+    let found = candidates.iter().find(|candidate| candidate.is_file());
+    // partials of the registry were read already, those next to the template are found by
+    // their path without the extension, which may be other than `.hbs`
+    let registered = match found {
+        Some(found) => registry.values().find(|partial| &partial.path == found),
+        None => registry.get(name).or_else(|| {
+            let next_to = file.and_then(Path::parent)?.join(name);
+            registry
+                .values()
+                .find(|partial| without_extension(&partial.path) == next_to)
+        }),
+    };
+    match (registered, found) {
+        (Some(partial), _) => Ok(partial.clone()),
+        (None, Some(found)) => {
+            let content = fs::read_to_string(found)
+                .map_err(|err| format!("failed to read partial {:?}: {}", found, err))?;
+            Ok(Partial {
+                path: found.clone(),
+                content,
+            })
+        }
+        (None, None) => {
+            let included_by = match file {
+                Some(file) => format!(" included by {:?}", file),
+                None => String::new(),
+            };
+            let registered = match registry.is_empty() {
+                true => String::new(),
+                false => format!(
+                    ", the directory's partials are {}",
+                    registry.keys().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            };
+            Err(format!(
+                "partial {:?}{} not found, tried: {}{}",
+                name,
+                included_by,
+                candidates
+                    .iter()
+                    .map(|candidate| format!("{:?}", candidate))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                registered
+            ))
+        }
+    }
+}
+
+/// Returns the path with its file name ending before the first dot, `_nav` of `_nav.html.hbs`
+fn without_extension(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(name.split('.').next().unwrap_or_default())
+}
+
+/// Prefixes every line of a partial's content with the indentation of its tag, apart from
+/// the lines starting in its preformatted regions
+fn indent_lines(content: &str, indent: &str) -> String {
+    let regions = preformatted::regions(content);
+    let mut indented = String::with_capacity(content.len());
+    for line in content.split_inclusive('\n') {
+        if !preformatted::contains(&regions, offset(content, line)) {
+            indented.push_str(indent);
+        }
+        indented.push_str(line);
+    }
+    indented
+}
+
+/// Expands the partials of a template, `file` is the template's file, `roots` the configured
+/// template roots and `registry` the partials of the directory being compiled
+pub fn expand(
+    src: &str,
+    file: Option<&Path>,
+    indent: bool,
+    roots: &[PathBuf],
+    registry: &Registry,
+) -> Result<Expanded, String> {
+    let template = file
+        .and_then(Path::file_stem)
+        .map_or("template".into(), |stem| stem.to_string_lossy());
+    let mut chain = vec![(template.to_string(), file.map(Path::to_path_buf))];
+    expand_within(src, file, indent, roots, registry, &mut chain)
+}
+
+/// Expands the partials of a template or partial, `chain` holds the name and file of the
+/// template and of each partial being expanded around it
+fn expand_within(
+    src: &str,
+    file: Option<&Path>,
+    indent: bool,
+    roots: &[PathBuf],
+    registry: &Registry,
+    chain: &mut Vec<(String, Option<PathBuf>)>,
+) -> Result<Expanded, String> {
+    let mut expanded = Expanded {
+        content: String::with_capacity(src.len()),
+        files: Vec::new(),
+    };
+    let mut copied = 0;
+    let regions = preformatted::regions(src);
+    let mut expression = Expression::from(src).map_err(|err| err.to_string())?;
+    while let Some(expr) = expression {
+        expression = expr.next().map_err(|err| err.to_string())?;
+        let name = match (expr.expression_type, expr.content.trim().strip_prefix('>')) {
+            (ExpressionType::HtmlEscaped, Some(name)) => name.trim(),
+            _ => continue,
+        };
+        if name.starts_with('(') {
+            return Err(format!(
+                "dynamic partials are not supported in {}, name the partial and choose \
+                 between partials with {{{{#if}}}}",
+                expr.raw
+            ));
+        }
+        if name.is_empty() {
+            return Err(format!("expected a partial name in {}", expr.raw));
+        }
+        if name.contains(char::is_whitespace) {
+            return Err(format!(
+                "partial arguments are not supported in {}, a partial shares the variables of \
+                 the template including it, use {{{{> name}}}}",
+                expr.raw
+            ));
+        }
+        let start = offset(src, expr.prefix) + expr.prefix.len();
+        let end = offset(src, expr.postfix);
+
+        let line_start = src[..start].rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = src[end..].find('\n').map_or(src.len(), |pos| end + pos + 1);
+        let standalone = src[line_start..start].trim().is_empty()
+            && src[end..line_end].trim().is_empty()
+            && line_start >= copied
+            && !preformatted::contains(&regions, start);
+
+        let Partial { path, content } = find_partial(name, file, roots, registry)?;
+        // `./a.hbs` and `../dir/a.hbs` are the same file
+        let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let partial_file = canonical(&path);
+        if chain.iter().any(|(_, including)| {
+            including
+                .as_deref()
+                .is_some_and(|including| canonical(including) == partial_file)
+        }) {
+            return Err(format!(
+                "partials include each other: {} > {}, {:?} is already being expanded",
+                chain
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" > "),
+                name,
+                path
+            ));
+        }
+        let content = content.strip_prefix('\u{feff}').unwrap_or(&content);
+        chain.push((name.to_string(), Some(path.clone())));
+        let partial = expand_within(content, Some(&path), indent, roots, registry, chain)?;
+        chain.pop();
+        expanded.files.push(path);
+        expanded.files.extend(partial.files);
+
+        if standalone {
+            // the tag's line is replaced by the partial, indented like the tag
+            expanded.content.push_str(&src[copied..line_start]);
+            match indent {
+                true => expanded
+                    .content
+                    .push_str(&indent_lines(&partial.content, &src[line_start..start])),
+                false => expanded.content.push_str(&partial.content),
+            }
+            copied = line_end;
+        } else {
+            expanded.content.push_str(&src[copied..start]);
+            expanded.content.push_str(&partial.content);
+            copied = end;
+        }
+    }
+    expanded.content.push_str(&src[copied..]);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the files of a test into a directory of its own under the temporary directory
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dry-handlebars-{}-{}", test, std::process::id()));
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn expand_file(dir: &Path, name: &str) -> Result<Expanded, String> {
+        let file = dir.join(name);
+        let src = fs::read_to_string(&file).unwrap();
+        expand(&src, Some(&file), true, &[], &Registry::new())
+    }
+
+    #[test]
+    fn self_including_partial() {
+        let dir = write_files(
+            "self-including",
+            &[
+                ("page.hbs", "<main>{{> a}}</main>"),
+                ("a.hbs", "a {{> a}}"),
+                ("b.hbs", "b {{> ./b}}"),
+            ],
+        );
+        let error = expand_file(&dir, "page.hbs").err().unwrap();
+        assert!(
+            error.starts_with("partials include each other: page > a > a,"),
+            "{}",
+            error
+        );
+        let error = expand_file(&dir, "b.hbs").err().unwrap();
+        assert!(
+            error.starts_with("partials include each other: b > ./b,"),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn partial_cycle_through_other_paths() {
+        let dir = write_files(
+            "cycle-paths",
+            &[
+                ("views/page.hbs", "{{> ./a}}"),
+                ("views/a.hbs", "a {{> ../views/a}}"),
+                ("views/c.hbs", "c {{> ../views/d}}"),
+                ("views/d.hbs", "d"),
+            ],
+        );
+        let error = expand_file(&dir, "views/page.hbs").err().unwrap();
+        assert!(
+            error.starts_with("partials include each other: page > ./a > ../views/a,"),
+            "{}",
+            error
+        );
+        let expanded = expand_file(&dir, "views/c.hbs").unwrap();
+        assert_eq!(expanded.content, "c d");
+        assert_eq!(expanded.files, [dir.join("views/../views/d.hbs")]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn registry_extensions() {
+        let dir = write_files(
+            "registry-extensions",
+            &[
+                ("_header.html.hbs", "<header></header>"),
+                ("admin/_nav.html.hbs", "<nav></nav>"),
+                (
+                    "admin/page.html.hbs",
+                    "{{> _header}}{{> _nav}}{{> admin/_nav}}",
+                ),
+                ("_footer.hbs", "<footer></footer>"),
+                ("_notes.txt", "notes"),
+            ],
+        );
+        let registry = registry(&dir, &["html.hbs".to_string()]).unwrap();
+        assert_eq!(
+            registry.keys().collect::<Vec<_>>(),
+            ["_header", "admin/_nav"]
+        );
+        let file = dir.join("admin/page.html.hbs");
+        let src = fs::read_to_string(&file).unwrap();
+        let expanded = expand(&src, Some(&file), true, &[], &registry).unwrap();
+        assert_eq!(expanded.content, "<header></header><nav></nav><nav></nav>");
+        let registry = super::registry(&dir, &["hbs".to_string()]).unwrap();
+        assert_eq!(
+            registry.keys().collect::<Vec<_>>(),
+            ["_footer", "_header.html", "admin/_nav.html"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_partial() {
+        let dir = write_files(
+            "missing-partial",
+            &[
+                ("page.hbs", "{{> _nav}}"),
+                ("_header.hbs", "<header></header>"),
+            ],
+        );
+        let file = dir.join("page.hbs");
+        let error = expand_file(&dir, "page.hbs").err().unwrap();
+        assert_eq!(
+            error,
+            format!(
+                "partial \"_nav\" included by {:?} not found, tried: {:?}",
+                file,
+                dir.join("_nav.hbs")
+            )
+        );
+        let registry = registry(&dir, &["hbs".to_string()]).unwrap();
+        let error = expand("{{> _nav}}", Some(&file), true, &[], &registry)
+            .err()
+            .unwrap();
+        assert!(
+            error.ends_with(", the directory's partials are _header"),
+            "{}",
+            error
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,57 @@
+//! Regions of a template whose whitespace is written byte for byte
+//!
+//! The content of `<pre>` and `<textarea>` elements and of raw blocks
+//! (`{{{{raw}}}}...{{{{/raw}}}}`) is never minified or reindented, so every source
+//! transformation touching whitespace goes through [`map_outside`] or checks [`contains`].
+
+use regex::Regex;
+use std::ops::Range;
+
+/// Returns the byte ranges of the regions, sorted and not overlapping
+pub fn regions(src: &str) -> Vec<Range<usize>> {
+    let mut regions: Vec<Range<usize>> = Vec::new();
+    for pattern in [
+        r"(?is)<pre\b[^>]*>(.*?)</pre\s*>",
+        r"(?is)<textarea\b[^>]*>(.*?)</textarea\s*>",
+        r"(?s)\{\{\{\{[^}]*\}\}\}\}(.*?)\{\{\{\{/[^}]*\}\}\}\}",
+    ] {
+        let regex = Regex::new(pattern).unwrap();
+        regions.extend(
+            regex
+                .captures_iter(src)
+                .filter_map(|caps| caps.get(1))
+                .map(|content| content.range()),
+        );
+    }
+    regions.sort_by_key(|region| region.start);
+    // a raw block inside a <pre> is already kept
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(regions.len());
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Checks if an offset is inside one of the regions or at its end, where whitespace would be
+/// added before the closing tag
+pub fn contains(regions: &[Range<usize>], offset: usize) -> bool {
+    regions
+        .iter()
+        .any(|region| region.start <= offset && offset <= region.end)
+}
+
+/// Applies a transformation to the text outside the regions, keeping them as they are
+pub fn map_outside(src: &str, transform: impl Fn(&str) -> String) -> String {
+    let mut mapped = String::with_capacity(src.len());
+    let mut copied = 0;
+    for region in regions(src) {
+        mapped.push_str(&transform(&src[copied..region.start]));
+        mapped.push_str(&src[region.clone()]);
+        copied = region.end;
+    }
+    mapped.push_str(&transform(&src[copied..]));
+    mapped
+}
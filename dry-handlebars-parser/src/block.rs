@@ -0,0 +1,935 @@
+// MIT License
+//
+// Copyright (c) 2024 Jerome Johnson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Handlebars block parsing and compilation
+//!
+//! This module provides functionality for parsing and compiling Handlebars block helpers.
+//! It supports various block types including:
+//! - `if`/`unless` for conditional rendering
+//! - `with` for changing context
+//! - `each` for iterating over collections
+//!
+//! # Block Types
+//!
+//! ## Conditional Blocks
+//! - `{{#if value}}...{{/if}}` - Renders content if value is truthy
+//! - `{{#unless value}}...{{/unless}}` - Renders content if value is falsy
+//!
+//! ## Context Blocks
+//! - `{{#with value as item}}...{{/with}}` - Changes context to value
+//! - `{{#with (try_lookup map "key") default="value" as |item|}}...{{/with}}` - Falls back to
+//!   the default when the value is `None`, the item can then only be displayed
+//!
+//! ## Custom Blocks
+//! - `{{#name args}}...{{/name}}` - Calls a function registered from user code with the
+//!   writer, the arguments by reference and a closure rendering the body
+//!
+//! ## Iteration Blocks
+//! - `{{#each items as item}}...{{/each}}` - Iterates over collection
+//! - `{{#each scores as |name score|}}...{{/each}}` - Iterates over a map, destructuring
+//!   each entry into its key and value
+//! - `{{#each (chunks items 3)}}...{{/each}}` - Iterates over slices of up to 3 items
+//! - `{{#each (sort_by items "date" desc=true)}}`, `{{#each (filter items "published")}}`
+//!   and `{{#each (take items 5)}}` - Iterate over the items sorted by a field, those with a
+//!   `true` field or the first 5, and can be nested such as `(take (sort_by items "date") 5)`
+//! - Supports `@index` for accessing current index
+//! - Supports `else` block for empty collections
+//! - `{{#repeat 5}}...{{/repeat}}` - Repeats the body a number of times, `@index` (or the
+//!   name given with `as |i|`) counting from 0
+//!
+//! ## Hole Blocks
+//! - `{{#hole "cart"}}...{{/hole}}` - Content the writer can skip or render on its own, for
+//!   a static shell cached apart from its dynamic holes
+//!
+//! ## Defer Blocks
+//! - `{{#defer}}...{{/defer}}` - Content buffered and written at `{{deferred}}` later in the
+//!   template, or at its end, such as modals and scripts collected at the end of `<body>`
+//!
+//! # Examples
+//!
+//! ```ignore
+//! use block::{Block, BlockFactory};
+//! use expression::{Expression, ExpressionType};
+//!
+//! let template = "{{#if user}}Hello {{user.name}}!{{/if}}";
+//! let expr = Expression::from(template).unwrap().unwrap();
+//! assert_eq!(expr.expression_type, ExpressionType::Open);
+//! ```
+
+use crate::{
+    compiler::{
+        Block, BlockFactory, BlockMap, Compile, HashArgs, HelperCall, Local, Rust,
+        append_with_depth, field_argument,
+    },
+    error::{ParseError, Result},
+    expression::{Expression, ExpressionType},
+    expression_tokenizer::{Token, TokenType},
+};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Reads the names following `as`, stripping pipe characters: `as |key value|`
+fn strip_pipes<'a>(mut token: Token<'a>, expression: &Expression<'a>) -> Result<Vec<&'a str>> {
+    let mut names = Vec::new();
+    while let Some(next) = token.next()? {
+        let name = next.value.trim_matches('|');
+        if !name.is_empty() {
+            names.push(name);
+        }
+        token = next;
+    }
+    match names.is_empty() {
+        true => Err(ParseError::new("expected variable after as", expression)),
+        false => Ok(names),
+    }
+}
+
+/// Reads a local variable declaration from a token
+fn read_local<'a>(token: &Token<'a>, expression: &Expression<'a>) -> Result<Local> {
+    match token.next()? {
+        Some(token) => match token.value {
+            "as" => match strip_pipes(token, expression)?.as_slice() {
+                [name] => Ok(Local::As(name.to_string())),
+                [key, value] => Ok(Local::Pair(key.to_string(), value.to_string())),
+                _ => Err(ParseError::new(
+                    "expected one or two variables after as",
+                    expression,
+                )),
+            },
+            token => Err(ParseError::new(
+                &format!("unexpected token {}", token),
+                expression,
+            )),
+        },
+        None => Ok(Local::This),
+    }
+}
+
+/// Handles if/unless block compilation
+struct IfOrUnless {}
+
+impl IfOrUnless {
+    /// Creates a new if/unless block, `likely=true` or `likely=false` tells the optimizer
+    /// which branch is taken most
+    pub fn new<'a>(
+        label: &str,
+        negate: bool,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<IfOrUnless> {
+        match token.next()? {
+            Some(var) => {
+                let hash = HashArgs::read(label, &var, &["likely"], expression)?;
+                let hint = match hash.get("likely") {
+                    Some(_) => match hash.flag("likely", true, expression)? {
+                        true => Some("likely"),
+                        false => Some("unlikely"),
+                    },
+                    None => None,
+                };
+                rust.code.push_str("if ");
+                if let Some(hint) = hint {
+                    rust.code.push_str(hint);
+                    rust.code.push('(');
+                    rust.using.insert(hint.to_string());
+                }
+                if negate {
+                    rust.code.push('!');
+                }
+                compile.write_condition(expression, rust, &var)?;
+                if hint.is_some() {
+                    rust.code.push(')');
+                }
+                rust.code.push('{');
+                Ok(Self {})
+            }
+            None => Err(ParseError::new(
+                &format!("expected variable after {}", label),
+                expression,
+            )),
+        }
+    }
+}
+
+impl Block for IfOrUnless {
+    /// Handles else block compilation
+    fn handle_else<'a>(&self, _expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
+        rust.code.push_str("}else{");
+        Ok(())
+    }
+}
+
+/// Factory for if blocks
+struct IfFty {}
+
+impl BlockFactory for IfFty {
+    /// Opens an if block
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        Ok(Box::new(IfOrUnless::new(
+            "if", false, compile, token, expression, rust,
+        )?))
+    }
+}
+
+/// Factory for unless blocks
+struct UnlessFty {}
+
+impl BlockFactory for UnlessFty {
+    /// Opens an unless block
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        Ok(Box::new(IfOrUnless::new(
+            "unless", true, compile, token, expression, rust,
+        )?))
+    }
+}
+
+/// Handles if_some block compilation
+struct IfSome {
+    local: Local,
+}
+
+impl IfSome {
+    /// Creates a new if_some block
+    fn new<'a>(
+        by_ref: bool,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Self> {
+        let next = token.next()?.ok_or_else(|| {
+            ParseError::new(
+                &format!(
+                    "expected variable after if_some{}",
+                    if by_ref { "_ref" } else { "" }
+                ),
+                expression,
+            )
+        })?;
+        let local = read_local(&next, expression)?;
+        rust.code.push_str("if let Some(");
+        compile.write_local(&mut rust.code, &local);
+        rust.code.push_str(") = ");
+        if by_ref {
+            rust.code.push('&');
+        }
+        compile.write_var(expression, rust, &next)?;
+        rust.code.push('{');
+        Ok(Self { local })
+    }
+}
+
+impl Block for IfSome {
+    /// Handles else block compilation
+    fn handle_else<'a>(&self, _expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
+        rust.code.push_str("}else{");
+        Ok(())
+    }
+
+    /// Returns the local variable
+    fn local<'a>(&self) -> &Local {
+        &self.local
+    }
+}
+
+/// Handles with block compilation
+struct With {
+    local: Local,
+}
+
+impl With {
+    /// Creates a new with block
+    pub fn new<'a>(
+        by_ref: bool,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Self> {
+        let next = token.next()?.ok_or_else(|| {
+            ParseError::new(
+                &format!(
+                    "expected variable after with{}",
+                    if by_ref { "_ref" } else { "" }
+                ),
+                expression,
+            )
+        })?;
+        let hash = HashArgs::read("with", &next, &["default"], expression)?;
+        let default = hash.get("default");
+        let local = read_local(&hash.rest, expression)?;
+        rust.code.push_str("{let ");
+        compile.write_local(&mut rust.code, &local);
+        rust.code.push_str(" = ");
+        if by_ref || default.is_some() {
+            rust.code.push('&');
+        }
+        compile.write_var(expression, rust, &next)?;
+        rust.code.push(';');
+        if let Some(default) = default {
+            // the value and the default only have to agree on being displayable
+            rust.code.push_str("let ");
+            compile.write_local(&mut rust.code, &local);
+            rust.code.push_str(": &dyn std::fmt::Display = match ");
+            compile.write_local(&mut rust.code, &local);
+            rust.code.push_str(" {Some(v) => v, None => &");
+            compile.write_var(expression, rust, default)?;
+            rust.code.push_str("};");
+        }
+        Ok(Self { local })
+    }
+}
+
+impl Block for With {
+    /// Returns the local variable
+    fn local<'a>(&self) -> &Local {
+        &self.local
+    }
+}
+
+/// Factory for with blocks
+struct WithFty {}
+
+impl BlockFactory for WithFty {
+    /// Opens a with block
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let token_clone = token.clone();
+        if let Some(var) = token_clone.next()? {
+            let var_name = var.value;
+            let optional = compile
+                .variable_types
+                .get(var_name)
+                .is_some_and(|type_str| type_str.contains("Option"))
+                || compile.is_optional_helper(&var)?;
+            if optional
+                && HashArgs::read("with", &var, &["default"], expression)?
+                    .get("default")
+                    .is_none()
+            {
+                return Ok(Box::new(IfSome::new(
+                    true, compile, token, expression, rust,
+                )?));
+            }
+        }
+        Ok(Box::new(With::new(true, compile, token, expression, rust)?))
+    }
+}
+
+/// Handles each block compilation
+struct Each {
+    local: Local,
+    indexer: Option<String>,
+    has_else: bool,
+    /// Code storing a rendered item in the cache, with `cache_key="id"`
+    cache_close: Option<String>,
+    /// The else block is open, outside the loop where the item is out of scope
+    in_else: Cell<bool>,
+}
+
+/// Checks if a string contains an indexer expression at the given depth, such as `@index`
+/// or `@../index` one block further out
+fn contains_indexer(src: &str, depth: i32) -> bool {
+    src.match_indices('@').any(|(at, _)| {
+        let mut depth = depth;
+        let mut rest = &src[at + 1..];
+        while let Some(outer) = rest.strip_prefix("../") {
+            depth -= 1;
+            rest = outer;
+        }
+        depth == 0
+            && rest
+                .strip_prefix("index")
+                .is_some_and(|end| !end.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    })
+}
+
+/// Checks if a block contains an indexer expression
+fn check_for_indexer(src: &str) -> Result<bool> {
+    let mut exp = Expression::from(src)?;
+    let mut depth = 1;
+    while let Some(expr) = &exp {
+        match expr.expression_type {
+            ExpressionType::Comment | ExpressionType::Escaped => (),
+            ExpressionType::Open => {
+                if contains_indexer(expr.content, depth - 1) {
+                    return Ok(true);
+                } else {
+                    depth += 1;
+                }
+            }
+            ExpressionType::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(false);
+                }
+            }
+            _ => {
+                if contains_indexer(expr.content, depth - 1) {
+                    return Ok(true);
+                }
+            }
+        }
+        exp = expr.next()?;
+    }
+    Ok(false)
+}
+
+/// Returns the first thing a cached each body reads that isn't part of its item and may change
+/// between renders: a value outside it through `../` or `@root`, the item's position through
+/// `@index`, `@first` or `@last`, or the per-render `nonce`
+fn find_uncacheable<'a>(
+    src: &'a str,
+    variable_types: &HashMap<String, String>,
+) -> Result<Option<&'a str>> {
+    let reads_position = |content: &str| {
+        content.match_indices('@').any(|(at, _)| {
+            ["index", "first", "last"].iter().any(|name| {
+                content[at + 1..]
+                    .strip_prefix(name)
+                    .is_some_and(|end| !end.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+            })
+        })
+    };
+    let mut exp = Expression::from(src)?;
+    let mut depth = 1;
+    // each blocks open inside the body, whose positions are their own
+    let mut inner_each = Vec::new();
+    while let Some(expr) = &exp {
+        let content = expr.content;
+        match expr.expression_type {
+            ExpressionType::Comment | ExpressionType::Escaped => (),
+            ExpressionType::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(None);
+                }
+                if inner_each.last() == Some(&depth) {
+                    inner_each.pop();
+                }
+            }
+            _ => {
+                let reads_nonce = !variable_types.contains_key("nonce")
+                    && content
+                        .split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                        .any(|token| token == "nonce");
+                if content.contains("../")
+                    || content.contains("@root")
+                    || inner_each.is_empty() && reads_position(content)
+                    || reads_nonce
+                {
+                    return Ok(Some(content));
+                }
+                if let ExpressionType::Open = expr.expression_type {
+                    if matches!(content.split_whitespace().next(), Some("each" | "each_ref")) {
+                        inner_each.push(depth);
+                    }
+                    depth += 1;
+                }
+            }
+        }
+        exp = expr.next()?;
+    }
+    Ok(None)
+}
+
+/// Checks if a block contains an else block
+fn check_for_else(src: &str) -> Result<bool> {
+    let mut exp = Expression::from(src)?;
+    let mut depth = 1;
+    while let Some(expr) = &exp {
+        match expr.expression_type {
+            ExpressionType::Comment | ExpressionType::Escaped => (),
+            ExpressionType::Open => depth += 1,
+            ExpressionType::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(false);
+                }
+            }
+            _ => {
+                if expr.content == "else" && depth == 1 {
+                    return Ok(true);
+                }
+            }
+        }
+        exp = expr.next()?;
+    }
+    Ok(false)
+}
+
+impl Each {
+    /// Creates a new each block
+    pub fn new<'a>(
+        by_ref: bool,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Self> {
+        let next = match token.next()? {
+            Some(next) => next,
+            None => {
+                return Err(ParseError::new(
+                    &format!(
+                        "expected variable after {}",
+                        if by_ref { "each_ref" } else { "each" }
+                    ),
+                    expression,
+                ));
+            }
+        };
+        let indexer = check_for_indexer(expression.postfix).map(|found| match found {
+            true => {
+                let indexer = format!("i_{}", compile.open_stack.len());
+                rust.code.push_str("let mut ");
+                rust.code.push_str(indexer.as_str());
+                // usize like the index of repeat, so arithmetic on either has the same type
+                rust.code.push_str(": usize = 0;");
+                Some(indexer)
+            }
+            false => None,
+        })?;
+        let hash = HashArgs::read("each", &next, &["cache_key", "by_ref"], expression)?;
+        // by_ref=false iterates the value itself, such as a range or a borrowed slice
+        let by_ref = hash.flag("by_ref", by_ref, expression)?;
+        let cache_key = hash.get("cache_key");
+        let local = read_local(&hash.rest, expression)?;
+        let has_else = check_for_else(expression.postfix)?;
+        if has_else {
+            rust.code.push_str("{let mut empty = true;");
+        }
+        rust.code.push_str("for ");
+        compile.write_local(&mut rust.code, &local);
+        rust.code.push_str(" in ");
+        if by_ref && !compile.is_iterator(&next)? {
+            // a method call derefs locals that are already references, such as an outer
+            // each's item
+            rust.code.push_str("(&");
+            compile.write_var(expression, rust, &next)?;
+            rust.code.push_str(").into_iter()");
+        } else {
+            compile.write_var(expression, rust, &next)?;
+        }
+        rust.code.push('{');
+        if has_else {
+            rust.code.push_str("empty = false;");
+        }
+        let cache_close = match cache_key {
+            Some(field) => Some(Self::write_cache_open(
+                compile, field, &local, expression, rust,
+            )?),
+            None => None,
+        };
+        Ok(Self {
+            local,
+            indexer,
+            has_else,
+            cache_close,
+            in_else: Cell::new(false),
+        })
+    }
+
+    /// Writes the lookup of an item in the fragment cache given to `render_with_cache`, the
+    /// item is rendered when it's missing, returning the code storing it
+    fn write_cache_open<'a>(
+        compile: &'a Compile<'a>,
+        field: &Token<'a>,
+        local: &Local,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<String> {
+        let field = field_argument("cache_key", expression, Some(field.clone()))?;
+        if let Local::Pair(..) = local {
+            return Err(ParseError::new(
+                "cache_key expects a list of items, not map entries",
+                expression,
+            ));
+        }
+        if let Some(read) = find_uncacheable(expression.postfix, compile.variable_types)? {
+            return Err(ParseError::new(
+                &format!(
+                    "cache_key can't cache items reading {{{{{}}}}}, which isn't part of the item and may change between renders",
+                    read
+                ),
+                expression,
+            ));
+        }
+        let mut item = String::new();
+        compile.write_local(&mut item, local);
+        let depth = compile.open_stack.len();
+        let f = compile.write_var_name;
+        // the template and each expression keep keys of different lists apart
+        write!(
+            rust.code,
+            "let fragment_key_{depth} = fragment_key(::std::any::type_name::<Self>(), {:?}, &{item}.{field});\
+             match cache.get(fragment_key_{depth}) {{Some(html) => {f}.write_str(&html)?,\
+             None => {{let fragment_start_{depth} = {f}.fragment_start();",
+            expression.raw,
+        )
+        .unwrap();
+        rust.using.insert("fragment_key".to_string());
+        rust.using.insert("FragmentWriter".to_string());
+        rust.cached = true;
+        Ok(format!(
+            "cache.insert(fragment_key_{depth}, {f}.fragment_since(fragment_start_{depth}));}}}}"
+        ))
+    }
+    /// Writes a map variable access
+    fn write_map_var(&self, depth: usize, suffix: &str, rust: &mut Rust) {
+        append_with_depth(
+            depth,
+            if let Local::As(name) = &self.local {
+                name.as_str()
+            } else {
+                "this"
+            },
+            &mut rust.code,
+        );
+        rust.code.push_str(suffix)
+    }
+
+    /// Writes the storing of a rendered item in the cache
+    fn write_cache_close(&self, rust: &mut Rust) {
+        if let Some(close) = &self.cache_close {
+            rust.code.push_str(close);
+        }
+    }
+
+    /// Writes an indexer increment
+    fn write_indexer(&self, rust: &mut Rust) {
+        if let Some(indexer) = &self.indexer {
+            rust.code.push_str(indexer);
+            rust.code.push_str("+=1;");
+        }
+    }
+}
+
+impl Block for Each {
+    fn handle_else<'a>(&self, _expression: &'a Expression<'a>, rust: &mut Rust) -> Result<()> {
+        self.write_cache_close(rust);
+        self.write_indexer(rust);
+        rust.code.push_str("} if empty {");
+        self.in_else.set(true);
+        Ok(())
+    }
+
+    fn resolve_private<'a>(
+        &self,
+        depth: usize,
+        expression: &'a Expression<'a>,
+        name: &str,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        if self.in_else.get() {
+            return Err(ParseError::new(
+                &format!(
+                    "@{} is only available in the body of each, not its else",
+                    name
+                ),
+                expression,
+            ));
+        }
+        match (name, &self.local) {
+            ("index", _) => rust.code.push_str(self.indexer.as_ref().unwrap()),
+            ("key", Local::Pair(key, _)) => append_with_depth(depth, key, &mut rust.code),
+            ("value", Local::Pair(_, value)) => append_with_depth(depth, value, &mut rust.code),
+            ("key", _) => self.write_map_var(depth, ".0", rust),
+            ("value", _) => self.write_map_var(depth, ".1", rust),
+            _ => Err(ParseError::new(
+                &format!("unexpected variable {}", name),
+                expression,
+            ))?,
+        }
+        Ok(())
+    }
+
+    fn handle_close<'a>(&self, rust: &mut Rust) {
+        if self.has_else {
+            rust.code.push_str("}}");
+        } else {
+            self.write_cache_close(rust);
+            self.write_indexer(rust);
+            rust.code.push('}');
+        }
+    }
+
+    fn local<'a>(&self) -> &Local {
+        // variables of the else block are those of the enclosing scope
+        match self.in_else.get() {
+            true => &Local::None,
+            false => &self.local,
+        }
+    }
+}
+
+/// Factory for each blocks
+struct EachFty {}
+
+impl BlockFactory for EachFty {
+    /// Opens an each block
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        Ok(Box::new(Each::new(true, compile, token, expression, rust)?))
+    }
+}
+
+/// Handles repeat block compilation
+struct Repeat {
+    local: Local,
+    /// Loop variable counting the repetitions
+    index: String,
+}
+
+impl Block for Repeat {
+    fn resolve_private<'a>(
+        &self,
+        _depth: usize,
+        expression: &'a Expression<'a>,
+        name: &str,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        match name {
+            "index" => rust.code.push_str(&self.index),
+            _ => Err(ParseError::new(
+                &format!("unexpected variable {}", name),
+                expression,
+            ))?,
+        }
+        Ok(())
+    }
+
+    fn local<'a>(&self) -> &Local {
+        &self.local
+    }
+}
+
+/// Factory for repeat blocks
+struct RepeatFty {}
+
+impl BlockFactory for RepeatFty {
+    /// Opens a repeat block, compiled to `for i in 0..count as usize`
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let count = token
+            .next()?
+            .ok_or_else(|| ParseError::new("expected count after repeat", expression))?;
+        let local = match read_local(&count, expression)? {
+            Local::This => Local::None,
+            Local::Pair(..) => {
+                return Err(ParseError::new(
+                    "expected one variable after as",
+                    expression,
+                ));
+            }
+            local => local,
+        };
+        let mut index = String::new();
+        match &local {
+            Local::As(_) => compile.write_local(&mut index, &local),
+            _ => append_with_depth(compile.open_stack.len(), "i", &mut index),
+        }
+        rust.code.push_str("for ");
+        rust.code.push_str(&index);
+        rust.code.push_str(" in 0..(");
+        compile.write_var(expression, rust, &count)?;
+        rust.code.push_str(") as usize{");
+        Ok(Box::new(Repeat { local, index }))
+    }
+}
+
+/// Handles hole blocks, rendered in place unless the writer skips them
+struct Hole {
+    /// Code closing the block
+    close: String,
+}
+
+impl Block for Hole {
+    fn handle_close(&self, rust: &mut Rust) {
+        rust.code.push_str(&self.close);
+    }
+}
+
+/// Factory for hole blocks
+struct HoleFty {}
+
+impl BlockFactory for HoleFty {
+    /// Opens a hole block, compiled to `if f.enter_hole("name") { .. } f.leave_hole("name");`
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        let name = match token.next()? {
+            Some(name)
+                if matches!(name.token_type, TokenType::Literal) && name.value.starts_with('"') =>
+            {
+                name.value
+            }
+            _ => {
+                return Err(ParseError::new(
+                    "expected a double quoted name after hole",
+                    expression,
+                ));
+            }
+        };
+        rust.using.insert("HoleWriter".to_string());
+        write!(
+            rust.code,
+            "if {}.enter_hole({}){{",
+            compile.write_var_name, name
+        )
+        .unwrap();
+        Ok(Box::new(Hole {
+            close: format!("}}{}.leave_hole({});", compile.write_var_name, name),
+        }))
+    }
+}
+
+/// Handles defer blocks, written to the `deferred` buffer instead of the output
+struct Defer {}
+
+impl Block for Defer {
+    fn handle_close(&self, rust: &mut Rust) {
+        rust.code.push('}');
+        rust.defer_depth -= 1;
+    }
+}
+
+/// Factory for defer blocks
+struct DeferFty {}
+
+impl BlockFactory for DeferFty {
+    /// Opens a defer block, compiled to `{ let f = &mut deferred; .. }`
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>> {
+        if token.next()?.is_some() {
+            return Err(ParseError::new("defer takes no arguments", expression));
+        }
+        if rust.deferred_written {
+            return Err(ParseError::new(
+                "defer after {{deferred}} would never be written",
+                expression,
+            ));
+        }
+        rust.code.push('{');
+        // a nested block already writes to the buffer
+        if rust.defer_depth == 0 {
+            write!(rust.code, "let {} = &mut deferred;", compile.write_var_name).unwrap();
+        }
+        rust.deferred = true;
+        rust.defer_depth += 1;
+        Ok(Box::new(Defer {}))
+    }
+}
+
+/// Handles block helpers registered from user code
+struct Custom {}
+
+impl Block for Custom {
+    fn handle_close(&self, rust: &mut Rust) {
+        rust.code.push_str("Ok(())})?;");
+    }
+}
+
+/// Opens a block helper registered from user code, compiled to
+/// `path(&mut f, &arg, .., &mut |f| { body })?`
+pub fn open_custom<'a>(
+    path: &str,
+    compile: &'a Compile<'a>,
+    token: Token<'a>,
+    expression: &'a Expression<'a>,
+    rust: &mut Rust,
+) -> Result<Box<dyn Block>> {
+    rust.code.push_str(path);
+    rust.code.push_str("(&mut ");
+    rust.code.push_str(compile.write_var_name);
+    // the writer is the first parameter
+    let mut call = HelperCall::new(path, 1);
+    let mut arg = token.next()?;
+    while let Some(token) = arg {
+        rust.code.push_str(", &");
+        compile.write_arg(expression, rust, &token, &mut call)?;
+        arg = token.next()?;
+    }
+    rust.code.push_str(", &mut |");
+    rust.code.push_str(compile.write_var_name);
+    rust.code
+        .push_str(": &mut dyn std::fmt::Write| -> std::fmt::Result {");
+    Ok(Box::new(Custom {}))
+}
+
+const IF: IfFty = IfFty {};
+const UNLESS: UnlessFty = UnlessFty {};
+const WITH: WithFty = WithFty {};
+const EACH: EachFty = EachFty {};
+const REPEAT: RepeatFty = RepeatFty {};
+const HOLE: HoleFty = HoleFty {};
+const DEFER: DeferFty = DeferFty {};
+
+/// Adds built-in block helpers to the block map
+pub fn add_builtins(map: &mut BlockMap) {
+    map.insert("if", &IF);
+    map.insert("unless", &UNLESS);
+    map.insert("with", &WITH);
+    map.insert("each", &EACH);
+    map.insert("repeat", &REPEAT);
+    map.insert("hole", &HOLE);
+    map.insert("defer", &DEFER);
+}
@@ -0,0 +1,2931 @@
+// MIT License
+//
+// Copyright (c) 2024 Jerome Johnson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Handlebars template compilation
+//!
+//! This module provides functionality for compiling Handlebars templates into Rust code.
+//! It handles:
+//! - Variable resolution and scope management
+//! - Block helper compilation
+//! - Expression evaluation
+//! - HTML escaping
+//!
+//! # Compilation Process
+//!
+//! The compilation process involves:
+//! 1. Parsing the template into expressions
+//! 2. Resolving variables and scopes
+//! 3. Compiling block helpers
+//! 4. Generating Rust code
+//!
+//! # Examples
+//!
+//! Basic usage:
+//! ```ignore
+//! use compiler::{Compiler, Options};
+//! use block::add_builtins;
+//!
+//! let mut block_map = HashMap::new();
+//! add_builtins(&mut block_map);
+//!
+//! let options = Options {
+//!     root_var_name: Some("data"),
+//!     write_var_name: "write"
+//! };
+//!
+//! let compiler = Compiler::new(options, block_map);
+//! let rust = compiler.compile("Hello {{name}}!")?;
+//! ```
+//!
+//! Complex template example:
+//! ```ignore
+//! use compiler::{Compiler, Options};
+//! use block::add_builtins;
+//!
+//! let mut block_map = HashMap::new();
+//! add_builtins(&mut block_map);
+//!
+//! let options = Options {
+//!     root_var_name: Some("data"),
+//!     write_var_name: "write"
+//! };
+//!
+//! let template = r#"
+//! <div class="user-profile">
+//!     {{#if user}}
+//!         <h1>{{user.name}}</h1>
+//!         {{#if user.bio}}
+//!             <p class="bio">{{user.bio}}</p>
+//!         {{else}}
+//!             <p class="no-bio">No bio available</p>
+//!         {{/if}}
+//!         
+//!         {{#if_some user.posts as post}}
+//!             <div class="posts">
+//!                 <h2>Posts</h2>
+//!                 {{#each post as post}}
+//!                     <article class="post">
+//!                         <h3>{{post.title}}</h3>
+//!                         <p>{{post.content}}</p>
+//!                         <div class="meta">
+//!                             <span>Posted on {{post.date}}</span>
+//!                             {{#if post.tags}}
+//!                                 <div class="tags">
+//!                                     {{#each post.tags as tag}}
+//!                                         <span class="tag">{{tag}}</span>
+//!                                     {{/each}}
+//!                                 </div>
+//!                             {{/if}}
+//!                         </div>
+//!                     </article>
+//!                 {{/each}}
+//!             </div>
+//!         {{/if_some}}
+//!     {{else}}
+//!         <p>Please log in to view your profile</p>
+//!     {{/if}}
+//! </div>
+//! "#;
+//!
+//! let compiler = Compiler::new(options, block_map);
+//! let rust = compiler.compile(template)?;
+//! ```
+//!
+//! This example demonstrates:
+//! - Nested conditional blocks with `if` and `else`
+//! - Option handling with `if_some`
+//! - Collection iteration with `each`
+//! - HTML escaping for safe output
+//! - Complex variable resolution
+//! - Block scope management
+//! - Template structure and formatting
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::{Display, Write},
+    ops::Range,
+    sync::LazyLock,
+};
+
+use regex::{Captures, Regex};
+
+use crate::{
+    block::{add_builtins, open_custom},
+    date_format::Backend,
+    error::{ParseError, Result},
+    expression::{Expression, ExpressionType},
+    expression_tokenizer::{Token, TokenType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Display,
+    Boolean,
+    /// Used as a condition and as a value
+    Both,
+    /// Iterated by `each`, a condition on it tests that it is not empty
+    Iterated,
+}
+
+/// A way a variable of a template is used, as reported by [`Compiler::scan_report`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UsageKind {
+    /// Written or given to a helper, a `Display` value
+    Display,
+    /// Tested by `if`, `unless` or a `class` condition
+    Bool,
+    /// Iterated by `each`
+    Iterable,
+    /// Followed by `?.`, an `Option`
+    Optional,
+    /// Iterated reading `@key`, or read by key with `try_lookup` or `dig`
+    Map,
+    /// Indexed by `lookup` or a `.[0]` segment
+    Index,
+}
+
+/// A variable of a template with every way it is used, in the order they first appear
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariableReport {
+    /// Path of the variable, `user.name` for `user?.name`
+    pub path: String,
+    pub kinds: Vec<UsageKind>,
+}
+
+/// Adds a way a variable is used to a report, the variable first if it isn't in it yet
+fn report_usage(report: &mut Vec<VariableReport>, path: &str, kind: UsageKind) {
+    let path = path.replace("?.", ".");
+    match report.iter_mut().find(|variable| variable.path == path) {
+        Some(variable) if variable.kinds.contains(&kind) => {}
+        Some(variable) => variable.kinds.push(kind),
+        None => report.push(VariableReport {
+            path,
+            kinds: vec![kind],
+        }),
+    }
+}
+
+/// Reports the kinds of usage `scan` doesn't tell apart in the tokens of an expression,
+/// `each` is the variable iterated by the innermost `each`
+fn report_tokens(
+    report: &mut Vec<VariableReport>,
+    first: Option<Token<'_>>,
+    each: Option<&str>,
+) -> Result<()> {
+    let mut helper: Option<&str> = None;
+    let mut position = 0;
+    let mut current = first;
+    while let Some(token) = current {
+        match token.token_type {
+            TokenType::Variable => {
+                let value = token.value;
+                for (pos, _) in value.match_indices("?.") {
+                    report_usage(report, &value[..pos], UsageKind::Optional);
+                }
+                if let Some(pos) = value.find(".[") {
+                    report_usage(report, &value[..pos], UsageKind::Index);
+                }
+                if position == 0 {
+                    helper = Some(value);
+                } else if position == 1 {
+                    match helper {
+                        Some("lookup") => report_usage(report, value, UsageKind::Index),
+                        Some("dig") => report_usage(report, value, UsageKind::Map),
+                        Some("try_lookup") => {
+                            let kind = match token.next()? {
+                                Some(key) if matches!(key.token_type, TokenType::Literal) => {
+                                    UsageKind::Map
+                                }
+                                _ => UsageKind::Index,
+                            };
+                            report_usage(report, value, kind);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            TokenType::PrivateVariable if token.value.trim_start_matches('@') == "key" => {
+                if let Some(each) = each {
+                    report_usage(report, each, UsageKind::Map);
+                }
+            }
+            TokenType::SubExpression(content) => {
+                report_tokens(report, Token::first(content)?, each)?;
+            }
+            _ => {}
+        }
+        position += 1;
+        current = token.next()?;
+    }
+    Ok(())
+}
+
+/// Local variable declaration in a block
+pub enum Local {
+    /// Named local variable: `as name`
+    As(String),
+    /// Named key and value of a map entry: `as |key value|`
+    Pair(String, String),
+    /// This context: `this`
+    This,
+    /// No local variable
+    None,
+}
+
+/// A scope in the template
+pub struct Scope {
+    /// The block that opened this scope
+    pub opened: Box<dyn Block>,
+    /// The depth of this scope
+    pub depth: usize,
+}
+
+/// A pending write operation
+enum PendingWrite<'a> {
+    /// Raw text to write
+    Raw(&'a str),
+    /// Expression to evaluate and write
+    Expression((Expression<'a>, &'static str, &'static str)),
+    Format((&'a str, &'a str, &'a str)),
+}
+
+/// Rust code generation state, and the result of compiling a template
+///
+/// The code is the body of a function writing the template with `write_var_name` and reading
+/// its variables from `root_var_name`, [`Rust::to_function`] wraps it in one.
+#[derive(Debug, Clone)]
+pub struct Rust {
+    /// Set of used traits
+    pub(crate) using: HashSet<String>,
+    /// Generated code
+    pub(crate) code: String,
+    /// Top level variables
+    pub(crate) top_level_vars: HashSet<String>,
+    /// Top level variables formatted with `Debug` by the debug helper
+    pub(crate) debug_vars: HashSet<String>,
+    /// The code writes `nonce`, the per-render nonce given to `render_with_nonce`
+    pub(crate) nonce: bool,
+    /// The code reads `cache`, the fragment cache given to `render_with_cache`
+    pub(crate) cached: bool,
+    /// The code buffers the output of defer blocks in `deferred`
+    pub(crate) deferred: bool,
+    /// `{{deferred}}` was written, a defer block after it would never be output
+    pub(crate) deferred_written: bool,
+    /// Number of defer blocks open
+    pub(crate) defer_depth: usize,
+    /// Length of the static text written, text in loops and conditions counted once
+    pub(crate) static_len: usize,
+    /// Number of values written, each counted once like the static text
+    pub(crate) value_writes: usize,
+    /// Hash arguments passed to helper functions, see [`Rust::hash_args`]
+    pub(crate) hash_args: Vec<(String, usize, String)>,
+}
+
+/// Trait for raw output, none is needed as values implement `Display`
+pub static USE_AS_DISPLAY: &str = "";
+/// Trait for HTML escaped output
+pub static USE_AS_DISPLAY_HTML: &str = "AsDisplayHtml";
+/// Postfix applying HTML escaping to a value
+pub static AS_DISPLAY_HTML: &str = ".as_display_html()";
+/// Built in helpers returning an iterator, which each iterates by value
+pub static ITERATOR_HELPERS: [&str; 4] = ["chunks", "filter", "sort_by", "take"];
+/// Built in helpers returning an `Option`, which with opens like `if_some`
+pub static OPTIONAL_HELPERS: [&str; 3] = ["dig", "last", "try_lookup"];
+/// Built in helpers returning already escaped HTML
+pub static SAFE_HELPERS: [&str; 4] = ["markdown", "nl2br", "sanitize", "time"];
+/// Trait for output starting a URL attribute value
+pub static USE_AS_DISPLAY_URL: &str = "AsDisplayUrl";
+/// Postfix rejecting unsafe URL schemes and applying HTML escaping to a value
+pub static AS_DISPLAY_URL: &str = ".as_display_url()";
+
+/// Helper for formatting use statements
+pub struct Uses<'a> {
+    uses: &'a HashSet<String>,
+    crate_name: &'a str,
+}
+
+impl<'a> Display for Uses<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.uses.len() {
+            0 => (),
+            1 => write!(
+                f,
+                "use {}::{}",
+                self.crate_name,
+                self.uses.iter().next().unwrap()
+            )?,
+            _ => {
+                f.write_str("use ")?;
+                f.write_str(self.crate_name)?;
+                f.write_str("::")?;
+                let mut glue = '{';
+                for use_ in self.uses {
+                    f.write_char(glue)?;
+                    f.write_str(use_)?;
+                    glue = ',';
+                }
+                f.write_str("}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Rust {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rust {
+    /// Creates a new Rust code generator
+    pub fn new() -> Self {
+        Self {
+            using: HashSet::new(),
+            code: String::new(),
+            top_level_vars: HashSet::new(),
+            debug_vars: HashSet::new(),
+            nonce: false,
+            cached: false,
+            deferred: false,
+            deferred_written: false,
+            defer_depth: 0,
+            static_len: 0,
+            value_writes: 0,
+            hash_args: Vec::new(),
+        }
+    }
+
+    /// Returns a formatter for use statements
+    pub fn uses<'a>(&'a self, crate_name: &'a str) -> Uses<'a> {
+        Uses {
+            uses: &self.using,
+            crate_name,
+        }
+    }
+
+    /// Generated code, the body of the rendering function
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Traits of `dry_handlebars` the code needs in scope, see [`Rust::uses`]
+    pub fn using(&self) -> &HashSet<String> {
+        &self.using
+    }
+
+    /// Variables read from the root, with their path such as `person.name`
+    pub fn top_level_vars(&self) -> &HashSet<String> {
+        &self.top_level_vars
+    }
+
+    /// Variables formatted with `Debug` by the debug helper
+    pub fn debug_vars(&self) -> &HashSet<String> {
+        &self.debug_vars
+    }
+
+    /// Whether the code writes `nonce`, the per-render nonce given to `render_with_nonce`
+    pub fn nonce(&self) -> bool {
+        self.nonce
+    }
+
+    /// Whether the code reads `cache`, the fragment cache given to `render_with_cache`
+    pub fn cached(&self) -> bool {
+        self.cached
+    }
+
+    /// Length of the static text written, text in loops and conditions counted once
+    pub fn static_len(&self) -> usize {
+        self.static_len
+    }
+
+    /// Number of values written, each counted once like the static text
+    pub fn value_writes(&self) -> usize {
+        self.value_writes
+    }
+
+    /// Hash arguments passed to helper functions: the path of the function, the index of the
+    /// parameter the value is passed as and the key naming it, such as `("toggle", 0, "expanded")`
+    /// for `{{toggle expanded=false}}`
+    pub fn hash_args(&self) -> &[(String, usize, String)] {
+        &self.hash_args
+    }
+
+    /// The code being generated, for a [`BlockFactory`] or [`Block`] to write its own to
+    pub fn code_mut(&mut self) -> &mut String {
+        &mut self.code
+    }
+
+    /// Wraps the code in a complete function definition returning `std::fmt::Result`, such as
+    /// `to_function("render", "&self, f: &mut impl std::fmt::Write")`
+    ///
+    /// The signature binds the writer and root variable the code was compiled with and, when
+    /// the code reads them, `translator`, `nonce` and `cache`.
+    pub fn to_function(&self, name: &str, signature: &str) -> String {
+        let uses = self.uses("::dry_handlebars").to_string();
+        let mut function = format!(
+            "fn {}({}) -> std::fmt::Result {{\nuse std::fmt::Write;\n",
+            name, signature
+        );
+        if !uses.is_empty() {
+            function.push_str(&uses);
+            function.push_str(";\n");
+        }
+        function.push_str(&self.code);
+        function.push_str("\nOk(())\n}\n");
+        function
+    }
+}
+
+/// Trait for block helpers
+pub trait Block {
+    /// Handles block closing
+    fn handle_close(&self, rust: &mut Rust) {
+        rust.code.push('}');
+    }
+
+    /// Resolves a private variable
+    fn resolve_private<'a>(
+        &self,
+        _depth: usize,
+        expression: &'a Expression<'a>,
+        _name: &str,
+        _rust: &mut Rust,
+    ) -> Result<()> {
+        Err(ParseError::new(
+            &format!("{} not expected ", expression.content),
+            expression,
+        ))
+    }
+
+    /// Handles else block
+    fn handle_else<'a>(&self, expression: &'a Expression<'a>, _rust: &mut Rust) -> Result<()> {
+        Err(ParseError::new("else not expected here", expression))
+    }
+
+    /// Returns the this context
+    fn this(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the local variable
+    fn local(&self) -> &Local {
+        &Local::None
+    }
+}
+
+/// Trait for block helper factories
+pub trait BlockFactory {
+    /// Opens a new block
+    fn open<'a>(
+        &self,
+        compile: &'a Compile<'a>,
+        token: Token<'a>,
+        expression: &'a Expression<'a>,
+        rust: &mut Rust,
+    ) -> Result<Box<dyn Block>>;
+}
+
+/// Map of block helper names to factories
+pub type BlockMap = HashMap<&'static str, &'static dyn BlockFactory>;
+
+/// Compiler state
+pub struct Compile<'a> {
+    /// Stack of open blocks
+    pub open_stack: Vec<Scope>,
+    /// Map of block helpers
+    pub block_map: &'a BlockMap,
+    /// Types of variables
+    pub variable_types: &'a HashMap<String, String>,
+    /// Block helpers registered from user code, by name to function path
+    pub block_helpers: &'a HashMap<String, String>,
+    /// Inline helpers registered from user code, by name to function path
+    pub helpers: &'a HashMap<String, String>,
+    /// Name of the write function
+    pub write_var_name: &'static str,
+    /// Keys of the translation catalog
+    pub translation_keys: Option<&'a HashSet<String>>,
+    /// Render the debug helper in release builds too
+    pub debug_in_release: bool,
+    /// Variables of the root iterated by `each`
+    pub iterated: &'a HashSet<String>,
+    /// Variables written raw through `RenderableFragment`
+    pub fragments: &'a HashSet<String>,
+}
+
+/// Appends a depth suffix to a variable name
+pub fn append_with_depth(depth: usize, var: &str, buffer: &mut String) {
+    buffer.push_str(var);
+    buffer.push('_');
+    buffer.push_str(depth.to_string().as_str());
+}
+
+/// Reads the quoted field name argument of a collection helper, e.g. `"date"` or `"author.name"`
+pub fn field_argument<'a>(
+    helper: &str,
+    expression: &Expression<'a>,
+    token: Option<Token<'a>>,
+) -> Result<&'a str> {
+    let token = token.ok_or(ParseError::new(
+        &format!("{} expects a collection and a field name", helper),
+        expression,
+    ))?;
+    let field = match token.token_type {
+        TokenType::Literal => &token.value[1..token.value.len() - 1],
+        _ => "",
+    };
+    match !field.is_empty()
+        && field
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_'))
+    {
+        true => Ok(field),
+        false => Err(ParseError::new(
+            &format!(
+                "{} field must be a quoted field name, found {}",
+                helper, token.value
+            ),
+            expression,
+        )),
+    }
+}
+
+/// Returns the value token of a hash argument such as `expanded=false`
+pub fn hash_value<'a>(token: &Token<'a>) -> Result<Option<Token<'a>>> {
+    match token.token_type {
+        TokenType::Variable => match token.value.split_once('=') {
+            Some((_, value)) => match Token::first(value)? {
+                Some(value) => Ok(Some(value)),
+                None => Err(ParseError::at(
+                    format!("expected value after {}", token.value),
+                    token.value,
+                )),
+            },
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+/// A call to a helper function whose arguments are being written, hash arguments must follow
+/// the positional ones and are recorded with the index of the parameter they are passed as
+pub struct HelperCall<'a> {
+    /// Path of the function called
+    path: String,
+    /// Index of the parameter the next argument is passed as
+    index: usize,
+    /// Keys of the hash arguments written
+    keys: Vec<&'a str>,
+}
+
+impl<'a> HelperCall<'a> {
+    /// Starts a call to the function at `path`, its first argument passed as the parameter
+    /// `index`, 1 for a block helper taking the writer first
+    pub fn new(path: &str, index: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            index,
+            keys: Vec::new(),
+        }
+    }
+}
+
+/// Hash arguments of a block helper, such as `by_ref=false` in `{{#each items by_ref=false}}`,
+/// read by its factory to choose the code it generates
+pub struct HashArgs<'a> {
+    /// Arguments by key, with their value token
+    args: Vec<(&'a str, Token<'a>)>,
+    /// The last token read, followed by the block's `as |name|` if it has one
+    pub rest: Token<'a>,
+}
+
+impl<'a> HashArgs<'a> {
+    /// Reads the hash arguments following `token` up to the first other token, an argument
+    /// whose key isn't in `known` is an error naming the helper
+    pub fn read(
+        helper: &str,
+        token: &Token<'a>,
+        known: &[&str],
+        expression: &Expression<'a>,
+    ) -> Result<Self> {
+        let mut args = Vec::new();
+        let mut rest = token.clone();
+        while let Some(next) = rest.next()? {
+            let Some(value) = hash_value(&next)? else {
+                break;
+            };
+            let key = next.value.split_once('=').unwrap().0;
+            if !known.contains(&key) {
+                return Err(ParseError::new(
+                    &format!(
+                        "unknown argument {} of {}, expected {}",
+                        key,
+                        helper,
+                        known
+                            .iter()
+                            .map(|known| format!("{}=", known))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    expression,
+                ));
+            }
+            args.push((key, value));
+            rest = next;
+        }
+        Ok(Self { args, rest })
+    }
+
+    /// Returns the value of an argument
+    pub fn get(&self, key: &str) -> Option<&Token<'a>> {
+        self.args
+            .iter()
+            .find(|(arg, _)| *arg == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the value of a `key=true` or `key=false` argument, `default` when it isn't given
+    pub fn flag(&self, key: &str, default: bool, expression: &Expression<'a>) -> Result<bool> {
+        match self.get(key) {
+            Some(value) if matches!(value.token_type, TokenType::Boolean) => {
+                Ok(value.value == "true")
+            }
+            Some(value) => Err(ParseError::new(
+                &format!(
+                    "expected {}=true or {}=false, found {}",
+                    key, key, value.value
+                ),
+                expression,
+            )),
+            None => Ok(default),
+        }
+    }
+}
+
+/// An argument of the `class` helper
+enum ClassArg<'a> {
+    /// Always part of the list: a quoted name or a value
+    Always(Token<'a>),
+    /// Part of the list when the condition is true: `active=is_active` or `"card--error"=failed`
+    When(&'a str, Token<'a>),
+}
+
+/// Splits the arguments of the `class` helper, a quoted name followed by `=condition` is
+/// tokenized as the literal and `=condition`
+fn class_args<'a>(args: Token<'a>) -> Result<Vec<ClassArg<'a>>> {
+    let mut class_args = Vec::new();
+    let mut arg = Some(args);
+    while let Some(token) = arg {
+        let mut next = token.next()?;
+        let quoted_condition = match (&token.token_type, &next) {
+            (TokenType::Literal, Some(next)) => next.value.strip_prefix('='),
+            _ => None,
+        };
+        match (hash_value(&token)?, quoted_condition) {
+            (Some(condition), _) => {
+                let (name, _) = token.value.split_once('=').unwrap();
+                class_args.push(ClassArg::When(name, condition));
+            }
+            (None, Some(condition)) => {
+                let after = next.unwrap();
+                // a sub expression condition is the token after `=`
+                let separate = condition.is_empty();
+                let condition = match separate {
+                    true => after.next()?,
+                    false => Token::first(condition)?,
+                };
+                let condition = condition.ok_or_else(|| {
+                    ParseError::at(
+                        format!("expected condition after {}=", token.value),
+                        token.value,
+                    )
+                })?;
+                let rest = match separate {
+                    true => condition.next()?,
+                    false => after.next()?,
+                };
+                class_args.push(ClassArg::When(
+                    &token.value[1..token.value.len() - 1],
+                    condition,
+                ));
+                next = rest;
+            }
+            (None, None) => class_args.push(ClassArg::Always(token)),
+        }
+        arg = next;
+    }
+    Ok(class_args)
+}
+
+/// Escapes text for a plain string literal
+fn escape_literal(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Returns the distance from the end for a negative integer index such as `-1`
+fn negative_index<'a>(token: &Token<'a>) -> Option<&'a str> {
+    match token.token_type {
+        TokenType::Integer => token.value.strip_prefix('-'),
+        _ => None,
+    }
+}
+
+/// Splits a path at its first literal segment: `items.[0].name` -> (`items`, `.[0].name`)
+fn split_index_segments(var: &str) -> (&str, &str) {
+    match var.find(".[") {
+        Some(pos) => (&var[..pos], &var[pos..]),
+        None => (var, ""),
+    }
+}
+
+/// Writes the path segments following the first literal segment, turning `.[0]` into `[0]`
+fn write_index_segments(
+    expression: &Expression<'_>,
+    mut rest: &str,
+    rust: &mut Rust,
+) -> Result<()> {
+    while let Some(tail) = rest.strip_prefix('.') {
+        if let Some(tail) = tail.strip_prefix('[') {
+            let close = tail
+                .find(']')
+                .ok_or_else(|| ParseError::new("unclosed [ in path", expression))?;
+            let index = &tail[..close];
+            if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+                return Err(ParseError::new(
+                    &format!("path segment [{}] must be an integer index", index),
+                    expression,
+                ));
+            }
+            rust.code.push('[');
+            rust.code.push_str(index);
+            rust.code.push(']');
+            rest = &tail[close + 1..];
+        } else {
+            let end = tail.find('.').unwrap_or(tail.len());
+            rust.code.push('.');
+            rust.code.push_str(&tail[..end]);
+            rest = &tail[end..];
+        }
+    }
+    if !rest.is_empty() {
+        return Err(ParseError::new(
+            &format!("unexpected {} in path", rest),
+            expression,
+        ));
+    }
+    Ok(())
+}
+
+/// Root block implementation
+struct Root<'a> {
+    this: Option<&'a str>,
+}
+
+impl<'a> Block for Root<'a> {
+    fn this<'b>(&self) -> Option<&str> {
+        self.this
+    }
+}
+
+impl<'a> Compile<'a> {
+    /// Creates a new compiler
+    fn new(
+        options: &'a Options,
+        block_map: &'a BlockMap,
+        variable_types: &'a HashMap<String, String>,
+        iterated: &'a HashSet<String>,
+    ) -> Self {
+        Self {
+            open_stack: vec![Scope {
+                depth: 0,
+                opened: Box::new(Root {
+                    this: options.root_var_name,
+                }),
+            }],
+            block_map,
+            variable_types,
+            block_helpers: &options.block_helpers,
+            helpers: &options.helpers,
+            write_var_name: options.write_var_name,
+            translation_keys: options.translation_keys.as_ref(),
+            debug_in_release: options.debug_in_release,
+            iterated,
+            fragments: &options.fragments,
+        }
+    }
+
+    /// Finds the scope for a variable
+    fn find_scope(&self, var: &'a str) -> Result<(&'a str, &Scope)> {
+        let mut scope = self.open_stack.last().unwrap();
+        let mut local = var;
+        while local.starts_with("../") {
+            match scope.depth {
+                0 => {
+                    return Err(ParseError::at(
+                        format!("unable to resolve scope for {}", var),
+                        var,
+                    ));
+                }
+                _ => {
+                    local = &local[3..];
+                    scope = self.open_stack.get(scope.depth - 1).unwrap();
+                }
+            }
+        }
+        Ok((local, scope))
+    }
+
+    /// Returns the mapped type of a variable that resolves to a field of the root, `None` for
+    /// a local or a property of a block's context
+    pub fn root_type(&self, var: &Token<'a>) -> Option<&'a str> {
+        let variable_types: &'a HashMap<String, String> = self.variable_types;
+        self.root_var(var)
+            .and_then(|var| variable_types.get(var).map(String::as_str))
+    }
+
+    /// Returns the path of a variable from the root, `None` for a local or a property of a
+    /// block's context
+    pub fn root_var(&self, var: &Token<'a>) -> Option<&str> {
+        if !matches!(var.token_type, TokenType::Variable) {
+            return None;
+        }
+        let (mut var, mut scope) = self.find_scope(var.value).ok()?;
+        while scope.depth > 0 {
+            let root = var.split(['.', '[']).next().unwrap_or(var);
+            let shadowed = match scope.opened.local() {
+                Local::As(local) => root == local,
+                Local::Pair(key, value) => root == key || root == value,
+                Local::This => true,
+                Local::None => false,
+            };
+            if shadowed {
+                return None;
+            }
+            if let Some(this) = scope.opened.this() {
+                if var != this {
+                    return None;
+                }
+                var = this;
+            }
+            scope = &self.open_stack[scope.depth - 1];
+        }
+        Some(var)
+    }
+
+    /// Resolves a local variable
+    fn resolve_local(
+        &self,
+        depth: usize,
+        var: &'a str,
+        local: &'a str,
+        buffer: &mut String,
+    ) -> bool {
+        if var.starts_with(local) {
+            let len = local.len();
+            if var.len() > len {
+                if &var[len..len + 1] != "." {
+                    return false;
+                }
+                append_with_depth(depth, local, buffer);
+                buffer.push_str(&var[len..]);
+            } else {
+                append_with_depth(depth, local, buffer);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Resolves a variable in a scope
+    fn resolve_var(&self, var: &'a str, scope: &Scope, rust: &mut Rust) -> Result<()> {
+        if scope.depth == 0 {
+            if let Some(this) = scope.opened.this() {
+                rust.code.push_str(this);
+                rust.code.push('.');
+            }
+            match var.split_once('.') {
+                // properties of trait objects are read through their methods
+                Some((root, rest))
+                    if self
+                        .variable_types
+                        .get(root)
+                        .is_some_and(|ty| ty.starts_with("dyn ")) =>
+                {
+                    let (method, rest) = rest.split_once('.').unwrap_or((rest, ""));
+                    write!(rust.code, "{}.{}()", root, method).unwrap();
+                    if !rest.is_empty() {
+                        rust.code.push('.');
+                        rust.code.push_str(rest);
+                    }
+                }
+                _ => rust.code.push_str(var),
+            }
+            rust.top_level_vars.insert(var.to_string());
+            return Ok(());
+        }
+        if match scope.opened.local() {
+            Local::As(local) => self.resolve_local(scope.depth, var, local, &mut rust.code),
+            Local::Pair(key, value) => {
+                self.resolve_local(scope.depth, var, key, &mut rust.code)
+                    || self.resolve_local(scope.depth, var, value, &mut rust.code)
+            }
+            Local::This => {
+                rust.code.push_str("this_");
+                rust.code.push_str(scope.depth.to_string().as_str());
+                if var != "this" {
+                    rust.code.push('.');
+                    rust.code.push_str(var);
+                }
+                true
+            }
+            Local::None => false,
+        } {
+            return Ok(());
+        }
+        let parent = &self.open_stack[scope.depth - 1];
+        if let Some(this) = scope.opened.this() {
+            self.resolve_var(this, parent, rust)?;
+            if var != this {
+                rust.code.push('.');
+                rust.code.push_str(var);
+            }
+        } else {
+            self.resolve_var(var, parent, rust)?;
+        }
+        Ok(())
+    }
+
+    /// Resolves a sub-expression
+    fn resolve_sub_expression(&self, raw: &str, value: &str, rust: &mut Rust) -> Result<()> {
+        self.resolve(
+            &Expression {
+                expression_type: ExpressionType::Raw,
+                prefix: "",
+                content: value,
+                postfix: "",
+                raw,
+            },
+            rust,
+        )
+    }
+
+    /// Writes a variable expression
+    pub fn write_var(
+        &self,
+        expression: &Expression<'a>,
+        rust: &mut Rust,
+        var: &Token<'a>,
+    ) -> Result<()> {
+        match var.token_type {
+            TokenType::PrivateVariable => {
+                let (name, scope) = self.find_scope(var.value)?;
+                scope
+                    .opened
+                    .resolve_private(scope.depth, expression, name, rust)?;
+            }
+            TokenType::Variable => {
+                let (path, segments) = split_index_segments(var.value);
+                let (name, scope) = self.find_scope(path)?;
+                self.resolve_var(name, scope, rust)?;
+                write_index_segments(expression, segments, rust)?;
+            }
+            TokenType::Literal | TokenType::Integer | TokenType::Float | TokenType::Boolean => {
+                rust.code.push_str(var.value);
+            }
+            TokenType::Null => {
+                rust.code.push_str("Option::None");
+            }
+            TokenType::SubExpression(raw) => {
+                self.resolve_sub_expression(raw, var.value, rust)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks if a token is a sub-expression calling a built in helper returning an iterator
+    pub fn is_iterator(&self, token: &Token<'a>) -> Result<bool> {
+        Ok(match token.token_type {
+            TokenType::SubExpression(_) => Token::first(token.value)?
+                .is_some_and(|helper| ITERATOR_HELPERS.contains(&helper.value)),
+            _ => false,
+        })
+    }
+
+    /// Checks if a token is a sub-expression calling a built in helper returning an `Option`
+    pub fn is_optional_helper(&self, token: &Token<'a>) -> Result<bool> {
+        Ok(match token.token_type {
+            TokenType::SubExpression(_) => Token::first(token.value)?
+                .is_some_and(|helper| OPTIONAL_HELPERS.contains(&helper.value)),
+            _ => false,
+        })
+    }
+
+    /// Writes a collection as an iterator over its items by reference, a helper returning an
+    /// iterator is written as is
+    fn write_iter(
+        &self,
+        expression: &Expression<'a>,
+        rust: &mut Rust,
+        var: &Token<'a>,
+    ) -> Result<()> {
+        if self.is_iterator(var)? {
+            return self.write_var(expression, rust, var);
+        }
+        rust.code.push_str("(&");
+        self.write_var(expression, rust, var)?;
+        rust.code.push_str(").into_iter()");
+        Ok(())
+    }
+
+    /// Resolves `concat "btn btn-" variant` into one `format_args!`, string literals become
+    /// part of the format string
+    fn resolve_concat(
+        &self,
+        expression: &Expression<'a>,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut format = String::new();
+        let mut values = Rust::new();
+        let mut arg = Some(args);
+        while let Some(token) = arg {
+            match token.token_type {
+                TokenType::Literal => {
+                    let text = &token.value[1..token.value.len() - 1];
+                    format.push_str(&text.replace('{', "{{").replace('}', "}}"));
+                }
+                _ => {
+                    format.push_str("{}");
+                    values.code.push_str(", ");
+                    self.write_var(expression, &mut values, &token)?;
+                }
+            }
+            arg = token.next()?;
+        }
+        rust.using.extend(values.using);
+        rust.nonce |= values.nonce;
+        rust.top_level_vars.extend(values.top_level_vars);
+        write!(rust.code, "format_args!(\"{}\"{})", format, values.code).unwrap();
+        Ok(())
+    }
+
+    /// Resolves `class "card" active=is_active` into a `ClassList` of the names and values
+    /// paired with the conditions including them
+    fn resolve_class(
+        &self,
+        expression: &Expression<'a>,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        rust.using.insert("ClassList".to_string());
+        rust.code.push_str("ClassList(&[");
+        for arg in class_args(args)? {
+            rust.code.push_str("(&");
+            match arg {
+                ClassArg::Always(token) => {
+                    self.write_var(expression, rust, &token)?;
+                    rust.code.push_str(" as &dyn ::std::fmt::Display, true");
+                }
+                ClassArg::When(name, condition) => {
+                    write!(
+                        rust.code,
+                        "\"{}\" as &dyn ::std::fmt::Display, ",
+                        escape_literal(name)
+                    )
+                    .unwrap();
+                    self.write_var(expression, rust, &condition)?;
+                }
+            }
+            rust.code.push_str("),");
+        }
+        rust.code.push_str("])");
+        Ok(())
+    }
+
+    /// Resolves `log "rendering user" user.id level="info"` into a call of the `log` crate's
+    /// macro for the level (`debug` by default) logging its arguments joined by spaces, the
+    /// helper itself writes nothing
+    fn resolve_log(
+        &self,
+        expression: &Expression<'a>,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut level = "debug";
+        let mut parts = Vec::new();
+        let mut values = Rust::new();
+        let mut arg = Some(args);
+        while let Some(token) = arg {
+            match (
+                token.value.split_once('=').map(|(key, _)| key),
+                hash_value(&token)?,
+            ) {
+                (Some("level"), Some(value)) => {
+                    level = match value.value.trim_matches('"') {
+                        level @ ("trace" | "debug" | "info" | "warn" | "error") => level,
+                        _ => {
+                            return Err(ParseError::new(
+                                &format!(
+                                    "unknown log level {}, expected \"trace\", \"debug\", \"info\", \"warn\" or \"error\"",
+                                    value.value
+                                ),
+                                expression,
+                            ));
+                        }
+                    }
+                }
+                (Some(key), Some(_)) => {
+                    return Err(ParseError::new(
+                        &format!("unexpected log argument {}=, expected level=", key),
+                        expression,
+                    ));
+                }
+                _ if matches!(token.token_type, TokenType::Literal) => {
+                    let text = &token.value[1..token.value.len() - 1];
+                    parts.push(text.replace('{', "{{").replace('}', "}}"));
+                }
+                _ => {
+                    parts.push("{}".to_string());
+                    values.code.push_str(", ");
+                    self.write_var(expression, &mut values, &token)?;
+                }
+            }
+            arg = token.next()?;
+        }
+        rust.using.extend(values.using);
+        rust.nonce |= values.nonce;
+        rust.top_level_vars.extend(values.top_level_vars);
+        write!(
+            rust.code,
+            "{{::log::{}!(\"{}\"{});\"\"}}",
+            level,
+            parts.join(" "),
+            values.code
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// Resolves `(sort_by items "field" desc=true)` into the items collected and sorted by a
+    /// field, values that can't be compared (such as NaN) are left in place
+    fn resolve_sort_by(
+        &self,
+        expression: &Expression<'a>,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let key = args.next()?;
+        let field = field_argument("sort_by", expression, key.clone())?;
+        let desc = match key.unwrap().next()? {
+            Some(order) => match (order.value.split_once('='), hash_value(&order)?) {
+                (Some(("desc", _)), Some(value))
+                    if matches!(value.token_type, TokenType::Boolean) =>
+                {
+                    value.value == "true"
+                }
+                _ => {
+                    return Err(ParseError::new(
+                        &format!(
+                            "unexpected sort_by argument {}, expected desc=true or desc=false",
+                            order.value
+                        ),
+                        expression,
+                    ));
+                }
+            },
+            None => false,
+        };
+        let (first, second) = if desc { ("b", "a") } else { ("a", "b") };
+        rust.code.push_str("{let mut items: Vec<_> = ");
+        self.write_iter(expression, rust, &args)?;
+        write!(
+            rust.code,
+            ".collect();items.sort_by(|a, b| {first}.{field}.partial_cmp(&{second}.{field}).unwrap_or(std::cmp::Ordering::Equal));items.into_iter()}}"
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// Writes the next argument of a helper call, a hash argument (`key=value`) is passed as
+    /// the parameter at its position and recorded in [`Rust::hash_args`] so the macros check
+    /// the parameter has its key as name
+    pub fn write_arg(
+        &self,
+        expression: &Expression<'a>,
+        rust: &mut Rust,
+        arg: &Token<'a>,
+        call: &mut HelperCall<'a>,
+    ) -> Result<()> {
+        let index = call.index;
+        call.index += 1;
+        match hash_value(arg)? {
+            Some(value) => {
+                let key = arg.value.split_once('=').unwrap().0;
+                if call.keys.contains(&key) {
+                    return Err(ParseError::new(
+                        &format!("{}= is given twice to {}", key, call.path),
+                        expression,
+                    ));
+                }
+                call.keys.push(key);
+                rust.hash_args
+                    .push((call.path.clone(), index, key.to_string()));
+                self.write_var(expression, rust, &value)
+            }
+            None if !call.keys.is_empty() => Err(ParseError::new(
+                &format!(
+                    "{} follows the hash arguments of {}, hash arguments come last",
+                    arg.value, call.path
+                ),
+                expression,
+            )),
+            None => self.write_var(expression, rust, arg),
+        }
+    }
+
+    /// Handles an else block
+    fn handle_else(&self, expression: &Expression<'a>, rust: &mut Rust) -> Result<()> {
+        match self.open_stack.last() {
+            Some(scope) => scope.opened.handle_else(expression, rust),
+            None => Err(ParseError::new("else not expected here", expression)),
+        }
+    }
+
+    /// Resolves a lookup expression
+    fn resolve_lookup(
+        &self,
+        expression: &Expression<'a>,
+        prefix: &str,
+        postfix: char,
+        args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let index = args
+            .next()?
+            .ok_or(ParseError::new("lookup expects 2 arguments", expression))?;
+        if let TokenType::Float = index.token_type {
+            return Err(ParseError::new(
+                &format!("lookup index must be an integer, found {}", index.value),
+                expression,
+            ));
+        }
+        if let Some(from_end) = negative_index(&index) {
+            // counts from the end, None when the collection is too short
+            self.write_var(expression, rust, &args)?;
+            write!(rust.code, ".len().checked_sub({}).and_then(|i| ", from_end).unwrap();
+            self.write_var(expression, rust, &args)?;
+            rust.code.push_str(".get(i))");
+            return Ok(());
+        }
+        self.write_var(expression, rust, &args)?;
+        rust.code.push_str(prefix);
+        self.write_var(expression, rust, &index)?;
+        rust.code.push(postfix);
+        Ok(())
+    }
+
+    /// Resolves a dig helper into successive `get` calls through nested collections, e.g.
+    /// `config.get("server").and_then(|v| v.get("port"))`, `None` when any key is missing
+    fn resolve_dig(
+        &self,
+        expression: &Expression<'a>,
+        collection: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut keys = Vec::new();
+        let mut last = collection.clone();
+        while let Some(next) = last.next()? {
+            keys.push(next.clone());
+            last = next;
+        }
+        if keys.is_empty() {
+            return Err(ParseError::new(
+                "dig expects a collection and at least one key",
+                expression,
+            ));
+        }
+        self.write_var(expression, rust, &collection)?;
+        for (i, key) in keys.iter().enumerate() {
+            rust.code.push_str(if i == 0 {
+                ".get("
+            } else {
+                ".and_then(|v| v.get("
+            });
+            self.write_var(expression, rust, key)?;
+            rust.code.push_str(if i == 0 { ")" } else { "))" });
+        }
+        Ok(())
+    }
+
+    /// Resolves an arithmetic helper into plain Rust arithmetic, when any operand is a float
+    /// literal the other operands are cast with `as f64` so integers and floats can be mixed
+    fn resolve_arithmetic(
+        &self,
+        expression: &Expression<'a>,
+        operator: &str,
+        method: &str,
+        first: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut operands = vec![first];
+        while let Some(next) = operands.last().unwrap().next()? {
+            operands.push(next);
+        }
+        if operands.len() < 2 {
+            return Err(ParseError::new(
+                "arithmetic helpers expect at least two operands",
+                expression,
+            ));
+        }
+        // a float literal or a variable mapped to a float makes it float arithmetic
+        let float = operands.iter().any(|operand| {
+            matches!(operand.token_type, TokenType::Float)
+                || (!operand.value.contains('.')
+                    && matches!(self.root_type(operand), Some("f32" | "f64")))
+        });
+        let literals = operands
+            .iter()
+            .all(|operand| matches!(operand.token_type, TokenType::Integer | TokenType::Float));
+        if float || literals {
+            rust.code.push('(');
+            for (i, operand) in operands.iter().enumerate() {
+                if i > 0 {
+                    rust.code.push_str(operator);
+                }
+                let cast = float && !matches!(operand.token_type, TokenType::Float);
+                if cast {
+                    rust.code.push('(');
+                }
+                self.write_var(expression, rust, operand)?;
+                if cast {
+                    rust.code.push_str(" as f64)");
+                }
+            }
+            rust.code.push(')');
+            return Ok(());
+        }
+        // the usize `@index` counter saturates (`{{sub @index 1}}` is 0 for the first item),
+        // other integer overflow and division by zero fail the render instead of panicking
+        let counter = operands.iter().any(|operand| {
+            matches!(operand.token_type, TokenType::PrivateVariable)
+                && operand.value.trim_start_matches("../") == "index"
+        });
+        let saturating = counter && matches!(method, "add" | "sub" | "mul");
+        rust.code.push('(');
+        self.write_var(expression, rust, &operands[0])?;
+        rust.code.push(')');
+        for operand in &operands[1..] {
+            match saturating {
+                true => write!(rust.code, ".saturating_{}(", method).unwrap(),
+                false => write!(rust.code, ".checked_{}(", method).unwrap(),
+            }
+            self.write_var(expression, rust, operand)?;
+            match saturating {
+                true => rust.code.push(')'),
+                false => rust.code.push_str(").ok_or(::std::fmt::Error)?"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a variable as a condition, a collection iterated by each is a condition on not
+    /// being empty, an optional value on being present
+    pub fn write_condition(
+        &self,
+        expression: &Expression<'a>,
+        rust: &mut Rust,
+        var: &Token<'a>,
+    ) -> Result<()> {
+        if matches!(var.token_type, TokenType::PrivateVariable)
+            && var.value.trim_start_matches("../") == "index"
+        {
+            // as in Handlebars the first item's index is falsy
+            rust.code.push('(');
+            self.write_var(expression, rust, var)?;
+            rust.code.push_str(" != 0)");
+        } else if self
+            .root_var(var)
+            .is_some_and(|name| self.iterated.contains(name))
+        {
+            rust.code.push_str("(&");
+            self.write_var(expression, rust, var)?;
+            rust.code.push_str(").into_iter().next().is_some()");
+        } else if let Some(zero) = self
+            .root_type(var)
+            .filter(|_| !var.value.contains('.'))
+            .and_then(numeric_zero)
+        {
+            // as in Handlebars zero is falsy
+            rust.code.push('(');
+            self.write_var(expression, rust, var)?;
+            write!(rust.code, " != {})", zero).unwrap();
+        } else {
+            self.write_var(expression, rust, var)?;
+            match self.root_type(var) {
+                Some(ty) if ty.starts_with("Option") => rust.code.push_str(".is_some()"),
+                // a generic field mapped to `impl AsBool` tells whether it's truthy
+                Some(ty) if ty.starts_with("impl ") && !var.value.contains('.') => {
+                    rust.code.push_str(".as_bool()");
+                    rust.using.insert("AsBool".to_string());
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves an inline unless helper into the value when the condition is falsy and an
+    /// empty string otherwise, e.g. `[&"hidden" as &dyn Display, &""][if open {1} else {0}]`,
+    /// the condition tested like the one of an unless block
+    fn resolve_unless(
+        &self,
+        expression: &Expression<'a>,
+        condition: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let value = match condition.next()? {
+            Some(value) if value.next()?.is_none() => value,
+            _ => {
+                return Err(ParseError::new(
+                    "unless expects a condition and a value",
+                    expression,
+                ));
+            }
+        };
+        rust.code.push_str("[&");
+        self.write_var(expression, rust, &value)?;
+        rust.code
+            .push_str(" as &dyn ::std::fmt::Display, &\"\" as &dyn ::std::fmt::Display][if ");
+        self.write_condition(expression, rust, &condition)?;
+        rust.code.push_str(" {1} else {0}]");
+        Ok(())
+    }
+
+    /// Resolves a cycle helper into the value at the index modulo the number of values, e.g.
+    /// `[&"odd", &"even"][(i) as usize % 2]`
+    fn resolve_cycle(
+        &self,
+        expression: &Expression<'a>,
+        index: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut values = Vec::new();
+        let mut last = index.clone();
+        while let Some(next) = last.next()? {
+            values.push(next.clone());
+            last = next;
+        }
+        if values.is_empty() {
+            return Err(ParseError::new(
+                "cycle expects an index and at least one value",
+                expression,
+            ));
+        }
+        rust.code.push('[');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                rust.code.push_str(", ");
+            }
+            rust.code.push('&');
+            self.write_var(expression, rust, value)?;
+        }
+        rust.code.push_str("][(");
+        self.write_var(expression, rust, &index)?;
+        write!(rust.code, ") as usize % {}]", values.len()).unwrap();
+        Ok(())
+    }
+
+    /// Resolves a format_date helper into a call to the date crate enabled by a feature, the
+    /// format must be a string literal and is checked at compile time
+    fn resolve_format_date(
+        &self,
+        expression: &Expression<'a>,
+        value: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let format = match value.next()? {
+            Some(format)
+                if matches!(format.token_type, TokenType::Literal)
+                    && format.value.len() > 1
+                    && format.value.starts_with('"')
+                    && format.value.ends_with('"') =>
+            {
+                &format.value[1..format.value.len() - 1]
+            }
+            _ => {
+                return Err(ParseError::new(
+                    "format_date expects a string literal format",
+                    expression,
+                ));
+            }
+        };
+        let Some(backend) = Backend::enabled() else {
+            return Err(ParseError::new(
+                "format_date requires the chrono or time feature",
+                expression,
+            ));
+        };
+        let code = backend
+            .format_code(format)
+            .map_err(|message| ParseError::new(&message, expression))?;
+        self.write_var(expression, rust, &value)?;
+        rust.code.push_str(&code);
+        Ok(())
+    }
+
+    /// Resolves a num helper into `Num(&value, &sep, precision)`, `sep` and `precision` are
+    /// optional hash arguments
+    fn resolve_num(
+        &self,
+        expression: &Expression<'a>,
+        value: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut sep = None;
+        let mut precision = None;
+        let mut arg = value.next()?;
+        while let Some(token) = arg {
+            match (
+                token.value.split_once('=').map(|(key, _)| key),
+                hash_value(&token)?,
+            ) {
+                (Some("sep"), Some(value)) => sep = Some(value),
+                (Some("precision"), Some(value)) => precision = Some(value),
+                _ => {
+                    return Err(ParseError::new(
+                        &format!(
+                            "unexpected num argument {}, expected sep= or precision=",
+                            token.value
+                        ),
+                        expression,
+                    ));
+                }
+            }
+            arg = token.next()?;
+        }
+        rust.using.insert("Num".to_string());
+        rust.code.push_str("Num(&");
+        self.write_var(expression, rust, &value)?;
+        rust.code.push_str(", &");
+        match sep {
+            Some(sep) => self.write_var(expression, rust, &sep)?,
+            None => rust.code.push_str("\",\""),
+        }
+        rust.code.push_str(", ");
+        match precision {
+            Some(precision) => {
+                rust.code.push_str("Some(");
+                self.write_var(expression, rust, &precision)?;
+                rust.code.push(')');
+            }
+            None => rust.code.push_str("None"),
+        }
+        rust.code.push(')');
+        Ok(())
+    }
+
+    /// Resolves a translation helper into `Translated(translator, key, &[(name, &value), ..])`,
+    /// named hash arguments are passed to the translator for interpolation
+    fn resolve_translation(
+        &self,
+        expression: &Expression<'a>,
+        key: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        if let (Some(keys), TokenType::Literal) = (self.translation_keys, &key.token_type) {
+            let name = key.value.trim_matches('"');
+            if !keys.contains(name) {
+                return Err(ParseError::new(
+                    &format!("translation key \"{}\" is not in the i18n catalog", name),
+                    expression,
+                ));
+            }
+        }
+        rust.using.insert("Translated".to_string());
+        rust.code.push_str("Translated(translator, ");
+        self.write_var(expression, rust, &key)?;
+        rust.code.push_str(", &[");
+        let mut arg = key.next()?;
+        while let Some(token) = arg {
+            let (name, value) = match (token.value.split_once('='), hash_value(&token)?) {
+                (Some((name, _)), Some(value)) => (name, value),
+                _ => {
+                    return Err(ParseError::new(
+                        &format!("unexpected t argument {}, expected name=value", token.value),
+                        expression,
+                    ));
+                }
+            };
+            write!(rust.code, "(\"{}\", &", name).unwrap();
+            self.write_var(expression, rust, &value)?;
+            rust.code.push_str(" as &dyn std::fmt::Display), ");
+            arg = token.next()?;
+        }
+        rust.code.push_str("])");
+        Ok(())
+    }
+
+    /// Resolves a time helper into a `<time>` element with the value as an RFC 3339
+    /// `datetime` attribute and formatted for people, with `%B %-d, %Y` unless a format is given
+    fn resolve_time_element(
+        &self,
+        expression: &Expression<'a>,
+        value: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let format = match value.next()? {
+            None => "%B %-d, %Y",
+            Some(format)
+                if matches!(format.token_type, TokenType::Literal)
+                    && format.value.len() > 1
+                    && format.value.starts_with('"')
+                    && format.value.ends_with('"') =>
+            {
+                &format.value[1..format.value.len() - 1]
+            }
+            Some(_) => {
+                return Err(ParseError::new(
+                    "time expects a string literal format",
+                    expression,
+                ));
+            }
+        };
+        let Some(backend) = Backend::enabled() else {
+            return Err(ParseError::new(
+                "time requires the chrono or time feature",
+                expression,
+            ));
+        };
+        let human = backend
+            .format_code(format)
+            .map_err(|message| ParseError::new(&message, expression))?;
+        rust.code.push_str("{let value = &");
+        self.write_var(expression, rust, &value)?;
+        write!(
+            rust.code,
+            ";format!(\"<time datetime=\\\"{{}}\\\">{{}}</time>\", value{}, value{})}}",
+            backend.rfc3339_code(),
+            human
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// Resolves a default helper, every argument but the last is an `Option` and the first `Some`
+    /// value is rendered, otherwise the last argument is rendered as the fallback
+    fn resolve_default(
+        &self,
+        expression: &Expression<'a>,
+        first: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let mut options = vec![first];
+        while let Some(next) = options.last().unwrap().next()? {
+            options.push(next);
+        }
+        let fallback = match options.len() {
+            1 => {
+                return Err(ParseError::new(
+                    "default expects a value and a fallback",
+                    expression,
+                ));
+            }
+            _ => options.pop().unwrap(),
+        };
+        for (i, option) in options.iter().enumerate() {
+            if i > 0 {
+                rust.code.push_str(".or_else(|| ");
+            }
+            self.write_var(expression, rust, option)?;
+            rust.code
+                .push_str(".as_ref().map(|v| v as &dyn std::fmt::Display)");
+            if i > 0 {
+                rust.code.push(')');
+            }
+        }
+        rust.code.push_str(".unwrap_or(&");
+        self.write_var(expression, rust, &fallback)?;
+        rust.code.push(')');
+        Ok(())
+    }
+
+    /// Resolves a string helper, the value is converted with `to_string` and transformed
+    /// into a new `String` which is escaped like any other output
+    fn resolve_string_helper(
+        &self,
+        expression: &Expression<'a>,
+        name: &str,
+        value: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let arg = |token: &Token<'a>, position: &str| -> Result<Token<'a>> {
+            token.next()?.ok_or_else(|| {
+                ParseError::new(
+                    &format!("{} expects {} argument", name, position),
+                    expression,
+                )
+            })
+        };
+        match name {
+            "capitalize" => {
+                rust.code.push_str("{let s = ");
+                self.write_var(expression, rust, &value)?;
+                rust.code.push_str(
+                    ".to_string();let mut chars = s.chars();chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()}",
+                );
+            }
+            "replace" => {
+                let from = arg(&value, "a second")?;
+                let to = arg(&from, "a third")?;
+                self.write_var(expression, rust, &value)?;
+                rust.code.push_str(".to_string().replace(&");
+                self.write_var(expression, rust, &from)?;
+                rust.code.push_str(", &");
+                self.write_var(expression, rust, &to)?;
+                rust.code.push(')');
+            }
+            // the first characters followed by an ellipsis when the value is longer
+            "truncate" => {
+                let length = arg(&value, "a length")?;
+                rust.code.push_str("{let s = ");
+                self.write_var(expression, rust, &value)?;
+                rust.code
+                    .push_str(".to_string();match s.char_indices().nth(");
+                self.write_var(expression, rust, &length)?;
+                rust.code.push_str(
+                    " as usize){Some((at, _)) => format!(\"{}\u{2026}\", &s[..at]),None => s}}",
+                );
+            }
+            _ => {
+                self.write_var(expression, rust, &value)?;
+                rust.code.push_str(match name {
+                    "upper" => ".to_string().to_uppercase()",
+                    "lower" => ".to_string().to_lowercase()",
+                    _ => ".to_string().trim().to_string()",
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves a helper expression
+    fn resolve_helper(
+        &self,
+        expression: &Expression<'a>,
+        name: Token<'a>,
+        mut args: Token<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        match name.value {
+            "lookup" => self.resolve_lookup(expression, "[", ']', args, rust),
+            "try_lookup" => self.resolve_lookup(expression, ".get(", ')', args, rust),
+            "dig" => self.resolve_dig(expression, args, rust),
+            "last" => {
+                self.write_var(expression, rust, &args)?;
+                rust.code.push_str(".last()");
+                Ok(())
+            }
+            "len" | "count" => {
+                self.write_var(expression, rust, &args)?;
+                rust.code.push_str(".len()");
+                Ok(())
+            }
+            "is_empty" => {
+                self.write_var(expression, rust, &args)?;
+                rust.code.push_str(".is_empty()");
+                Ok(())
+            }
+            "chunks" => {
+                let size = args
+                    .next()?
+                    .ok_or(ParseError::new("chunks expects 2 arguments", expression))?;
+                if !matches!(size.token_type, TokenType::Integer) || size.value.starts_with('-') {
+                    return Err(ParseError::new(
+                        &format!(
+                            "chunks size must be a positive integer, found {}",
+                            size.value
+                        ),
+                        expression,
+                    ));
+                }
+                self.write_var(expression, rust, &args)?;
+                if self.is_iterator(&args)? {
+                    rust.code.push_str(".collect::<Vec<_>>()");
+                }
+                write!(rust.code, ".chunks({})", size.value).unwrap();
+                Ok(())
+            }
+            "take" => {
+                let count = args
+                    .next()?
+                    .ok_or(ParseError::new("take expects 2 arguments", expression))?;
+                self.write_iter(expression, rust, &args)?;
+                rust.code.push_str(".take(");
+                match count.token_type {
+                    TokenType::Integer => rust.code.push_str(count.value),
+                    _ => {
+                        rust.code.push('(');
+                        self.write_var(expression, rust, &count)?;
+                        rust.code.push_str(") as usize");
+                    }
+                }
+                rust.code.push(')');
+                Ok(())
+            }
+            "sort_by" => self.resolve_sort_by(expression, args, rust),
+            "filter" => {
+                let field = field_argument("filter", expression, args.next()?)?;
+                self.write_iter(expression, rust, &args)?;
+                write!(rust.code, ".filter(|item| item.{})", field).unwrap();
+                Ok(())
+            }
+            "concat" => self.resolve_concat(expression, args, rust),
+            "class" => self.resolve_class(expression, args, rust),
+            "log" if cfg!(feature = "log") => self.resolve_log(expression, args, rust),
+            "log" => Err(ParseError::new("log requires the log feature", expression)),
+            "debug" => {
+                let mut value = Rust::new();
+                self.write_var(expression, &mut value, &args)?;
+                rust.using.insert("Debugged".to_string());
+                rust.using.extend(value.using);
+                rust.nonce |= value.nonce;
+                rust.debug_vars.extend(value.top_level_vars.iter().cloned());
+                rust.top_level_vars.extend(value.top_level_vars);
+                write!(
+                    rust.code,
+                    "Debugged(&{}, {})",
+                    value.code,
+                    match self.debug_in_release {
+                        true => "true",
+                        false => "cfg!(debug_assertions)",
+                    }
+                )
+                .unwrap();
+                Ok(())
+            }
+            "default" => self.resolve_default(expression, args, rust),
+            "format_date" => self.resolve_format_date(expression, args, rust),
+            "time" => self.resolve_time_element(expression, args, rust),
+            "t" if cfg!(feature = "i18n") => self.resolve_translation(expression, args, rust),
+            "t" => Err(ParseError::new("t requires the i18n feature", expression)),
+            "num" => self.resolve_num(expression, args, rust),
+            "markdown" if cfg!(feature = "markdown") => {
+                // raw HTML in the markdown is written as text and links and images with an
+                // unsafe scheme such as `javascript:` lose their URL, so the output is safe
+                rust.code.push_str("{let source = ");
+                self.write_var(expression, rust, &args)?;
+                rust.code.push_str(
+                    ".to_string();let mut html = String::new();\
+                     ::pulldown_cmark::html::push_html(&mut html, ::pulldown_cmark::Parser::new(&source).map(|event| match event {\
+                     ::pulldown_cmark::Event::Html(raw) | ::pulldown_cmark::Event::InlineHtml(raw) => ::pulldown_cmark::Event::Text(raw),\
+                     ::pulldown_cmark::Event::Start(::pulldown_cmark::Tag::Link { link_type, dest_url, title, id }) => \
+                     ::pulldown_cmark::Event::Start(::pulldown_cmark::Tag::Link { link_type, dest_url: ::dry_handlebars::safe_url(dest_url.into_string()).into(), title, id }),\
+                     ::pulldown_cmark::Event::Start(::pulldown_cmark::Tag::Image { link_type, dest_url, title, id }) => \
+                     ::pulldown_cmark::Event::Start(::pulldown_cmark::Tag::Image { link_type, dest_url: ::dry_handlebars::safe_url(dest_url.into_string()).into(), title, id }),\
+                     event => event}));html}",
+                );
+                Ok(())
+            }
+            "markdown" => Err(ParseError::new(
+                "markdown requires the markdown feature",
+                expression,
+            )),
+            "sanitize" if cfg!(feature = "sanitize") => {
+                rust.code.push_str("::ammonia::clean(&");
+                self.write_var(expression, rust, &args)?;
+                rust.code.push_str(".to_string())");
+                Ok(())
+            }
+            "sanitize" => Err(ParseError::new(
+                "sanitize requires the sanitize feature",
+                expression,
+            )),
+            "urlencode" => {
+                rust.using.insert("UrlEncoded".to_string());
+                rust.code.push_str("UrlEncoded(&");
+                self.write_var(expression, rust, &args)?;
+                rust.code.push(')');
+                Ok(())
+            }
+            "nl2br" => {
+                rust.using.insert("Nl2Br".to_string());
+                rust.code.push_str("Nl2Br(&");
+                self.write_var(expression, rust, &args)?;
+                rust.code.push(')');
+                Ok(())
+            }
+            "cycle" => self.resolve_cycle(expression, args, rust),
+            "unless" => self.resolve_unless(expression, args, rust),
+            "add" => self.resolve_arithmetic(expression, " + ", "add", args, rust),
+            "sub" => self.resolve_arithmetic(expression, " - ", "sub", args, rust),
+            "mul" => self.resolve_arithmetic(expression, " * ", "mul", args, rust),
+            "div" => self.resolve_arithmetic(expression, " / ", "div", args, rust),
+            "mod" => self.resolve_arithmetic(expression, " % ", "rem", args, rust),
+            "upper" | "lower" | "capitalize" | "trim" | "replace" | "truncate" => {
+                self.resolve_string_helper(expression, name.value, args, rust)
+            }
+            name => {
+                // registered helpers are called by path with their arguments by reference
+                let (path, by_ref) = match self.helpers.get(name) {
+                    Some(path) => (path.as_str(), "&"),
+                    None => (name, ""),
+                };
+                let mut call = HelperCall::new(path, 0);
+                rust.code.push_str(path);
+                rust.code.push('(');
+                rust.code.push_str(by_ref);
+                self.write_arg(expression, rust, &args, &mut call)?;
+                loop {
+                    args = match args.next()? {
+                        Some(token) => {
+                            rust.code.push_str(", ");
+                            rust.code.push_str(by_ref);
+                            self.write_arg(expression, rust, &token, &mut call)?;
+                            token
+                        }
+                        None => {
+                            rust.code.push(')');
+                            return Ok(());
+                        }
+                    };
+                }
+            }
+        }
+    }
+
+    /// Resolves an expression
+    fn resolve(&self, expression: &Expression<'a>, rust: &mut Rust) -> Result<()> {
+        let token = match Token::first(expression.content)? {
+            Some(token) => token,
+            None => return Err(ParseError::new("expected token", expression)),
+        };
+        rust.code.push_str(expression.prefix);
+        if let TokenType::SubExpression(raw) = token.token_type {
+            self.resolve_sub_expression(raw, token.value, rust)?;
+        } else if let Some(args) = token.next()? {
+            self.resolve_helper(expression, token, args, rust)?;
+        } else if let (TokenType::Variable, Some(path)) =
+            (&token.token_type, self.helpers.get(token.value))
+        {
+            // registered helpers take precedence over variables of the same name
+            rust.code.push_str(path);
+            rust.code.push_str("()");
+        } else if token.value == "nonce" && !self.variable_types.contains_key("nonce") {
+            rust.code.push_str("nonce");
+            rust.nonce = true;
+        } else if token.value == "deferred" && !self.variable_types.contains_key("deferred") {
+            if rust.defer_depth > 0 {
+                return Err(ParseError::new(
+                    "deferred can't be written inside a defer block",
+                    expression,
+                ));
+            }
+            rust.code.push_str("deferred");
+            rust.deferred_written = true;
+        } else if let TokenType::Variable = token.token_type
+            && self.fragments.contains(token.value)
+        {
+            // a local of the same name must be a fragment too, it isn't escaped either
+            rust.using.insert("Fragment".to_string());
+            rust.code.push_str("Fragment(&");
+            self.write_var(expression, rust, &token)?;
+            rust.code.push(')');
+        } else {
+            self.write_var(expression, rust, &token)?;
+        }
+        rust.code.push_str(expression.postfix);
+        Ok(())
+    }
+
+    /// Writes a local variable declaration
+    pub fn write_local(&self, rust: &mut String, local: &Local) {
+        if let Local::Pair(key, value) = local {
+            rust.push('(');
+            append_with_depth(self.open_stack.len(), key, rust);
+            rust.push_str(", ");
+            append_with_depth(self.open_stack.len(), value, rust);
+            rust.push(')');
+            return;
+        }
+        append_with_depth(
+            self.open_stack.len(),
+            match local {
+                Local::As(local) => local,
+                _ => "this",
+            },
+            rust,
+        );
+    }
+
+    /// Closes a block
+    fn close(&mut self, expression: Expression<'a>, rust: &mut Rust) -> Result<()> {
+        let scope = self
+            .open_stack
+            .pop()
+            .ok_or_else(|| ParseError::new("Mismatched block helper", &expression))?;
+        scope.opened.handle_close(rust);
+        Ok(())
+    }
+
+    /// Opens a block
+    fn open(&mut self, expression: Expression<'a>, rust: &mut Rust) -> Result<()> {
+        let token = Token::first(expression.content)?
+            .ok_or_else(|| ParseError::new("expected token", &expression))?;
+        let opened = match self.block_map.get(token.value) {
+            Some(block) => block.open(self, token, &expression, rust)?,
+            None => match self.block_helpers.get(token.value) {
+                Some(path) => open_custom(path, self, token, &expression, rust)?,
+                None => {
+                    return Err(ParseError::new(
+                        &format!("unsupported block helper {}", token.value),
+                        &expression,
+                    ));
+                }
+            },
+        };
+        self.open_stack.push(Scope {
+            opened,
+            depth: self.open_stack.len(),
+        });
+        Ok(())
+    }
+}
+
+/// Rejects Handlebars syntax the compiler knows but doesn't support, naming the closest
+/// supported alternative rather than failing on it as an unknown helper or token
+fn check_supported(expression: &Expression<'_>) -> Result<()> {
+    let content = expression.content.trim();
+    let message = match expression.expression_type {
+        ExpressionType::Open if content.starts_with("*inline") => {
+            "inline partials ({{#*inline}}) are not supported, move the content to a `name.hbs` \
+             file next to the template and include it with {{> name}}"
+        }
+        ExpressionType::Open if content.starts_with('>') => {
+            "partial blocks ({{#> layout}}) are not supported, include the parts of the layout \
+             around the content instead, e.g. {{> header}} .. {{> footer}}"
+        }
+        ExpressionType::Open if content.starts_with('*') => {
+            "decorators ({{#*name}}) are not supported"
+        }
+        ExpressionType::HtmlEscaped | ExpressionType::Raw if content.starts_with('*') => {
+            "decorators ({{*name}}) are not supported"
+        }
+        ExpressionType::HtmlEscaped | ExpressionType::Raw if content.starts_with('>') => {
+            "partials ({{> name}}) are expanded by the dry-handlebars macros before compiling, \
+             the compiler doesn't read them, expand them in the source given to it"
+        }
+        ExpressionType::HtmlEscaped
+            if content.starts_with("else ") && content["else ".len()..].trim() != "" =>
+        {
+            "chained else ({{else if x}}) is not supported, nest the block in the else: \
+             {{else}}{{#if x}}..{{/if}}"
+        }
+        ExpressionType::HtmlEscaped if content == "^" => "{{^}} is not supported, use {{else}}",
+        ExpressionType::HtmlEscaped if content.starts_with('^') => {
+            "inverse sections ({{^name}}) are not supported, use {{#unless name}}..{{/unless}}"
+        }
+        ExpressionType::Open
+            if (content.starts_with("if ") || content.starts_with("unless "))
+                && content.split_whitespace().any(|word| word == "as") =>
+        {
+            "if and unless don't take block params (as |name|), open the value with \
+             {{#with value as |name|}} or test it with {{#if_some value as |name|}}"
+        }
+        _ => return Ok(()),
+    };
+    Err(ParseError::new(message, expression))
+}
+
+/// Compiler options, the defaults compile like the macros: writing to `f` from `self`
+///
+/// Other options are set on the defaults, such as `Options::default().root_var_name(None)`.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Name of the root variable
+    pub(crate) root_var_name: Option<&'static str>,
+    /// Name of the write function
+    pub(crate) write_var_name: &'static str,
+    /// Types of variables
+    pub(crate) variable_types: HashMap<String, String>,
+    /// Block helpers registered from user code, by name to function path
+    pub(crate) block_helpers: HashMap<String, String>,
+    /// Inline helpers registered from user code, by name to function path
+    pub(crate) helpers: HashMap<String, String>,
+    /// Registered inline helpers returning already escaped HTML, written without escaping
+    pub(crate) safe_helpers: HashSet<String>,
+    /// Write static text with `push_static` so the writer can keep it as borrowed segments
+    pub(crate) segmented: bool,
+    /// HTML-escape `{{ }}` output, otherwise it is written like `{{{ }}}`
+    pub(crate) escape_html: bool,
+    /// Keys of the translation catalog, a `t` helper with any other key is an error
+    pub(crate) translation_keys: Option<HashSet<String>>,
+    /// Render `{{debug value}}` in release builds too, by default it renders nothing there
+    pub(crate) debug_in_release: bool,
+    /// Variables implementing `RenderableFragment`, such as HTML built by another crate,
+    /// written raw by the fragment itself
+    pub(crate) fragments: HashSet<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            root_var_name: Some("self"),
+            write_var_name: "f",
+            variable_types: HashMap::new(),
+            block_helpers: HashMap::new(),
+            helpers: HashMap::new(),
+            safe_helpers: HashSet::new(),
+            segmented: false,
+            escape_html: true,
+            translation_keys: None,
+            debug_in_release: false,
+            fragments: HashSet::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Sets the name of the root variable, `None` for templates compiled without one
+    pub fn root_var_name(mut self, root_var_name: Option<&'static str>) -> Self {
+        self.root_var_name = root_var_name;
+        self
+    }
+
+    /// Sets the name of the write function
+    pub fn write_var_name(mut self, write_var_name: &'static str) -> Self {
+        self.write_var_name = write_var_name;
+        self
+    }
+
+    /// Sets the types of variables, by name to the Rust type
+    pub fn variable_types(mut self, variable_types: HashMap<String, String>) -> Self {
+        self.variable_types = variable_types;
+        self
+    }
+
+    /// Sets the block helpers registered from user code, by name to function path
+    pub fn block_helpers(mut self, block_helpers: HashMap<String, String>) -> Self {
+        self.block_helpers = block_helpers;
+        self
+    }
+
+    /// Sets the inline helpers registered from user code, by name to function path
+    pub fn helpers(mut self, helpers: HashMap<String, String>) -> Self {
+        self.helpers = helpers;
+        self
+    }
+
+    /// Sets the registered inline helpers returning already escaped HTML
+    pub fn safe_helpers(mut self, safe_helpers: HashSet<String>) -> Self {
+        self.safe_helpers = safe_helpers;
+        self
+    }
+
+    /// Writes static text with `push_static`
+    pub fn segmented(mut self, segmented: bool) -> Self {
+        self.segmented = segmented;
+        self
+    }
+
+    /// HTML-escapes `{{ }}` output, on by default
+    pub fn escape_html(mut self, escape_html: bool) -> Self {
+        self.escape_html = escape_html;
+        self
+    }
+
+    /// Sets the keys of the translation catalog the keys of `t` are checked against
+    pub fn translation_keys(mut self, translation_keys: Option<HashSet<String>>) -> Self {
+        self.translation_keys = translation_keys;
+        self
+    }
+
+    /// Renders `{{debug value}}` in release builds too
+    pub fn debug_in_release(mut self, debug_in_release: bool) -> Self {
+        self.debug_in_release = debug_in_release;
+        self
+    }
+
+    /// Sets the variables implementing `RenderableFragment`
+    pub fn fragments(mut self, fragments: HashSet<String>) -> Self {
+        self.fragments = fragments;
+        self
+    }
+}
+
+/// Compiles a template with the built in block helpers, returning the code of its rendering
+/// function wrapped by [`Rust::to_function`] as `fn name(signature)`
+pub fn compile_to_string(
+    src: &str,
+    options: Options,
+    name: &str,
+    signature: &str,
+) -> Result<String> {
+    let mut block_map = BlockMap::new();
+    add_builtins(&mut block_map);
+    let rust = Compiler::new(options, block_map).compile(src)?;
+    Ok(rust.to_function(name, signature))
+}
+
+/// Returns the zero of a numeric type a condition is compared with, `None` for other types
+fn numeric_zero(ty: &str) -> Option<&'static str> {
+    match ty {
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64" | "i128"
+        | "isize" => Some("0"),
+        "f32" | "f64" => Some("0.0"),
+        _ => None,
+    }
+}
+
+/// Regex matching the text before the start of a URL attribute value such as `href="`
+static URL_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\s(?:href|src|action|formaction)\s*=\s*["']?$"#).unwrap());
+
+/// Whether a `{{ }}` value written after the template text `preceding` starts the value of
+/// a URL attribute, where it is escaped with `as_display_url` instead of `as_display_html`
+pub fn starts_url_attribute(preceding: &str) -> bool {
+    let in_tag = preceding
+        .rfind('<')
+        .is_some_and(|start| !preceding[start..].contains('>'));
+    in_tag && URL_ATTRIBUTE.is_match(preceding)
+}
+
+/// Returns the byte range of the expression starting at `offset`, through its closing
+/// expression when it opens a block (or to the end of an unclosed one)
+fn expression_span(src: &str, offset: usize) -> Option<Range<usize>> {
+    let rest = src.get(offset..).filter(|rest| rest.starts_with("{{"))?;
+    let end = |expr: &Expression| offset + expr.postfix.as_ptr() as usize - rest.as_ptr() as usize;
+    let first = Expression::from(rest).ok()??;
+    if !matches!(first.expression_type, ExpressionType::Open) {
+        return Some(offset..end(&first));
+    }
+    let mut depth = 0;
+    let mut expression = Some(first);
+    while let Some(expr) = expression {
+        match expr.expression_type {
+            ExpressionType::Open => depth += 1,
+            ExpressionType::Close if depth == 1 => return Some(offset..end(&expr)),
+            ExpressionType::Close => depth -= 1,
+            _ => (),
+        }
+        expression = match expr.next() {
+            Ok(next) => next,
+            Err(_) => break,
+        };
+    }
+    Some(offset..src.len())
+}
+
+/// Main compiler implementation
+pub struct Compiler {
+    /// Regex for cleaning whitespace
+    clean: Regex,
+    /// Compiler options
+    options: Options,
+    /// Map of block helpers
+    block_map: BlockMap,
+}
+
+impl Compiler {
+    /// Creates a new compiler
+    pub fn new(options: Options, block_map: BlockMap) -> Self {
+        Self {
+            clean: Regex::new("[\\\\\"\\{\\}\r]").unwrap(),
+            options,
+            block_map,
+        }
+    }
+
+    /// Escapes HTML content
+    fn escape<'a>(&self, content: &'a str) -> Cow<'a, str> {
+        self.clean
+            .replace_all(content, |captures: &Captures| match &captures[0] {
+                "{" | "}" => format!("{}{}", &captures[0], &captures[0]),
+                // a raw carriage return would be dropped when the literal is tokenized
+                "\r" => "\\r".to_string(),
+                _ => format!("\\{}", &captures[0]),
+            })
+    }
+
+    fn scan_token<'a>(
+        &self,
+        token: &Token<'a>,
+        usages: &mut Vec<(String, Usage)>,
+        seen: &mut HashSet<String>,
+        usage: Usage,
+    ) -> Result<()> {
+        if let Some(value) = hash_value(token)? {
+            return self.scan_token(&value, usages, seen, usage);
+        }
+        match token.token_type {
+            TokenType::Variable => {
+                let name = token.value.replace("?.", ".");
+                if seen.contains(&name) {
+                    if let Some((_, existing_usage)) = usages.iter_mut().find(|(n, _)| *n == name)
+                        && *existing_usage != usage
+                    {
+                        *existing_usage = match (*existing_usage, usage) {
+                            (Usage::Iterated, _) | (_, Usage::Iterated) => Usage::Iterated,
+                            _ => Usage::Both,
+                        };
+                    }
+                } else {
+                    seen.insert(name.clone());
+                    usages.push((name, usage));
+                }
+            }
+            TokenType::SubExpression(_) => {
+                if let Some(sub_token) = Token::first(token.value)?
+                    && let Some(arg) = sub_token.next()?
+                {
+                    self.scan_token(&arg, usages, seen, Usage::Display)?;
+                    let mut current = arg;
+                    while let Some(next_arg) = current.next()? {
+                        self.scan_token(&next_arg, usages, seen, Usage::Display)?;
+                        current = next_arg;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Reports every variable of a template with the ways it is used, for tools and macros
+    /// choosing its types
+    pub fn scan_report(&self, src: &str) -> Result<Vec<VariableReport>> {
+        self.report_source(src).map_err(|err| err.locate(src))
+    }
+
+    fn report_source(&self, src: &str) -> Result<Vec<VariableReport>> {
+        let mut report = Vec::new();
+        for (path, usage) in self.scan(src)? {
+            let kinds: &[UsageKind] = match usage {
+                Usage::Display => &[UsageKind::Display],
+                Usage::Boolean => &[UsageKind::Bool],
+                Usage::Both => &[UsageKind::Display, UsageKind::Bool],
+                Usage::Iterated => &[UsageKind::Iterable],
+            };
+            for kind in kinds {
+                report_usage(&mut report, &path, *kind);
+            }
+        }
+        // the variable iterated by each open block, None for other blocks
+        let mut blocks: Vec<Option<&str>> = Vec::new();
+        let mut expression = Expression::from(src)?;
+        while let Some(expr) = expression {
+            let each = blocks.iter().rev().find_map(|block| *block);
+            match expr.expression_type {
+                ExpressionType::Raw | ExpressionType::HtmlEscaped if expr.content != "else" => {
+                    report_tokens(&mut report, Token::first(expr.content)?, each)?;
+                }
+                ExpressionType::Open => {
+                    let first = Token::first(expr.content)?;
+                    let iterated = match &first {
+                        Some(helper) if helper.value == "each" => helper.next()?,
+                        _ => None,
+                    };
+                    report_tokens(
+                        &mut report,
+                        first.and_then(|helper| helper.next().ok()?),
+                        each,
+                    )?;
+                    blocks.push(iterated.map(|token| token.value));
+                }
+                ExpressionType::Close => {
+                    blocks.pop();
+                }
+                _ => {}
+            }
+            expression = expr.next()?;
+        }
+        Ok(report)
+    }
+
+    /// Lists the variables of a template with how they are used, errors carry their offset
+    pub fn scan(&self, src: &str) -> Result<Vec<(String, Usage)>> {
+        self.scan_source(src).map_err(|err| err.locate(src))
+    }
+
+    fn scan_source(&self, src: &str) -> Result<Vec<(String, Usage)>> {
+        let mut usages = Vec::new();
+        let mut seen = HashSet::new();
+        let mut expression = Expression::from(src)?;
+        while let Some(expr) = expression {
+            check_supported(&expr)?;
+            match expr.expression_type {
+                ExpressionType::Raw | ExpressionType::HtmlEscaped => {
+                    if expr.content != "else"
+                        && let Some(token) = Token::first(expr.content)?
+                        && token.value == "class"
+                        && let Some(args) = token.next()?
+                    {
+                        for arg in class_args(args)? {
+                            match arg {
+                                ClassArg::Always(value) => {
+                                    self.scan_token(&value, &mut usages, &mut seen, Usage::Display)?
+                                }
+                                ClassArg::When(_, condition) => self.scan_token(
+                                    &condition,
+                                    &mut usages,
+                                    &mut seen,
+                                    Usage::Boolean,
+                                )?,
+                            }
+                        }
+                    } else if let Some(token) = Token::first(expr.content)?
+                        && token.value == "unless"
+                        && let Some(condition) = token.next()?
+                    {
+                        self.scan_token(&condition, &mut usages, &mut seen, Usage::Boolean)?;
+                        let mut current = condition;
+                        while let Some(arg) = current.next()? {
+                            self.scan_token(&arg, &mut usages, &mut seen, Usage::Display)?;
+                            current = arg;
+                        }
+                    } else if expr.content != "else"
+                        && let Some(token) = Token::first(expr.content)?
+                    {
+                        self.scan_token(&token, &mut usages, &mut seen, Usage::Display)?;
+                        let mut current = token;
+                        while let Some(arg) = current.next()? {
+                            self.scan_token(&arg, &mut usages, &mut seen, Usage::Display)?;
+                            current = arg;
+                        }
+                    }
+                }
+                ExpressionType::Open => {
+                    if let Some(token) = Token::first(expr.content)? {
+                        let usage = match token.value {
+                            "if" | "unless" => Usage::Boolean,
+                            "each" => Usage::Iterated,
+                            _ => Usage::Display,
+                        };
+
+                        if let Some(arg) = token.next()? {
+                            self.scan_token(&arg, &mut usages, &mut seen, usage)?;
+                            let mut current = arg;
+                            while let Some(next_arg) = current.next()? {
+                                self.scan_token(&next_arg, &mut usages, &mut seen, Usage::Display)?;
+                                current = next_arg;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+            expression = expr.next()?;
+        }
+        Ok(usages)
+    }
+
+    /// Writes pending writes one by one, static text as `push_static("..")` and expressions
+    /// with `write!`
+    fn commit_segments<'a>(
+        &self,
+        pending: &mut Vec<PendingWrite<'a>>,
+        compile: &mut Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let write_var_name = self.options.write_var_name;
+        for pending in pending.drain(..) {
+            let (format, content, raw, display, uses) = match pending {
+                PendingWrite::Raw(raw) => {
+                    write!(
+                        rust.code,
+                        "{}.push_static(\"{}\");",
+                        write_var_name,
+                        escape_literal(raw)
+                    )
+                    .unwrap();
+                    continue;
+                }
+                PendingWrite::Expression((expression, uses, display)) => {
+                    ("{}", expression.content, expression.raw, display, uses)
+                }
+                PendingWrite::Format((raw, format, content)) => (format, content, raw, "", ""),
+            };
+            write!(rust.code, "write!({}, \"{}\"", write_var_name, format).unwrap();
+            compile.resolve(
+                &Expression {
+                    expression_type: ExpressionType::Raw,
+                    prefix: ", ",
+                    content,
+                    postfix: display,
+                    raw,
+                },
+                rust,
+            )?;
+            rust.code.push_str(")?;");
+            if !uses.is_empty() {
+                rust.using.insert(uses.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits pending writes
+    fn commit_pending<'a>(
+        &self,
+        pending: &mut Vec<PendingWrite<'a>>,
+        compile: &mut Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        if pending.is_empty() {
+            return Ok(());
+        }
+        for pending in pending.iter() {
+            match pending {
+                PendingWrite::Raw(raw) => rust.static_len += raw.len(),
+                _ => rust.value_writes += 1,
+            }
+        }
+        if self.options.segmented {
+            return self.commit_segments(pending, compile, rust);
+        }
+        rust.code.push_str("write!(");
+        rust.code.push_str(self.options.write_var_name);
+        rust.code.push_str(", \"");
+        for pending in pending.iter() {
+            match pending {
+                PendingWrite::Raw(raw) => rust.code.push_str(self.escape(raw).as_ref()),
+                PendingWrite::Expression(_) => rust.code.push_str("{}"),
+                PendingWrite::Format((_, format, _)) => rust.code.push_str(format),
+            }
+        }
+        rust.code.push('"');
+        for pending in pending.iter() {
+            match pending {
+                PendingWrite::Expression((expression, uses, display)) => {
+                    compile.resolve(
+                        &Expression {
+                            expression_type: ExpressionType::Raw,
+                            prefix: ", ",
+                            content: expression.content,
+                            postfix: display,
+                            raw: expression.raw,
+                        },
+                        rust,
+                    )?;
+                    if !uses.is_empty() {
+                        rust.using.insert(uses.to_string());
+                    }
+                }
+                PendingWrite::Format((raw, _, content)) => {
+                    compile.resolve(
+                        &Expression {
+                            expression_type: ExpressionType::Raw,
+                            prefix: ", ",
+                            content,
+                            postfix: "",
+                            raw,
+                        },
+                        rust,
+                    )?;
+                }
+                _ => (),
+            }
+        }
+        rust.code.push_str(")?;");
+        pending.clear();
+        Ok(())
+    }
+
+    fn select_write<'a>(
+        expression: &Expression<'a>,
+        uses: &'static str,
+        postfix: &'static str,
+    ) -> Result<PendingWrite<'a>> {
+        if let Some(token) = Token::first(expression.content)?
+            && let TokenType::Variable = token.token_type
+        {
+            if token.value != "format" {
+                return Ok(PendingWrite::Expression((*expression, uses, postfix)));
+            }
+            let pattern = match token.next()? {
+                Some(token) => token,
+                _ => {
+                    return Ok(PendingWrite::Expression((*expression, uses, postfix)));
+                }
+            };
+            let value = match pattern.next() {
+                Ok(Some(token)) => token,
+                _ => return Err(ParseError::new("format requires 2 arguments", expression)),
+            };
+            if let TokenType::Literal = pattern.token_type
+                && pattern.value.starts_with('"')
+                && pattern.value.ends_with('"')
+            {
+                return Ok(PendingWrite::Format((
+                    expression.raw,
+                    &pattern.value[1..pattern.value.len() - 1],
+                    value.value,
+                )));
+            }
+            return Err(ParseError::new(
+                "first argument of format must be a string literal",
+                expression,
+            ));
+        }
+        Ok(PendingWrite::Expression((*expression, uses, postfix)))
+    }
+
+    /// Checks if an expression evaluates to an `Option`: an optional chain, `last`, `dig`, a
+    /// `lookup` counting from the end or a variable mapped to an `Option`
+    fn is_optional(content: &str, compile: &Compile) -> Result<bool> {
+        if content.contains("?.") {
+            return Ok(true);
+        }
+        let token = match Token::first(content)? {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        if token.next()?.is_none()
+            && compile
+                .root_type(&token)
+                .is_some_and(|ty| ty.starts_with("Option"))
+        {
+            return Ok(true);
+        }
+        Ok(match token.value {
+            "last" | "dig" => token.next()?.is_some(),
+            "lookup" => match token.next()? {
+                Some(collection) => collection
+                    .next()?
+                    .is_some_and(|index| negative_index(&index).is_some()),
+                None => false,
+            },
+            _ => false,
+        })
+    }
+
+    /// Checks if an expression calls a helper returning already escaped HTML, either a
+    /// built in one or one registered as safe
+    fn is_safe(&self, content: &str) -> Result<bool> {
+        let token = match Token::first(content)? {
+            Some(token) if matches!(token.token_type, TokenType::Variable) => token,
+            _ => return Ok(false),
+        };
+        // without arguments a built in helper's name is a variable
+        Ok(self.options.safe_helpers.contains(token.value)
+            || (SAFE_HELPERS.contains(&token.value) && token.next()?.is_some())
+            // the output of defer blocks is already escaped
+            || (token.value == "deferred"
+                && !self.options.variable_types.contains_key("deferred")
+                && token.next()?.is_none())
+            || (self.options.fragments.contains(token.value) && token.next()?.is_none()))
+    }
+
+    /// Selects the escaping of `{{ }}` output from the template text before it, a value
+    /// starting a URL attribute has unsafe schemes such as `javascript:` rejected
+    fn escaping(&self, preceding: &str) -> (&'static str, &'static str) {
+        match starts_url_attribute(preceding) {
+            true => (USE_AS_DISPLAY_URL, AS_DISPLAY_URL),
+            false => (USE_AS_DISPLAY_HTML, AS_DISPLAY_HTML),
+        }
+    }
+
+    /// Writes an optional expression, rendering nothing if it is `None`
+    fn write_optional<'a>(
+        &self,
+        expression: &Expression<'a>,
+        display: &str,
+        compile: &Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        rust.code.push_str("if let Some(chained) = ");
+        if expression.content.contains("?.") {
+            self.write_optional_chain(expression, compile, rust)?;
+        } else {
+            rust.code.push('&');
+            compile.resolve(
+                &Expression {
+                    expression_type: ExpressionType::Raw,
+                    prefix: "",
+                    content: expression.content,
+                    postfix: "",
+                    raw: expression.raw,
+                },
+                rust,
+            )?;
+        }
+        write!(
+            rust.code,
+            "{{write!({}, \"{{}}\", chained{})?;}}",
+            self.options.write_var_name, display
+        )
+        .unwrap();
+        Ok(())
+    }
+
+    /// Writes an optional chain such as `{{a?.b?.c}}` as nested `Option` accesses
+    fn write_optional_chain<'a>(
+        &self,
+        expression: &Expression<'a>,
+        compile: &Compile<'a>,
+        rust: &mut Rust,
+    ) -> Result<()> {
+        let token = Token::first(expression.content)?
+            .ok_or_else(|| ParseError::new("expected token", expression))?;
+        if !matches!(token.token_type, TokenType::Variable) || token.next()?.is_some() {
+            return Err(ParseError::new(
+                "optional chaining is only supported on plain paths",
+                expression,
+            ));
+        }
+        let mut links = token.value.split("?.");
+        let root = links.next().unwrap_or_default();
+        let links: Vec<&str> = links.collect();
+        if root.is_empty() || links.iter().any(|link| link.is_empty()) {
+            return Err(ParseError::new("empty link in optional chain", expression));
+        }
+        compile.resolve(
+            &Expression {
+                expression_type: ExpressionType::Raw,
+                prefix: "",
+                content: root,
+                postfix: ".as_ref()",
+                raw: expression.raw,
+            },
+            rust,
+        )?;
+        for (i, link) in links.iter().enumerate() {
+            if i + 1 == links.len() {
+                write!(rust.code, ".map(|v| &v.{})", link).unwrap();
+            } else {
+                write!(rust.code, ".and_then(|v| v.{}.as_ref())", link).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a template, errors carry their offset in `src`
+    pub fn compile(&self, src: &str) -> Result<Rust> {
+        self.compile_source(src).map_err(|err| err.locate(src))
+    }
+
+    /// Compiles a template like [`Compiler::compile`], reporting every independent error
+    /// rather than the first
+    ///
+    /// After each error the expression at fault, or the whole block it opens, is blanked out
+    /// and the rest compiled again, until it compiles or an error can't be located.
+    pub fn compile_all(&self, src: &str) -> std::result::Result<Rust, Vec<ParseError>> {
+        let mut masked = src.to_string();
+        let mut errors = Vec::new();
+        loop {
+            let err = match self.compile_source(&masked) {
+                Ok(rust) if errors.is_empty() => return Ok(rust),
+                Ok(_) => return Err(errors),
+                Err(err) => err.locate(&masked),
+            };
+            let span = err
+                .offset()
+                .and_then(|offset| expression_span(&masked, offset));
+            errors.push(err);
+            let Some(span) = span else {
+                return Err(errors);
+            };
+            // blanked with spaces of the same length so offsets and lines stay the same
+            let blank: String = masked[span.clone()]
+                .chars()
+                .map(|c| match c {
+                    '\n' | '\r' => c.to_string(),
+                    _ => " ".repeat(c.len_utf8()),
+                })
+                .collect();
+            masked.replace_range(span, &blank);
+        }
+    }
+
+    fn compile_source(&self, src: &str) -> Result<Rust> {
+        let usages = self.scan(src)?;
+        let mut variable_types = self.options.variable_types.clone();
+        let mut iterated = HashSet::new();
+        for (name, usage) in usages {
+            match usage {
+                Usage::Boolean | Usage::Both if !variable_types.contains_key(&name) => {
+                    variable_types.insert(name, "bool".to_string());
+                }
+                Usage::Iterated => {
+                    iterated.insert(name);
+                }
+                _ => (),
+            }
+        }
+
+        let mut compile = Compile::new(&self.options, &self.block_map, &variable_types, &iterated);
+        let mut rust = Rust::new();
+        let mut pending: Vec<PendingWrite> = Vec::new();
+        // the blocks still open, so one left unclosed is reported where it opened
+        let mut opened: Vec<(&str, &str)> = Vec::new();
+        let mut rest = src;
+        let mut expression = Expression::from(src)?;
+        while let Some(expr) = expression {
+            let Expression {
+                expression_type,
+                prefix,
+                content,
+                postfix,
+                raw,
+            } = &expr;
+            rest = postfix;
+            if !prefix.is_empty() {
+                pending.push(PendingWrite::Raw(prefix));
+            }
+            let optional = matches!(
+                expression_type,
+                ExpressionType::Raw | ExpressionType::HtmlEscaped
+            ) && Self::is_optional(content, &compile)?;
+            let (uses, display) = match expression_type {
+                ExpressionType::HtmlEscaped
+                    if !self.options.escape_html || self.is_safe(content)? =>
+                {
+                    (USE_AS_DISPLAY, "")
+                }
+                ExpressionType::HtmlEscaped => self.escaping(
+                    &src[..prefix.as_ptr() as usize + prefix.len() - src.as_ptr() as usize],
+                ),
+                _ => (USE_AS_DISPLAY, ""),
+            };
+            match expression_type {
+                _ if optional => {
+                    self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+                    self.write_optional(&expr, display, &compile, &mut rust)?;
+                    if !uses.is_empty() {
+                        rust.using.insert(uses.to_string());
+                    }
+                }
+                ExpressionType::Raw => pending.push(Self::select_write(&expr, USE_AS_DISPLAY, "")?),
+                ExpressionType::HtmlEscaped => {
+                    if *content == "else" {
+                        self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+                        compile.handle_else(&expr, &mut rust)?
+                    } else {
+                        pending.push(Self::select_write(&expr, uses, display)?)
+                    }
+                }
+                ExpressionType::Open => {
+                    self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+                    opened.push((raw, content));
+                    compile.open(expr, &mut rust)?
+                }
+                ExpressionType::Close => {
+                    self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+                    opened.pop();
+                    compile.close(expr, &mut rust)?
+                }
+                ExpressionType::Escaped => pending.push(PendingWrite::Raw(content)),
+                _ => (),
+            };
+            expression = expr.next()?;
+        }
+        if !rest.is_empty() {
+            pending.push(PendingWrite::Raw(rest));
+        }
+        self.commit_pending(&mut pending, &mut compile, &mut rust)?;
+        if let Some((raw, content)) = opened.last() {
+            return Err(ParseError::at(
+                format!("unclosed block {{{{#{}}}}}", content.trim()),
+                raw,
+            ));
+        }
+        if rust.deferred || rust.deferred_written {
+            rust.code
+                .insert_str(0, "#[allow(unused_mut)] let mut deferred = String::new();");
+        }
+        // without {{deferred}} the output of defer blocks ends the template
+        if rust.deferred && !rust.deferred_written {
+            rust.code.push_str(self.options.write_var_name);
+            rust.code.push_str(".write_str(&deferred)?;");
+        }
+        Ok(rust)
+    }
+}
@@ -0,0 +1,247 @@
+//! Date formats for the `format_date` helper
+//!
+//! Formats are written with strftime specifiers (e.g. `%Y-%m-%d`) and checked when the
+//! template is compiled:
+//! - With the `chrono` feature the format is passed to chrono's `format` as is
+//! - With the `time` feature the format is translated into a `time` format description,
+//!   which supports a subset of the specifiers. The generated code builds it with
+//!   `::time::macros::format_description!`, so the using crate needs time's `macros` and
+//!   `formatting` features, and a value failing to format fails the render
+
+/// Date crate the generated code formats with
+pub enum Backend {
+    Chrono,
+    Time,
+}
+
+impl Backend {
+    /// Returns the backend enabled by a crate feature, `chrono` is preferred when both are
+    pub fn enabled() -> Option<Backend> {
+        if cfg!(feature = "chrono") {
+            Some(Backend::Chrono)
+        } else if cfg!(feature = "time") {
+            Some(Backend::Time)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the code following a value to format it, checking the format
+    pub fn format_code(&self, format: &str) -> Result<String, String> {
+        match self {
+            Backend::Chrono => {
+                validate_chrono(format)?;
+                Ok(format!(".format({:?})", format))
+            }
+            Backend::Time => Ok(format!(
+                ".format(::time::macros::format_description!({:?})).map_err(|_| ::std::fmt::Error)?",
+                to_time_description(format)?
+            )),
+        }
+    }
+
+    /// Returns the code following a value to format it as RFC 3339, for machine readable
+    /// attributes such as `datetime`
+    pub fn rfc3339_code(&self) -> &'static str {
+        match self {
+            Backend::Chrono => ".format(\"%Y-%m-%dT%H:%M:%S%:z\")",
+            Backend::Time => {
+                ".format(&::time::format_description::well_known::Rfc3339).map_err(|_| ::std::fmt::Error)?"
+            }
+        }
+    }
+}
+
+/// Specifiers chrono accepts after `%` and an optional `-`, `_` or `0` padding modifier
+const CHRONO_SPECIFIERS: &str = "YCymbBhdeaAwuUWGgVjDxFvHkIlPpMSfRTXrzZcs+tn%";
+
+/// Part of a date format
+enum Piece<'a> {
+    /// Literal character
+    Literal(char),
+    /// Text following a `%` (e.g. `Y`, `-d` or `.3f`)
+    Specifier(&'a str),
+}
+
+/// Splits a format into literal characters and specifiers
+fn parse<'a>(
+    format: &'a str,
+    mut piece: impl FnMut(Piece<'a>) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut rest = format;
+    while let Some(c) = rest.chars().next() {
+        if c != '%' {
+            piece(Piece::Literal(c))?;
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let spec = &rest[1..];
+        let padding = spec.starts_with(['-', '_', '0']) as usize;
+        let len = match &spec[padding..] {
+            s if s.starts_with(".3f") || s.starts_with(".6f") || s.starts_with(".9f") => 3,
+            s if s.starts_with(":::z") => 4,
+            s if s.starts_with("::z") => 3,
+            s if s.starts_with(".f")
+                || s.starts_with("3f")
+                || s.starts_with("6f")
+                || s.starts_with("9f")
+                || s.starts_with(":z")
+                || s.starts_with("#z") =>
+            {
+                2
+            }
+            s => match s.chars().next() {
+                Some(c) if CHRONO_SPECIFIERS.contains(c) => c.len_utf8(),
+                Some(c) => return Err(format!("unknown date format specifier %{}", c)),
+                None => return Err("date format ends with %".to_string()),
+            },
+        };
+        piece(Piece::Specifier(&spec[..padding + len]))?;
+        rest = &spec[padding + len..];
+    }
+    Ok(())
+}
+
+/// Checks a format against the specifiers chrono supports
+pub fn validate_chrono(format: &str) -> Result<(), String> {
+    parse(format, |_| Ok(()))
+}
+
+/// Translates a format into a `time` format description such as `[year]-[month]-[day]`
+pub fn to_time_description(format: &str) -> Result<String, String> {
+    let mut description = String::new();
+    parse(format, |piece| {
+        let spec = match piece {
+            Piece::Literal('[') => {
+                description.push_str("[[");
+                return Ok(());
+            }
+            Piece::Literal(c) => {
+                description.push(c);
+                return Ok(());
+            }
+            Piece::Specifier(spec) => spec,
+        };
+        let component = match spec {
+            "Y" => "[year]",
+            "y" => "[year repr:last_two]",
+            "m" => "[month]",
+            "b" | "h" => "[month repr:short]",
+            "B" => "[month repr:long]",
+            "d" => "[day]",
+            "e" => "[day padding:space]",
+            "-d" => "[day padding:none]",
+            "a" => "[weekday repr:short]",
+            "A" => "[weekday repr:long]",
+            "j" => "[ordinal]",
+            "H" => "[hour]",
+            "I" => "[hour repr:12]",
+            "M" => "[minute]",
+            "S" => "[second]",
+            "p" => "[period]",
+            "z" => "[offset_hour sign:mandatory][offset_minute]",
+            "F" => "[year]-[month]-[day]",
+            "T" => "[hour]:[minute]:[second]",
+            "R" => "[hour]:[minute]",
+            "%" => "%",
+            spec => {
+                return Err(format!(
+                    "date format specifier %{} is not supported with the time feature",
+                    spec
+                ));
+            }
+        };
+        description.push_str(component);
+        Ok(())
+    })?;
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_descriptions() {
+        let cases = [
+            ("%Y-%m-%d", "[year]-[month]-[day]"),
+            ("%F %T", "[year]-[month]-[day] [hour]:[minute]:[second]"),
+            (
+                "%y %b %h %B",
+                "[year repr:last_two] [month repr:short] [month repr:short] [month repr:long]",
+            ),
+            (
+                "%e %-d %a %A %j",
+                "[day padding:space] [day padding:none] [weekday repr:short] [weekday repr:long] [ordinal]",
+            ),
+            ("%I:%M %p", "[hour repr:12]:[minute] [period]"),
+            (
+                "%R%z",
+                "[hour]:[minute][offset_hour sign:mandatory][offset_minute]",
+            ),
+            ("100%% [%H]", "100% [[[hour]]"),
+            ("é %d", "é [day]"),
+        ];
+        for (format, description) in cases {
+            assert_eq!(
+                to_time_description(format).as_deref(),
+                Ok(description),
+                "{}",
+                format
+            );
+        }
+        assert_eq!(
+            to_time_description("%Y %c"),
+            Err("date format specifier %c is not supported with the time feature".to_string())
+        );
+        assert_eq!(
+            to_time_description("%Q"),
+            Err("unknown date format specifier %Q".to_string())
+        );
+    }
+
+    #[test]
+    fn chrono_specifiers() {
+        for format in [
+            "%Y-%m-%d",
+            "%-d %B %Y",
+            "%_H:%0M",
+            "%H:%M:%S%.3f",
+            "%.f %3f %:z %::z %:::z %#z",
+            "%c %+ %s %%",
+        ] {
+            assert_eq!(validate_chrono(format), Ok(()), "{}", format);
+        }
+        assert_eq!(
+            validate_chrono("%Y-%Q"),
+            Err("unknown date format specifier %Q".to_string())
+        );
+        assert_eq!(
+            validate_chrono("%Y %"),
+            Err("date format ends with %".to_string())
+        );
+        assert_eq!(
+            validate_chrono("%-"),
+            Err("date format ends with %".to_string())
+        );
+    }
+
+    #[test]
+    fn format_code() {
+        assert_eq!(
+            Backend::Chrono.format_code("%d \"%b\""),
+            Ok(".format(\"%d \\\"%b\\\"\")".to_string())
+        );
+        assert_eq!(
+            Backend::Time.format_code("%Y-%m-%d"),
+            Ok(".format(::time::macros::format_description!(\"[year]-[month]-[day]\")).map_err(|_| ::std::fmt::Error)?".to_string())
+        );
+        assert!(Backend::Chrono.format_code("%Q").is_err());
+        assert!(Backend::Time.format_code("%c").is_err());
+        assert!(
+            Backend::Time
+                .rfc3339_code()
+                .ends_with(".map_err(|_| ::std::fmt::Error)?")
+        );
+    }
+}
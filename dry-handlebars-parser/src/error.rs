@@ -25,7 +25,7 @@
 //! This module provides error types and handling for the template parsing process.
 //! It includes detailed error messages with context about where parsing errors occurred.
 
-use crate::parser::expression::Expression;
+use crate::expression::Expression;
 use std::{error::Error, fmt::Display};
 
 /// Error type for template parsing failures
@@ -35,6 +35,20 @@ use std::{error::Error, fmt::Display};
 #[derive(Debug)]
 pub struct ParseError {
     pub(crate) message: String,
+    /// Address of the text the error is about, a slice of the source being parsed
+    at: Option<usize>,
+    /// Byte offset of that text in the source, resolved by `locate`
+    offset: Option<usize>,
+}
+
+/// Returns the line and column, both counted from 1, of a byte offset in a source
+pub fn line_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset.min(src.len())];
+    let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
 }
 
 /// Returns the last 32 characters of a string for error context
@@ -51,16 +65,57 @@ pub(crate) fn rcap(src: &str) -> &str {
 impl ParseError {
     /// Creates a new parse error with context from an expression
     pub(crate) fn new(message: &str, expression: &Expression<'_>) -> Self {
+        Self::at(
+            format!("{} near \"{}\"", message, expression.around()),
+            expression.raw,
+        )
+    }
+
+    /// Creates an error about a slice of the source being parsed
+    pub(crate) fn at(message: String, text: &str) -> Self {
         Self {
-            message: format!("{} near \"{}\"", message, expression.around()),
+            message,
+            at: Some(text.as_ptr() as usize),
+            offset: None,
         }
     }
 
     /// Creates an error for unclosed blocks
     pub(crate) fn unclosed(preffix: &str) -> Self {
-        Self {
-            message: format!("unclosed block near {}", rcap(preffix)),
+        Self::at(
+            format!("unclosed block near {}", rcap(preffix)),
+            &preffix[preffix.len()..],
+        )
+    }
+
+    /// Resolves the offset of the error in the source it was parsed from, errors about text
+    /// outside of `src` keep no offset
+    pub fn locate(mut self, src: &str) -> Self {
+        let start = src.as_ptr() as usize;
+        if let Some(at) = self
+            .at
+            .filter(|at| (start..=start + src.len()).contains(at))
+        {
+            // an expression's error points at its opening braces
+            let before =
+                src[..at - start].trim_end_matches(['#', '/', '~', '>', '!', '^', '*', '&']);
+            let braces = before.trim_end_matches('{');
+            self.offset = Some(match before.len() - braces.len() >= 2 {
+                true => braces.len(),
+                false => at - start,
+            });
         }
+        self
+    }
+
+    /// Byte offset of the error in the template source, once located
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Line and column of the error in the template source, once located
+    pub fn location(&self, src: &str) -> Option<(usize, usize)> {
+        self.offset.map(|offset| line_column(src, offset))
     }
 }
 
@@ -74,6 +129,8 @@ impl From<std::io::Error> for ParseError {
     fn from(err: std::io::Error) -> Self {
         Self {
             message: err.to_string(),
+            at: None,
+            offset: None,
         }
     }
 }
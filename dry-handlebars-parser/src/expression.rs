@@ -29,6 +29,7 @@
 //!
 //! The module supports the following types of expressions:
 //! - Variables: `{{name}}`
+//! - Optional chains: `{{a?.b?.c}}`, rendering nothing if any link is `None`
 //! - HTML-escaped variables: `{{{name}}}`
 //! - Block helpers: `{{#helper}}...{{/helper}}`
 //! - Comments: `{{! comment }}` or `{{!-- comment --}}`
@@ -47,7 +48,7 @@
 
 use std::{cmp::min, fmt::Display};
 
-use crate::parser::error::{ParseError, Result};
+use crate::error::{ParseError, Result};
 
 /// Types of Handlebars expressions
 #[derive(Debug, Clone, Copy)]
@@ -97,9 +98,10 @@ impl<'a> Expression<'a> {
         match start.find(end) {
             Some(mut pos) => {
                 if pos == 0 {
-                    return Err(ParseError {
-                        message: format!("empty block near {}", preffix),
-                    });
+                    return Err(ParseError::at(
+                        format!("empty block near {}", preffix),
+                        start,
+                    ));
                 }
                 let mut postfix = &start[pos + end.len()..];
                 if &start[pos - 1..pos] == "~" {
@@ -138,9 +140,7 @@ impl<'a> Expression<'a> {
                 .ok_or(ParseError::unclosed(open.raw))?;
             let start = candidate + 5;
             let remains = &postfix[start..];
-            let close = remains
-                .find("}}}}")
-                .ok_or(ParseError::unclosed(open.raw))?;
+            let close = remains.find("}}}}").ok_or(ParseError::unclosed(open.raw))?;
             let end = start + close + 4;
             if &remains[..close] == open.content {
                 return Ok(Self {
@@ -161,7 +161,7 @@ impl<'a> Expression<'a> {
         match src.find("{{") {
             Some(start) => {
                 let mut second = nibble(src, start, 3)?;
-                if start > 0 && &src[start - 1..start] == "\\" {
+                if src[..start].ends_with('\\') {
                     return Ok(Some(Self::close(
                         ExpressionType::Escaped,
                         &src[..start - 1],
@@ -228,7 +228,7 @@ impl<'a> Expression<'a> {
         }
         let start = self.prefix.len();
         let end = start + self.content.len() + 16;
-        &self.raw[min(len - 1, if start > 16 { start - 16 } else { 0 })..min(self.raw.len(), end)]
+        &self.raw[min(len - 1, start.saturating_sub(16))..min(self.raw.len(), end)]
     }
 }
 
@@ -26,6 +26,8 @@
 //! It handles various token types including:
 //! - Literals: Plain text values
 //! - Private variables: Variables prefixed with @ (e.g. @index)
+//! - Numbers: Integer and float literals (e.g. 10, -1, 9.99)
+//! - Keywords: `true`, `false` and `null`
 //! - Sub-expressions: Parenthesized expressions
 //!
 //! # Token Types
@@ -45,6 +47,21 @@
 //! @value
 //! ```
 //!
+//! ## Numbers
+//! Integer and float literals, passed through to the generated code unchanged:
+//! ```handlebars
+//! 10
+//! -1
+//! 9.99
+//! ```
+//!
+//! ## Keywords
+//! `true` and `false` map to Rust booleans, `null` maps to `Option::None`:
+//! ```handlebars
+//! (toggle expanded=false)
+//! (greet null)
+//! ```
+//!
 //! ## Sub-expressions
 //! Parenthesized expressions that are evaluated first:
 //! ```handlebars
@@ -63,7 +80,7 @@
 //! assert_eq!(token.token_type, TokenType::Literal);
 //! ```
 
-use crate::parser::error::{ParseError, Result, rcap};
+use crate::error::{ParseError, Result, rcap};
 
 /// Types of tokens that can be parsed from an expression
 #[derive(Clone)]
@@ -73,6 +90,14 @@ pub enum TokenType<'a> {
     /// A private variable prefixed with @
     PrivateVariable,
     Variable,
+    /// An integer literal, e.g. `10` or `-1`
+    Integer,
+    /// A float literal, e.g. `9.99`
+    Float,
+    /// A boolean literal, `true` or `false`
+    Boolean,
+    /// The `null` literal
+    Null,
     /// A plain text literal
     Literal,
 }
@@ -102,9 +127,10 @@ fn find_closing(src: &str) -> Result<usize> {
             return Ok(i + 1);
         }
     }
-    Err(ParseError {
-        message: format!("unmatched brackets near {}", rcap(src)),
-    })
+    Err(ParseError::at(
+        format!("unmatched brackets near {}", rcap(src)),
+        src,
+    ))
 }
 
 fn find_end_of_string(src: &str) -> Result<usize> {
@@ -113,17 +139,14 @@ fn find_end_of_string(src: &str) -> Result<usize> {
     for (i, c) in cliped.char_indices() {
         match c {
             '\\' => escaped = !escaped,
-            '"' => {
-                if !escaped {
-                    return Ok(i + 2);
-                }
-            }
+            '"' if !escaped => return Ok(i + 2),
             _ => (),
         }
     }
-    Err(ParseError {
-        message: format!("unterminated string near {}", rcap(src)),
-    })
+    Err(ParseError::at(
+        format!("unterminated string near {}", rcap(src)),
+        src,
+    ))
 }
 
 /// Finds the end of a token by looking for whitespace or special characters
@@ -136,12 +159,35 @@ fn find_end(src: &str) -> usize {
     src.len()
 }
 
+/// Checks if a token starts like a number (a digit, optionally preceded by `-`)
+fn starts_number(src: &str) -> bool {
+    let mut chars = src.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('-') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// Classifies a numeric literal, rejecting malformed numbers such as `1.2.3` or `12px`
+fn number_type<'a>(value: &str) -> Result<TokenType<'a>> {
+    let digits = value.strip_prefix('-').unwrap_or(value);
+    let all_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    match digits.split_once('.') {
+        None if all_digits(digits) => Ok(TokenType::Integer),
+        Some((int, frac)) if all_digits(int) && all_digits(frac) => Ok(TokenType::Float),
+        _ => Err(ParseError::at(
+            format!("malformed number literal {}", value),
+            value,
+        )),
+    }
+}
+
 fn invalid_variable_name(src: &str) -> bool {
     if src.starts_with("../") {
         return false; // ../ is valid for relative paths
     }
-    src
-        .chars()
+    src.chars()
         .next()
         .map(|c| !(c.is_alphabetic() || c == '_'))
         .unwrap_or(false)
@@ -170,13 +216,24 @@ fn parse<'a>(src: &'a str) -> Result<Option<Token<'a>>> {
         _ => {
             let (end, token_type) = if src.starts_with('"') {
                 (find_end_of_string(src)?, TokenType::Literal)
+            } else if starts_number(src) {
+                let end = find_end(src);
+                (end, number_type(&src[..end])?)
             } else {
+                let mut end = find_end(src);
+                // a hash argument's string value may contain whitespace: `sep=" "`
+                if let Some(eq) = src[..end].find('=')
+                    && src[eq + 1..].starts_with('"')
+                {
+                    end = eq + 1 + find_end_of_string(&src[eq + 1..])?;
+                }
                 (
-                    find_end(src),
-                    if invalid_variable_name(src) {
-                        TokenType::Literal
-                    } else {
-                        TokenType::Variable
+                    end,
+                    match &src[..end] {
+                        "true" | "false" => TokenType::Boolean,
+                        "null" => TokenType::Null,
+                        _ if invalid_variable_name(src) => TokenType::Literal,
+                        _ => TokenType::Variable,
                     },
                 )
             };
@@ -0,0 +1,119 @@
+// MIT License
+//
+// Copyright (c) 2024 Jerome Johnson
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Handlebars template parser and compiler
+//!
+//! This crate provides the core functionality for parsing and compiling Handlebars templates
+//! into Rust code. It's used by the `dry-handlebars` macros to process templates at compile
+//! time and can be used on its own by other tools, such as a CLI, a language server or a
+//! build script.
+//!
+//! # Stability
+//!
+//! The crate follows semver: the public items of its modules only change incompatibly with
+//! a new major version (or minor version before 1.0). The generated code is not part of
+//! the API, only that it compiles against the matching `dry-handlebars` runtime.
+//!
+//! # Features
+//!
+//! - Handlebars template parsing
+//! - Template compilation to Rust code
+//! - Support for all standard Handlebars features:
+//!   - Variables and expressions
+//!   - Block helpers (if, unless, each, with)
+//!   - Partials
+//!   - Comments
+//!   - HTML escaping
+//!   - Whitespace control
+//!   - Subexpressions
+//!   - Lookup helpers
+//!
+//! # Example
+//!
+//! The items needed to compile a template are re-exported at the crate root:
+//!
+//! ```
+//! use dry_handlebars_parser::{BlockMap, Compiler, Options, add_builtins};
+//!
+//! let mut factories = BlockMap::new();
+//! add_builtins(&mut factories);
+//!
+//! let compiler = Compiler::new(Options::default(), factories);
+//!
+//! let template = "Hello {{name}}!";
+//! let rust_code = compiler.compile(template).unwrap();
+//! assert!(rust_code.top_level_vars().contains("name"));
+//! ```
+//!
+//! A build script or CLI generating source files can have the code wrapped in a function:
+//!
+//! ```
+//! use dry_handlebars_parser::{Options, compile_to_string};
+//!
+//! let function = compile_to_string(
+//!     "Hello {{name}}!",
+//!     Options::default(),
+//!     "render_into",
+//!     "&self, f: &mut impl std::fmt::Write",
+//! )?;
+//! assert!(function.starts_with("fn render_into("));
+//! # Ok::<(), dry_handlebars_parser::ParseError>(())
+//! ```
+//!
+//! Templates are read expression by expression with [`Expression`]:
+//!
+//! ```
+//! use dry_handlebars_parser::{Expression, ExpressionType};
+//!
+//! let expression = Expression::from("Hi {{name}}!")?.unwrap();
+//! assert!(matches!(expression.expression_type, ExpressionType::HtmlEscaped));
+//! assert_eq!((expression.prefix, expression.content), ("Hi ", "name"));
+//! # Ok::<(), dry_handlebars_parser::ParseError>(())
+//! ```
+//!
+//! # Module Structure
+//!
+//! - `compiler.rs`: Main compiler implementation
+//! - `block.rs`: Block helper implementations
+//! - `expression.rs`: Expression parsing and evaluation
+//! - `expression_tokenizer.rs`: Tokenization of expressions
+//! - `error.rs`: Error types and handling
+//! - `date_format.rs`: Date formats of the `format_date` and `time` helpers
+//!
+//! # Cargo Features
+//!
+//! `chrono`, `time`, `markdown`, `sanitize`, `i18n` and `log` enable the helpers generating code
+//! for the crates of the same purpose, they are forwarded by `dry-handlebars`.
+
+pub mod block;
+pub mod compiler;
+pub mod date_format;
+pub mod error;
+pub mod expression;
+pub mod expression_tokenizer;
+
+pub use block::add_builtins;
+pub use compiler::{
+    BlockMap, Compiler, Options, Rust, Usage, UsageKind, VariableReport, compile_to_string,
+};
+pub use error::ParseError;
+pub use expression::{Expression, ExpressionType};
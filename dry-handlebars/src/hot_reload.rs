@@ -0,0 +1,125 @@
+//! Rendering a template from its file at render time, so edits show without a rebuild
+//!
+//! With the `hot-reload` feature, debug builds of templates read from files call [`render`]
+//! before their compiled body. The interpreter knows the template's plain values and
+//! `{{#if}}`/`{{#unless}}` blocks over booleans; a file that can't be read or parsed, or that
+//! uses anything else (paths, helpers, each, partials), renders with the compiled body.
+
+use crate::{AsDisplayHtml, AsDisplayUrl};
+use dry_handlebars_parser::compiler::starts_url_attribute;
+use dry_handlebars_parser::expression::{Expression, ExpressionType};
+use std::fmt::{Display, Write};
+
+/// A field of the template as the interpreter reads it
+pub enum Value<'a> {
+    /// Written by `{{name}}` and `{{{name}}}`
+    Text(&'a dyn Display),
+    /// Tested by `{{#if name}}` and `{{#unless name}}`
+    Bool(bool),
+}
+
+/// An open `{{#if}}` or `{{#unless}}` block
+struct Block<'a> {
+    helper: &'a str,
+    /// The content around the block is written
+    outer: bool,
+    /// The branch being read is written when the content around the block is
+    branch: bool,
+    /// `{{else}}` was read
+    inverted: bool,
+}
+
+/// Returns the offset of a slice within the source it was taken from
+fn offset(src: &str, slice: &str) -> usize {
+    slice.as_ptr() as usize - src.as_ptr() as usize
+}
+
+/// Renders the template at `path` with the given fields, `None` when it can't be interpreted
+/// and the compiled body should render instead
+pub fn render(path: &str, values: &[(&str, Value<'_>)], escape: bool) -> Option<String> {
+    let src = std::fs::read_to_string(path).ok()?;
+    let src = src.strip_prefix('\u{feff}').unwrap_or(&src);
+    let lookup = |name: &str| {
+        values
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    };
+    let mut out = String::with_capacity(src.len());
+    let mut blocks: Vec<Block> = Vec::new();
+    let writing = |blocks: &[Block]| {
+        blocks
+            .last()
+            .is_none_or(|block| block.outer && block.branch)
+    };
+    let mut rest = src;
+    let mut expression = Expression::from(src).ok()?;
+    while let Some(expr) = expression {
+        if writing(&blocks) {
+            out.push_str(expr.prefix);
+        }
+        let content = expr.content.trim();
+        match expr.expression_type {
+            ExpressionType::Comment => {}
+            ExpressionType::Escaped => {
+                if writing(&blocks) {
+                    out.push_str(expr.content);
+                }
+            }
+            ExpressionType::HtmlEscaped if content == "else" => {
+                let block = blocks.last_mut().filter(|block| !block.inverted)?;
+                block.branch = !block.branch;
+                block.inverted = true;
+            }
+            ExpressionType::HtmlEscaped | ExpressionType::Raw => {
+                let Value::Text(value) = lookup(content)? else {
+                    return None;
+                };
+                if writing(&blocks) {
+                    let escaped =
+                        escape && matches!(expr.expression_type, ExpressionType::HtmlEscaped);
+                    match escaped {
+                        // values are escaped for the context the compiler would have chosen
+                        true if starts_url_attribute(
+                            &src[..offset(src, expr.prefix) + expr.prefix.len()],
+                        ) =>
+                        {
+                            write!(out, "{}", value.as_display_url()).ok()?
+                        }
+                        true => write!(out, "{}", value.as_display_html()).ok()?,
+                        false => write!(out, "{}", value).ok()?,
+                    }
+                }
+            }
+            ExpressionType::Open => {
+                let (helper, name) = content.split_once(char::is_whitespace)?;
+                let Value::Bool(condition) = lookup(name.trim())? else {
+                    return None;
+                };
+                let branch = match helper {
+                    "if" => *condition,
+                    "unless" => !*condition,
+                    _ => return None,
+                };
+                blocks.push(Block {
+                    helper,
+                    outer: writing(&blocks),
+                    branch,
+                    inverted: false,
+                });
+            }
+            ExpressionType::Close => {
+                if blocks.pop()?.helper != content {
+                    return None;
+                }
+            }
+        }
+        rest = expr.postfix;
+        expression = expr.next().ok()?;
+    }
+    if !blocks.is_empty() {
+        return None;
+    }
+    out.push_str(rest);
+    Some(out)
+}
@@ -0,0 +1,199 @@
+//! Comparison of rendered HTML for tests, see [`assert_html_eq!`](crate::assert_html_eq)
+
+/// Elements whose text is compared with its whitespace
+const PREFORMATTED: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Decodes character references, unknown ones are kept as written
+fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .filter(|name| name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#'));
+        let c = match reference {
+            Some("amp") => Some('&'),
+            Some("lt") => Some('<'),
+            Some("gt") => Some('>'),
+            Some("quot") => Some('"'),
+            Some("apos") => Some('\''),
+            Some("nbsp") => Some('\u{a0}'),
+            Some(code) => match code.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                None => None,
+            },
+            None => None,
+        };
+        match (c, reference) {
+            (Some(c), Some(reference)) => {
+                decoded.push(c);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Escapes text and attribute values the same way on both sides of a comparison
+fn encode(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Writes a tag with its name lowercased and its attributes sorted, returning its name
+fn write_tag(tag: &str, out: &mut String) -> String {
+    let closing = tag.starts_with('/');
+    let tag = tag.trim_start_matches('/').trim_end_matches('/');
+    let name_end = tag.find(|c: char| c.is_whitespace()).unwrap_or(tag.len());
+    let name = tag[..name_end].to_ascii_lowercase();
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let attribute = rest[..end].to_ascii_lowercase();
+        rest = rest[end..].trim_start();
+        let mut value = None;
+        if let Some(after) = rest.strip_prefix('=') {
+            let after = after.trim_start();
+            let (text, remaining) = match after.chars().next() {
+                Some(quote @ ('"' | '\'')) => match after[1..].find(quote) {
+                    Some(end) => (&after[1..end + 1], &after[end + 2..]),
+                    None => (&after[1..], ""),
+                },
+                _ => {
+                    let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                    (&after[..end], &after[end..])
+                }
+            };
+            value = Some(decode(text));
+            rest = remaining.trim_start();
+        }
+        attributes.push((attribute, value));
+    }
+    attributes.sort();
+
+    out.push('<');
+    if closing {
+        out.push('/');
+    }
+    out.push_str(&name);
+    for (attribute, value) in attributes {
+        out.push(' ');
+        out.push_str(&attribute);
+        if let Some(value) = value {
+            out.push_str("=\"");
+            encode(&value, out);
+            out.push('"');
+        }
+    }
+    out.push_str(">\n");
+    name
+}
+
+/// Normalizes HTML for comparison, one tag or text node per line
+///
+/// Tag and attribute names are lowercased, attributes are sorted, character references are
+/// decoded and escaped again, comments are dropped and whitespace in text is collapsed and
+/// trimmed except inside `pre`, `textarea`, `script` and `style`.
+pub fn normalize(html: impl AsRef<str>) -> String {
+    let mut out = String::new();
+    let mut rest = html.as_ref();
+    let mut preformatted: Option<String> = None;
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let tag_end = rest
+            .strip_prefix('<')
+            .filter(|tag| {
+                tag.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!')
+            })
+            .and_then(|tag| {
+                let mut quote = None;
+                tag.char_indices().find_map(|(i, c)| {
+                    match (quote, c) {
+                        (None, '"' | '\'') => quote = Some(c),
+                        (Some(open), c) if open == c => quote = None,
+                        (None, '>') => return Some(i + 1),
+                        _ => (),
+                    }
+                    None
+                })
+            });
+        if let Some(end) = tag_end {
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            let name = write_tag(tag, &mut out);
+            preformatted = match (preformatted, tag.starts_with('/')) {
+                (Some(open), true) if open == name => None,
+                (Some(open), _) => Some(open),
+                (None, false) if PREFORMATTED.contains(&name.as_str()) => Some(name),
+                (None, _) => None,
+            };
+            continue;
+        }
+        // text runs to the next tag, past a `<` that doesn't start one
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let end = rest[first..]
+            .find('<')
+            .map_or(rest.len(), |end| end + first);
+        let text = decode(&rest[..end]);
+        rest = &rest[end..];
+        let text = match preformatted {
+            Some(_) => text,
+            None => text.split_whitespace().collect::<Vec<_>>().join(" "),
+        };
+        if !text.is_empty() {
+            encode(&text, &mut out);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Asserts that two pieces of HTML are equivalent: attribute order, character references
+/// and insignificant whitespace are ignored, see [`html::normalize`](crate::html::normalize)
+///
+/// ```
+/// dry_handlebars::assert_html_eq!(
+///     "<p class='a' id=x>Tom &amp; Jerry</p>\n",
+///     r#"<p id="x" class="a">  Tom &#x26; Jerry </p>"#
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_html_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        assert_eq!(
+            $crate::html::normalize(&$actual),
+            $crate::html::normalize(&$expected)
+        )
+    };
+    ($actual:expr, $expected:expr, $($arg:tt)+) => {
+        assert_eq!(
+            $crate::html::normalize(&$actual),
+            $crate::html::normalize(&$expected),
+            $($arg)+
+        )
+    };
+}
@@ -1,240 +1,3717 @@
+pub use dry_handlebars_macros::Template;
 pub use dry_handlebars_macros::dry_handlebars_directory as directory;
 pub use dry_handlebars_macros::dry_handlebars_file as file;
+pub use dry_handlebars_macros::dry_handlebars_helper as helper;
 pub use dry_handlebars_macros::dry_handlebars_str as str;
 
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod html;
+/// The template parser and compiler the macros use, for build scripts and tools compiling
+/// templates programmatically
+#[cfg(feature = "parser")]
+pub use dry_handlebars_parser as parser;
+
+// generated code refers to this crate as `::dry_handlebars`, including in its own tests
+extern crate self as dry_handlebars;
+
+use std::fmt::{self, Display, Write};
+
+/// Writer adapter escaping HTML special characters as they are written
+struct HtmlEscaper<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Write for HtmlEscaper<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut last = 0;
+        for (i, c) in s.char_indices() {
+            let escaped = match c {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' => "&quot;",
+                '\'' => "&#x27;",
+                '`' => "&#x60;",
+                '=' => "&#x3D;",
+                _ => continue,
+            };
+            self.0.write_str(&s[last..i])?;
+            self.0.write_str(escaped)?;
+            last = i + 1;
+        }
+        self.0.write_str(&s[last..])
+    }
+}
+
+/// Displays a value with HTML special characters escaped, used for `{{ }}` output
+pub struct DisplayHtml<'a, T: ?Sized>(&'a T);
+
+impl<T: Display + ?Sized> Display for DisplayHtml<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(HtmlEscaper(f), "{}", self.0)
+    }
+}
+
+/// Escapes HTML for any `Display` value, `{{ value }}` renders `value.as_display_html()`
+pub trait AsDisplayHtml {
+    fn as_display_html(&self) -> DisplayHtml<'_, Self>;
+}
+
+impl<T: Display + ?Sized> AsDisplayHtml for T {
+    fn as_display_html(&self) -> DisplayHtml<'_, Self> {
+        DisplayHtml(self)
+    }
+}
+
+/// Implemented by every generated template, so generic code such as middleware, caches and
+/// tests can handle any of them
+///
+/// The trait shares its name with the derive macro, `#[derive(Template)]` implements it too.
+pub trait Template {
+    /// Name of the template, that of its struct
+    const NAME: &'static str;
+    /// Path of the template's file, `None` for `str!` templates
+    const SOURCE_PATH: Option<&'static str>;
+    /// Content type of the rendered output, `text/plain` for text templates such as
+    /// `email.txt.hbs` and `text/html` otherwise
+    const MIME_TYPE: &'static str;
+    /// Hash of the template's source as compiled, with its partials expanded, which changes
+    /// with the template and stays the same across builds, for cache keys and ETags
+    const CONTENT_HASH: u64;
+    /// Length of the compiled template's static text, that of every branch and loop body
+    const COMPILED_STATIC_LEN: usize;
+
+    /// Renders the template into a writer
+    fn render_into(&self, f: &mut dyn Write) -> fmt::Result;
+}
+
+/// Writer adapter writing the UTF-8 bytes of the output to an `io::Write`, used by
+/// `render_to_io`, keeping the I/O error behind the `fmt::Error` a failed write returns
+pub struct IoWriter<'a, W: std::io::Write + ?Sized> {
+    out: &'a mut W,
+    error: Option<std::io::Error>,
+}
+
+impl<'a, W: std::io::Write + ?Sized> IoWriter<'a, W> {
+    pub fn new(out: &'a mut W) -> Self {
+        Self { out, error: None }
+    }
+
+    /// Turns the result of rendering into the adapter into that of the I/O, a formatting
+    /// error of a value is reported as `io::ErrorKind::Other`
+    pub fn finish(self, result: fmt::Result) -> std::io::Result<()> {
+        match (result, self.error) {
+            (Ok(()), _) => Ok(()),
+            (Err(_), Some(err)) => Err(err),
+            (Err(err), None) => Err(std::io::Error::other(err)),
+        }
+    }
+}
+
+impl<W: std::io::Write + ?Sized> Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+/// HTML built by another crate, such as `maud` or `html!` fragments, written as is into the
+/// output of a variable mapped with `("sidebar", Markup => fragment)`
+///
+/// ```
+/// struct Markup(String);
+///
+/// impl dry_handlebars::RenderableFragment for Markup {
+///     fn render_fragment(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+///         f.write_str(&self.0)
+///     }
+/// }
+/// ```
+pub trait RenderableFragment {
+    fn render_fragment(&self, f: &mut dyn Write) -> fmt::Result;
+}
+
+impl<T: RenderableFragment + ?Sized> RenderableFragment for &T {
+    fn render_fragment(&self, f: &mut dyn Write) -> fmt::Result {
+        (**self).render_fragment(f)
+    }
+}
+
+impl<T: RenderableFragment + ?Sized> RenderableFragment for Box<T> {
+    fn render_fragment(&self, f: &mut dyn Write) -> fmt::Result {
+        (**self).render_fragment(f)
+    }
+}
+
+/// Displays a fragment without escaping, used for `{{ }}` output of a fragment variable
+pub struct Fragment<'a, T: ?Sized>(pub &'a T);
+
+impl<T: RenderableFragment + ?Sized> Display for Fragment<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.render_fragment(f)
+    }
+}
+
+/// Schemes allowed at the start of a URL attribute value, relative URLs have no scheme
+const SAFE_URL_SCHEMES: [&str; 4] = ["http", "https", "mailto", "tel"];
+
+/// Value written in place of a URL with an unsafe scheme
+const INVALID_URL: &str = "about:invalid#dry-handlebars";
+
+/// Returns true if a URL is relative or uses one of the safe schemes
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => SAFE_URL_SCHEMES
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(&url[..end])),
+        _ => true,
+    }
+}
+
+/// Returns a URL unchanged if it's relative or uses a safe scheme, otherwise
+/// `about:invalid#dry-handlebars`, used by `{{markdown}}` for link and image destinations
+pub fn safe_url(url: String) -> String {
+    match is_safe_url(&url) {
+        true => url,
+        false => INVALID_URL.to_string(),
+    }
+}
+
+/// Displays a value starting a URL attribute such as `href="{{ url }}"`, HTML escaped and
+/// replaced with `about:invalid#dry-handlebars` when it uses an unsafe scheme like `javascript:`
+pub struct DisplayUrl<'a, T: ?Sized>(&'a T);
+
+impl<T: Display + ?Sized> Display for DisplayUrl<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let url = self.0.to_string();
+        match is_safe_url(&url) {
+            true => HtmlEscaper(f).write_str(&url),
+            false => f.write_str(INVALID_URL),
+        }
+    }
+}
+
+/// Escapes values starting a URL attribute, `href="{{ value }}"` renders `value.as_display_url()`
+pub trait AsDisplayUrl {
+    fn as_display_url(&self) -> DisplayUrl<'_, Self>;
+}
+
+impl<T: Display + ?Sized> AsDisplayUrl for T {
+    fn as_display_url(&self) -> DisplayUrl<'_, Self> {
+        DisplayUrl(self)
+    }
+}
+
+/// Writer adapter percent-encoding everything but unreserved characters as it is written
+struct UrlEncoder<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Write for UrlEncoder<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    self.0.write_char(byte as char)?
+                }
+                _ => write!(self.0, "%{:02X}", byte)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays a value percent-encoded for a query string component, used by `{{urlencode value}}`
+pub struct UrlEncoded<'a, T: ?Sized>(pub &'a T);
+
+impl<T: Display + ?Sized> Display for UrlEncoded<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(UrlEncoder(f), "{}", self.0)
+    }
+}
+
+/// Writer adapter escaping HTML and replacing line breaks (`\n`, `\r\n` or `\r`) with `<br>`
+struct LineBreaker<'a, 'b> {
+    f: &'a mut fmt::Formatter<'b>,
+    /// A `\r` was the last character written, so a `\n` following it is part of its break
+    after_cr: bool,
+}
+
+impl Write for LineBreaker<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut last = 0;
+        for (i, c) in s.char_indices() {
+            if c != '\n' && c != '\r' {
+                continue;
+            }
+            HtmlEscaper(self.f).write_str(&s[last..i])?;
+            if c == '\r' || !(self.after_cr && i == last) {
+                self.f.write_str("<br>")?;
+            }
+            self.after_cr = c == '\r';
+            last = i + 1;
+        }
+        if last < s.len() {
+            self.after_cr = false;
+        }
+        HtmlEscaper(self.f).write_str(&s[last..])
+    }
+}
+
+/// Displays a value HTML-escaped with line breaks as `<br>`, used by `{{nl2br value}}`
+pub struct Nl2Br<'a, T: ?Sized>(pub &'a T);
+
+impl<T: Display + ?Sized> Display for Nl2Br<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut breaker = LineBreaker { f, after_cr: false };
+        write!(breaker, "{}", self.0)
+    }
+}
+
+/// A value that can be written with `Display` and `Debug`, the type generic fields given to
+/// `{{debug value}}` are borrowed as by templates built with `compact = true`
+pub trait DisplayDebug: Display + fmt::Debug {}
+
+impl<T: Display + fmt::Debug + ?Sized> DisplayDebug for T {}
+
+/// Truthiness of a generic field mapped to `impl AsBool` and tested by `{{#if}}`, as in
+/// Handlebars empty strings and collections, zero and `None` are falsy
+pub trait AsBool {
+    fn as_bool(&self) -> bool;
+}
+
+impl AsBool for bool {
+    fn as_bool(&self) -> bool {
+        *self
+    }
+}
+
+impl AsBool for str {
+    fn as_bool(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl AsBool for String {
+    fn as_bool(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> AsBool for [T] {
+    fn as_bool(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> AsBool for Vec<T> {
+    fn as_bool(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<T> AsBool for Option<T> {
+    fn as_bool(&self) -> bool {
+        self.is_some()
+    }
+}
+
+impl<T: AsBool + ?Sized> AsBool for &T {
+    fn as_bool(&self) -> bool {
+        (**self).as_bool()
+    }
+}
+
+impl<T: AsBool + ?Sized> AsBool for Box<T> {
+    fn as_bool(&self) -> bool {
+        (**self).as_bool()
+    }
+}
+
+macro_rules! number_as_bool {
+    ($($ty:ty),*) => {
+        $(impl AsBool for $ty {
+            fn as_bool(&self) -> bool {
+                *self != 0 as $ty
+            }
+        })*
+    };
+}
+
+number_as_bool!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+/// Displays the values paired with `true` separated by spaces, written by
+/// `{{class "card" active=is_active}}`
+pub struct ClassList<'a>(pub &'a [(&'a dyn Display, bool)]);
+
+impl Display for ClassList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (class, _) in self.0.iter().filter(|(_, included)| *included) {
+            if !first {
+                f.write_str(" ")?;
+            }
+            first = false;
+            class.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a value with its `Debug` implementation when the flag is set, used by
+/// `{{debug value}}` with `cfg!(debug_assertions)` unless `debug_in_release = true`
+pub struct Debugged<'a, T: ?Sized>(pub &'a T, pub bool);
+
+impl<T: fmt::Debug + ?Sized> Display for Debugged<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            true => write!(f, "{:?}", self.0),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Displays a number with its digits grouped by a separator and an optional fixed number of
+/// decimal places, used by `{{num total sep="," precision=2}}`
+pub struct Num<'a, T: ?Sized>(pub &'a T, pub &'a str, pub Option<usize>);
+
+impl<T: Display + ?Sized> Display for Num<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Num(value, sep, precision) = self;
+        let mut number = match precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => value.to_string(),
+        };
+        // integers ignore the precision of a format
+        if let Some(precision) = precision
+            && *precision > 0
+            && !number.contains('.')
+        {
+            number.push('.');
+            number.extend(std::iter::repeat_n('0', *precision));
+        }
+        let (sign, unsigned) = match number.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", number.as_str()),
+        };
+        let (integer, fraction) = match unsigned.find('.') {
+            Some(pos) => unsigned.split_at(pos),
+            None => (unsigned, ""),
+        };
+        f.write_str(sign)?;
+        for (i, digit) in integer.char_indices() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                f.write_str(sep)?;
+            }
+            f.write_char(digit)?;
+        }
+        f.write_str(fraction)
+    }
+}
+
+/// A piece of segmented output, static template text is borrowed and rendered values owned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+}
+
+impl Segment {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Segment::Static(bytes) => bytes,
+            Segment::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Output of `render_segments`, generated for templates with `segments = true`, which keeps
+/// the static text of the template as borrowed segments so it can be written with vectored I/O
+#[derive(Debug, Default)]
+pub struct Segments {
+    segments: Vec<Segment>,
+    /// Total length of the segments in bytes
+    len: usize,
+}
+
+impl Segments {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends static template text
+    pub fn push_static(&mut self, text: &'static str) {
+        if !text.is_empty() {
+            self.segments.push(Segment::Static(text.as_bytes()));
+            self.len += text.len();
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Total length of the output in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the segments as slices for `write_vectored`
+    pub fn io_slices(&self) -> Vec<std::io::IoSlice<'_>> {
+        self.segments
+            .iter()
+            .map(|s| std::io::IoSlice::new(s.as_bytes()))
+            .collect()
+    }
+
+    /// Writes all segments with `write_vectored`, retrying until everything is written
+    pub fn write_to(&self, out: &mut impl std::io::Write) -> std::io::Result<()> {
+        let mut slices = self.io_slices();
+        let mut slices = &mut slices[..];
+        while !slices.is_empty() {
+            match out.write_vectored(slices) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(written) => std::io::IoSlice::advance_slices(&mut slices, written),
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Joins the segments into one buffer
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for segment in &self.segments {
+            bytes.extend_from_slice(segment.as_bytes());
+        }
+        bytes
+    }
+}
+
+impl Write for Segments {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.segments.last_mut() {
+            Some(Segment::Owned(bytes)) => bytes.extend_from_slice(s.as_bytes()),
+            _ if s.is_empty() => {}
+            _ => self.segments.push(Segment::Owned(s.as_bytes().to_vec())),
+        }
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// A writer templates with `{{#hole "name"}}` blocks can be rendered to, deciding which holes
+/// are rendered, every hole is rendered in place by default
+pub trait HoleWriter: Write {
+    /// Called before the content of a hole, which is skipped when `false` is returned
+    fn enter_hole(&mut self, _name: &'static str) -> bool {
+        true
+    }
+
+    /// Called after the content of a hole, or in its place when it was skipped
+    fn leave_hole(&mut self, _name: &'static str) {}
+}
+
+impl HoleWriter for String {}
+
+impl HoleWriter for Segments {}
+
+/// A piece of a [`Shell`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellPart {
+    Static(String),
+    /// A hole by name, rendered by `render_hole`
+    Hole(&'static str),
+}
+
+/// Output of `render_shell`: the template with the content of its holes left out, so the
+/// shell can be cached by a CDN and the holes rendered by the origin
+#[derive(Debug, Default)]
+pub struct Shell {
+    parts: Vec<ShellPart>,
+}
+
+impl Shell {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parts(&self) -> &[ShellPart] {
+        &self.parts
+    }
+
+    /// Names of the holes, in order
+    pub fn holes(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.parts.iter().filter_map(|part| match part {
+            ShellPart::Static(_) => None,
+            ShellPart::Hole(name) => Some(*name),
+        })
+    }
+
+    /// Joins the shell with each hole replaced by the given HTML
+    pub fn fill(&self, mut hole: impl FnMut(&str) -> String) -> String {
+        let mut html = String::new();
+        for part in &self.parts {
+            match part {
+                ShellPart::Static(text) => html.push_str(text),
+                ShellPart::Hole(name) => html.push_str(&hole(name)),
+            }
+        }
+        html
+    }
+
+    /// Joins the shell with each hole replaced by an `<esi:include>` of the given URL
+    pub fn to_esi(&self, src: impl Fn(&str) -> String) -> String {
+        self.fill(|name| format!("<esi:include src=\"{}\"/>", src(name).as_display_html()))
+    }
+}
+
+impl Write for Shell {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.parts.last_mut() {
+            Some(ShellPart::Static(text)) => text.push_str(s),
+            _ if s.is_empty() => {}
+            _ => self.parts.push(ShellPart::Static(s.to_string())),
+        }
+        Ok(())
+    }
+}
+
+impl HoleWriter for Shell {
+    fn enter_hole(&mut self, name: &'static str) -> bool {
+        self.parts.push(ShellPart::Hole(name));
+        false
+    }
+}
+
+/// Output of `render_hole`: the content of the holes of one name, everything else is discarded
+#[derive(Debug)]
+pub struct HoleFragment<'a> {
+    name: &'a str,
+    /// Holes entered since the first one of the name, those nested in it included
+    depth: usize,
+    html: String,
+}
+
+impl<'a> HoleFragment<'a> {
+    pub fn new(name: &'a str) -> Self {
+        HoleFragment {
+            name,
+            depth: 0,
+            html: String::new(),
+        }
+    }
+
+    pub fn into_string(self) -> String {
+        self.html
+    }
+}
+
+impl Write for HoleFragment<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.depth > 0 {
+            self.html.push_str(s);
+        }
+        Ok(())
+    }
+}
+
+impl HoleWriter for HoleFragment<'_> {
+    fn enter_hole(&mut self, name: &'static str) -> bool {
+        if self.depth > 0 || name == self.name {
+            self.depth += 1;
+        }
+        true
+    }
+
+    fn leave_hole(&mut self, _name: &'static str) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+}
+
+/// Rendered items of `{{#each items cache_key="id"}}` by key, given to `render_with_cache`
+///
+/// The methods take `&self` so one cache can be shared between renders, implementations
+/// synchronize and evict as they need. It is implemented for maps in a `RefCell` or `Mutex`.
+pub trait FragmentCache {
+    fn get(&self, key: u64) -> Option<String>;
+    fn insert(&self, key: u64, html: String);
+}
+
+/// Cache used by `render`, every item is rendered
+pub struct NoCache;
+
+impl FragmentCache for NoCache {
+    fn get(&self, _key: u64) -> Option<String> {
+        None
+    }
+
+    fn insert(&self, _key: u64, _html: String) {}
+}
+
+impl FragmentCache for std::cell::RefCell<std::collections::HashMap<u64, String>> {
+    fn get(&self, key: u64) -> Option<String> {
+        self.borrow().get(&key).cloned()
+    }
+
+    fn insert(&self, key: u64, html: String) {
+        self.borrow_mut().insert(key, html);
+    }
+}
+
+impl FragmentCache for std::sync::Mutex<std::collections::HashMap<u64, String>> {
+    fn get(&self, key: u64) -> Option<String> {
+        self.lock().ok()?.get(&key).cloned()
+    }
+
+    fn insert(&self, key: u64, html: String) {
+        if let Ok(mut map) = self.lock() {
+            map.insert(key, html);
+        }
+    }
+}
+
+/// Hashes the key of a cached item with the template and the `each` it is rendered by, so
+/// items of different lists sharing a cache don't collide
+pub fn fragment_key(template: &str, block: &str, key: &impl std::hash::Hash) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    template.hash(&mut hasher);
+    block.hash(&mut hasher);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Never inlined into the branch calling it, so the optimizer lays that branch out of the
+/// hot path
+#[cold]
+fn cold() {}
+
+/// Condition of `{{#if value likely=true}}`, the branch not taken is marked cold
+#[inline(always)]
+pub fn likely(condition: bool) -> bool {
+    if !condition {
+        cold();
+    }
+    condition
+}
+
+/// Condition of `{{#if value likely=false}}`, the body is marked cold
+#[inline(always)]
+pub fn unlikely(condition: bool) -> bool {
+    if condition {
+        cold();
+    }
+    condition
+}
+
+/// A writer the output of a cached item can be read back from, by the number of bytes
+/// written before it
+pub trait FragmentWriter: Write {
+    fn fragment_start(&self) -> usize;
+    fn fragment_since(&self, start: usize) -> String;
+}
+
+impl FragmentWriter for String {
+    fn fragment_start(&self) -> usize {
+        self.len()
+    }
+
+    fn fragment_since(&self, start: usize) -> String {
+        self[start..].to_string()
+    }
+}
+
+impl FragmentWriter for Segments {
+    fn fragment_start(&self) -> usize {
+        self.len
+    }
+
+    fn fragment_since(&self, start: usize) -> String {
+        // the segments are walked back from the end, the first one may start before the item
+        let mut remaining = self.len - start;
+        let mut parts = Vec::new();
+        for segment in self.segments.iter().rev() {
+            if remaining == 0 {
+                break;
+            }
+            let bytes = segment.as_bytes();
+            let taken = remaining.min(bytes.len());
+            parts.push(&bytes[bytes.len() - taken..]);
+            remaining -= taken;
+        }
+        let bytes: Vec<u8> = parts.into_iter().rev().flatten().copied().collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl FragmentWriter for Shell {
+    fn fragment_start(&self) -> usize {
+        self.fill(|_| String::new()).len()
+    }
+
+    fn fragment_since(&self, start: usize) -> String {
+        self.fill(|_| String::new())[start..].to_string()
+    }
+}
+
+impl FragmentWriter for HoleFragment<'_> {
+    fn fragment_start(&self) -> usize {
+        self.html.len()
+    }
+
+    fn fragment_since(&self, start: usize) -> String {
+        self.html[start..].to_string()
+    }
+}
+
+/// Translates the keys of `{{t "checkout.title" name=user.name}}`, implemented for a Fluent
+/// bundle, gettext catalog or similar and given to `render_with_translator`
+pub trait Translator {
+    /// Writes the translation of a key, interpolating the named arguments
+    fn translate(
+        &self,
+        out: &mut dyn Write,
+        key: &str,
+        args: &[(&str, &dyn Display)],
+    ) -> fmt::Result;
+}
+
+/// Translator used by `render`, writes keys untranslated as gettext does for missing messages
+pub struct NoTranslation;
+
+impl Translator for NoTranslation {
+    fn translate(
+        &self,
+        out: &mut dyn Write,
+        key: &str,
+        _args: &[(&str, &dyn Display)],
+    ) -> fmt::Result {
+        out.write_str(key)
+    }
+}
+
+/// Displays a translated key, used by the `t` helper
+pub struct Translated<'a>(
+    pub &'a dyn Translator,
+    pub &'a str,
+    pub &'a [(&'a str, &'a dyn Display)],
+);
+
+impl Display for Translated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.translate(f, self.1, self.2)
+    }
+}
+
+/// Error of rendering data only known at runtime, as `render_value` does with the `json`
+/// feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    /// A variable of the template isn't in the data
+    Missing(String),
+    /// A variable's value doesn't fit its mapped type, with the reason
+    Invalid(String, String),
+    /// Writing the output failed
+    Fmt,
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Missing(name) => write!(f, "missing value for {}", name),
+            RenderError::Invalid(name, reason) => {
+                write!(f, "invalid value for {}: {}", name, reason)
+            }
+            RenderError::Fmt => f.write_str("failed to write the template"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<fmt::Error> for RenderError {
+    fn from(_: fmt::Error) -> Self {
+        RenderError::Fmt
+    }
+}
+
+/// Deterministic placeholder values for the `fixture()` constructors generated with the
+/// `test-fixtures` feature, `name` is the name of the field being filled
+///
+/// Implemented for strings, numbers, `bool` (`true`, so conditional content renders),
+/// `Option` (`Some`), collections (one item) and boxes, types used in mappings implement
+/// it to make their templates' fixtures compile.
+pub trait Fixture {
+    fn fixture(name: &str) -> Self;
+}
+
+impl Fixture for String {
+    fn fixture(name: &str) -> Self {
+        name.to_string()
+    }
+}
+
+impl Fixture for &'static str {
+    fn fixture(name: &str) -> Self {
+        // fixtures are built in tests, leaking keeps the placeholder the field's name
+        name.to_string().leak()
+    }
+}
+
+impl Fixture for bool {
+    fn fixture(_name: &str) -> Self {
+        true
+    }
+}
+
+impl Fixture for char {
+    fn fixture(_name: &str) -> Self {
+        'x'
+    }
+}
+
+macro_rules! number_fixtures {
+    ($($ty:ty = $value:expr),*) => {
+        $(impl Fixture for $ty {
+            fn fixture(_name: &str) -> Self {
+                $value
+            }
+        })*
+    };
+}
+
+number_fixtures!(
+    u8 = 1,
+    u16 = 1,
+    u32 = 1,
+    u64 = 1,
+    u128 = 1,
+    usize = 1,
+    i8 = 1,
+    i16 = 1,
+    i32 = 1,
+    i64 = 1,
+    i128 = 1,
+    isize = 1,
+    f32 = 1.5,
+    f64 = 1.5
+);
+
+impl<T: Fixture> Fixture for Option<T> {
+    fn fixture(name: &str) -> Self {
+        Some(T::fixture(name))
+    }
+}
+
+impl<T: Fixture> Fixture for Box<T> {
+    fn fixture(name: &str) -> Self {
+        Box::new(T::fixture(name))
+    }
+}
+
+impl<T: Fixture> Fixture for Vec<T> {
+    fn fixture(name: &str) -> Self {
+        vec![T::fixture(name)]
+    }
+}
+
+impl<T: Fixture> Fixture for &'static [T] {
+    fn fixture(name: &str) -> Self {
+        vec![T::fixture(name)].leak()
+    }
+}
+
+impl<K: Fixture + Ord, V: Fixture> Fixture for std::collections::BTreeMap<K, V> {
+    fn fixture(name: &str) -> Self {
+        Self::from([(K::fixture(name), V::fixture(name))])
+    }
+}
+
+impl<K: Fixture + Eq + std::hash::Hash, V: Fixture> Fixture for std::collections::HashMap<K, V> {
+    fn fixture(name: &str) -> Self {
+        Self::from([(K::fixture(name), V::fixture(name))])
+    }
+}
+
+/// A template of a `directory!` given `catalog = true`, listed by its `catalog()` so a style
+/// guide route can list and render every template
+#[derive(Debug, Clone, Copy)]
+pub struct CatalogEntry {
+    /// Path of the template in the directory without extension, e.g. `admin/index`
+    pub name: &'static str,
+    /// Path of the template's file
+    pub source_path: &'static str,
+    /// Content type of the rendered output, as [`Template::MIME_TYPE`]
+    pub mime_type: &'static str,
+    /// Renders the template built with placeholder values from `fixture()`
+    pub preview: fn() -> String,
+}
+
+impl CatalogEntry {
+    /// Finds the entry of a name, such as the last segment of a `/styleguide/{name}` route
+    pub fn find<'a>(entries: &'a [CatalogEntry], name: &str) -> Option<&'a CatalogEntry> {
+        entries.iter().find(|entry| entry.name == name)
+    }
+}
+
+/// Renders an HTML list of links to the previews of the entries, `href` gives the URL of an
+/// entry's preview from its name
+pub fn catalog_index(entries: &[CatalogEntry], href: impl Fn(&str) -> String) -> String {
+    let mut f = String::from("<ul>");
+    for entry in entries {
+        write!(
+            f,
+            "<li><a href=\"{}\">{}</a></li>",
+            href(entry.name).as_display_url(),
+            entry.name.as_display_html()
+        )
+        .unwrap();
+    }
+    f.push_str("</ul>");
+    f
+}
+
+/// The body of a custom block helper, renders the block content into the given writer
+///
+/// Block helpers are registered with `blocks = [("card", path::to::card)]` and called as
+/// `card(out, &arg, .., body)`:
+///
+/// ```
+/// fn card(out: &mut dyn std::fmt::Write, title: &str, body: &mut dry_handlebars::BlockBody) -> std::fmt::Result {
+///     write!(out, "<section><h2>{}</h2>", title)?;
+///     body(out)?;
+///     out.write_str("</section>")
+/// }
+/// ```
+pub type BlockBody<'a> = dyn FnMut(&mut dyn std::fmt::Write) -> std::fmt::Result + 'a;
+
 #[cfg(test)]
 mod tests {
 
     #[test]
-    fn basic_usage() {
-        mod template {
-            crate::str!("test", r#"<p>{{firstname}} {{lastname}}</p>"#);
+    fn basic_usage() {
+        mod template {
+            crate::str!("test", r#"<p>{{firstname}} {{lastname}}</p>"#);
+        }
+        assert_eq!(
+            template::test("King", "Tubby").render(),
+            "<p>King Tubby</p>"
+        );
+    }
+
+    struct Person {
+        firstname: String,
+        lastname: String,
+    }
+
+    #[test]
+    fn path_expressions() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{person.firstname}} {{person.lastname}}"#,
+                ("person", super::Person)
+            );
+        }
+        let person = Person {
+            firstname: "King".to_string(),
+            lastname: "Tubby".to_string(),
+        };
+        assert_eq!(template::test(person).render(), "King Tubby");
+    }
+
+    struct Page {
+        title: String,
+        links: Vec<String>,
+    }
+
+    #[test]
+    fn context_struct_conversions() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<h1>{{page.title}}</h1>{{#each page.links}}<a>{{this}}</a>{{/each}}"#,
+                ("page", super::Page)
+            );
+        }
+        let page = Page {
+            title: "Home".to_string(),
+            links: vec!["About".to_string()],
+        };
+        assert_eq!(
+            template::test::render_page(&page),
+            //language=html
+            "<h1>Home</h1><a>About</a>"
+        );
+        let from_page: template::test = page.into();
+        assert_eq!(
+            from_page.render(),
+            //language=html
+            "<h1>Home</h1><a>About</a>"
+        );
+    }
+
+    struct Author {
+        first_name: String,
+        last_name: String,
+    }
+
+    #[test]
+    fn if_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#if has_author}}<h1>{{first_name}} {{last_name}}</h1>{{/if}}</div>"#
+            );
+        }
+        assert_eq!(
+            template::test(true, "King", "Tubby").render(),
+            //language=html
+            "<div><h1>King Tubby</h1></div>"
+        );
+        assert_eq!(
+            template::test(false, "King", "Tubby").render(),
+            //language=html
+            "<div></div>"
+        );
+    }
+
+    #[test]
+    fn unless_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#unless has_author}}<h1>Unknown</h1>{{/unless}}</div>"#
+            );
+        }
+        assert_eq!(
+            template::test(false).render(),
+            //language=html
+            "<div><h1>Unknown</h1></div>"
+        );
+        assert_eq!(
+            template::test(true).render(),
+            //language=html
+            "<div></div>"
+        );
+    }
+
+    #[test]
+    fn if_else_helper() {
+        // author is never read, which is reported as a warning
+        #[allow(deprecated)]
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#if has_author}}<h1>{{first_name}}</h1>{{else}}<h1>Unknown</h1>{{/if}}</div>"#,
+                ("author", Option<super::Author>)
+            );
+        }
+        assert_eq!(
+            template::test(true, "King").render(),
+            //language=html
+            r#"<div><h1>King</h1></div>"#
+        );
+        assert_eq!(
+            template::test(false, "King").render(),
+            //language=html
+            r#"<div><h1>Unknown</h1></div>"#
+        );
+    }
+
+    #[test]
+    fn with_helper_option() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#with author}}<h1>{{first_name}} {{last_name}}</h1>{{/with}}</div>"#,
+                ("author", Option<super::Author>)
+            );
+        }
+        let author = Author {
+            first_name: "King".to_string(),
+            last_name: "Tubby".to_string(),
+        };
+        assert_eq!(
+            template::test(Some(author)).render(),
+            //language=html
+            "<div><h1>King Tubby</h1></div>"
+        );
+        assert_eq!(
+            template::test(None).render(),
+            //language=html
+            "<div></div>"
+        );
+    }
+
+    #[test]
+    fn with_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#with author}}<h1>{{first_name}} {{last_name}}</h1>{{/with}}</div>"#,
+                ("author", super::Author)
+            );
+        }
+        let author = Author {
+            first_name: "King".to_string(),
+            last_name: "Tubby".to_string(),
+        };
+        assert_eq!(
+            template::test(author).render(),
+            //language=html
+            "<div><h1>King Tubby</h1></div>"
+        );
+    }
+
+    #[test]
+    fn dig_helper() {
+        use std::collections::HashMap;
+        mod template {
+            use std::collections::HashMap;
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{#with (dig config "server" "port") as |port|}}:{{port}}{{else}}none{{/with}} {{dig config "client" "port"}}"#,
+                ("config", HashMap<String, HashMap<String, u16>>)
+            );
+        }
+        let mut config = HashMap::new();
+        assert_eq!(template::test(config.clone()).render(), "none ");
+        config.insert(
+            "server".to_string(),
+            HashMap::from([("port".to_string(), 8080)]),
+        );
+        config.insert("client".to_string(), HashMap::new());
+        assert_eq!(template::test(config).render(), ":8080 ");
+    }
+
+    #[test]
+    fn with_helper_default() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<body class="{{#with (try_lookup settings "theme") default="light" as |theme|}}{{theme}}{{/with}}">"#,
+                ("settings", std::collections::HashMap<String, String>)
+            );
+        }
+        let mut settings = std::collections::HashMap::new();
+        assert_eq!(
+            template::test(settings.clone()).render(),
+            //language=html
+            r#"<body class="light">"#
+        );
+        settings.insert("theme".to_string(), "dark".to_string());
+        assert_eq!(
+            template::test(settings).render(),
+            //language=html
+            r#"<body class="dark">"#
+        );
+    }
+
+    #[test]
+    fn for_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div>{{#each authors}}<p>Hello {{first_name}}</p>{{/each}}</div>"#,
+                ("authors", Vec<super::Author>)
+            );
+        }
+        let author = Author {
+            first_name: "King".to_string(),
+            last_name: "Tubby".to_string(),
+        };
+        assert_eq!(
+            template::test(vec![author]).render(),
+            //language=html
+            "<div><p>Hello King</p></div>"
+        );
+    }
+
+    trait Widget {
+        fn title(&self) -> String;
+        fn size(&self) -> usize;
+    }
+
+    struct Clock;
+
+    impl Widget for Clock {
+        fn title(&self) -> String {
+            "Clock".to_string()
+        }
+        fn size(&self) -> usize {
+            2
+        }
+    }
+
+    #[test]
+    fn trait_object_context() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<div class="w{{widget.size}}">{{widget.title}}</div>"#,
+                ("widget", dyn super::Widget)
+            );
+        }
+        assert_eq!(
+            template::test(Box::new(Clock)).render(),
+            //language=html
+            r#"<div class="w2">Clock</div>"#
+        );
+    }
+
+    struct Address {
+        city: String,
+    }
+
+    struct Customer {
+        address: Option<Address>,
+    }
+
+    #[test]
+    fn optional_chaining() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<p>{{customer?.address?.city}}</p>"#,
+                ("customer", Option<super::Customer>)
+            );
+        }
+        let customer = |city: Option<&str>| Customer {
+            address: city.map(|city| Address {
+                city: city.to_string(),
+            }),
+        };
+        assert_eq!(
+            template::test(Some(customer(Some("Kingston")))).render(),
+            //language=html
+            "<p>Kingston</p>"
+        );
+        assert_eq!(
+            template::test(Some(customer(None))).render(),
+            //language=html
+            "<p></p>"
+        );
+        assert_eq!(
+            template::test(None).render(),
+            //language=html
+            "<p></p>"
+        );
+    }
+
+    mod blocks {
+        pub fn card(
+            out: &mut dyn std::fmt::Write,
+            title: &str,
+            body: &mut crate::BlockBody,
+        ) -> std::fmt::Result {
+            write!(out, "<section><h2>{}</h2>", title)?;
+            body(out)?;
+            out.write_str("</section>")
+        }
+    }
+
+    #[test]
+    fn custom_block_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{#card title}}<p>{{content}}</p>{{/card}}"#,
+                ("title", String),
+                blocks = [("card", super::blocks::card)]
+            );
+        }
+        assert_eq!(
+            template::test("News".to_string(), "All quiet").render(),
+            //language=html
+            "<section><h2>News</h2><p>All quiet</p></section>"
+        );
+    }
+
+    mod helpers {
+        #[crate::helper]
+        pub fn shout(value: &str) -> String {
+            value.to_uppercase()
+        }
+
+        #[crate::helper]
+        pub fn site() -> &'static str {
+            "example.com"
+        }
+
+        #[crate::helper]
+        pub fn icon(name: &str) -> String {
+            format!("<i class=\"icon-{}\"></i>", name)
+        }
+    }
+
+    #[test]
+    fn safe_helper_output() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{icon name}} {{shout name}}"#,
+                ("name", String),
+                helpers = [super::helpers::icon => safe, super::helpers::shout => text]
+            );
+        }
+        assert_eq!(
+            template::test("<b>".to_string()).render(),
+            r#"<i class="icon-<b>"></i> &lt;B&gt;"#
+        );
+    }
+
+    #[test]
+    fn zero_argument_helpers() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{site}} {{pid}} {{name}}"#,
+                helpers = [super::helpers::site, ("pid", std::process::id)]
+            );
+        }
+        assert_eq!(
+            template::test("King").render(),
+            format!("example.com {} King", std::process::id())
+        );
+    }
+
+    #[test]
+    fn registered_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<p>{{shout name}}!</p>"#,
+                ("name", String),
+                helpers = [super::helpers::shout]
+            );
+        }
+        assert_eq!(
+            template::test("King".to_string()).render(),
+            //language=html
+            "<p>KING!</p>"
+        );
+    }
+
+    #[test]
+    fn test_comment() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"Note: {{! This is a comment }} and {{!-- {{so is this}} --}}\\{{{{}}"#,
+            );
+        }
+        assert_eq!(template::test().render(), "Note:  and \\{{");
+    }
+
+    #[test]
+    fn test_trimming() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"  {{~#if some ~}}   Hello{{~/if~}}"#,
+            );
+        }
+        assert_eq!(template::test(true).render(), "Hello");
+    }
+
+    #[test]
+    fn it_works() {
+        mod template {
+            crate::str!("test", "Hello {{{name}}}!");
+        }
+        assert_eq!(template::test("King").render(), "Hello King!");
+    }
+
+    #[test]
+    fn test_escaped() {
+        mod template {
+            crate::str!(
+                "test",
+                "{{{{skip}}}}wang doodle {{{{/dandy}}}}{{{{/skip}}}}"
+            );
+        }
+        assert_eq!(template::test().render(), "wang doodle {{{{/dandy}}}}");
+    }
+
+    #[test]
+    fn test_format_number() {
+        mod template {
+            crate::str!("test", "Price: ${{format \"{:.2}\" price}}");
+        }
+        assert_eq!(template::test(12.2345f64).render(), "Price: $12.23");
+    }
+
+    #[test]
+    fn test_html_escaping() {
+        mod template {
+            crate::str!("test", r#"{{name}} {{{name}}}"#);
+        }
+        assert_eq!(
+            template::test("<b>'Tubby' & co</b>").render(),
+            "&lt;b&gt;&#x27;Tubby&#x27; &amp; co&lt;/b&gt; <b>'Tubby' & co</b>"
+        );
+    }
+
+    #[test]
+    fn test_string_helpers() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{upper name}}|{{lower name}}|{{capitalize title}}|[{{trim padded}}]|{{replace name "Tubby" "Jammy"}}|{{truncate title 4}}"#,
+            );
+        }
+        assert_eq!(
+            template::test("King <Tubby>", "dub plate", "  x  ").render(),
+            "KING &lt;TUBBY&gt;|king &lt;tubby&gt;|Dub plate|[x]|King &lt;Jammy&gt;|dub \u{2026}"
+        );
+    }
+
+    #[test]
+    fn truncate_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                r#"{{truncate title 4}}|{{{truncate title 4}}}|{{truncate title 20}}|{{truncate title 0}}"#,
+            );
+        }
+        // escaped after truncating, like any value, with an ellipsis only when cut
+        assert_eq!(
+            template::test("<b>bold</b>").render(),
+            "&lt;b&gt;b\u{2026}|<b>b\u{2026}|&lt;b&gt;bold&lt;/b&gt;|\u{2026}"
+        );
+        assert_eq!(
+            template::test("Caf\u{e9}").render(),
+            "Caf\u{e9}|Caf\u{e9}|Caf\u{e9}|\u{2026}"
+        );
+        assert_eq!(
+            template::test("Caf\u{e9}s").render(),
+            "Caf\u{e9}\u{2026}|Caf\u{e9}\u{2026}|Caf\u{e9}s|\u{2026}"
+        );
+        assert_eq!(template::test("").render(), "|||");
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        mod template {
+            fn gt(a: f64, b: f64) -> bool {
+                a > b
+            }
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{lookup scores 1}} {{scores.[0]}}{{#if (gt price 9.99)}} pricey{{/if}}"#,
+                ("scores", Vec<u32>),
+                ("price", f64)
+            );
+        }
+        assert_eq!(template::test(vec![3, 5], 12.5).render(), "5 3 pricey");
+        assert_eq!(template::test(vec![3, 5], 5.0).render(), "5 3");
+    }
+
+    #[test]
+    fn test_lookup_from_end() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{lookup items -1}}|{{last items}}|{{lookup items -3}}"#,
+                ("items", Vec<&'static str>)
+            );
+        }
+        assert_eq!(template::test(vec!["a", "b"]).render(), "b|b|");
+        assert_eq!(template::test(vec![]).render(), "||");
+    }
+
+    struct Catalog;
+
+    impl crate::Translator for Catalog {
+        fn translate(
+            &self,
+            out: &mut dyn std::fmt::Write,
+            key: &str,
+            args: &[(&str, &dyn std::fmt::Display)],
+        ) -> std::fmt::Result {
+            match (key, args) {
+                ("greeting", [("name", name)]) => write!(out, "Bonjour {}", name),
+                ("title", []) => out.write_str("Panier & paiement"),
+                _ => out.write_str(key),
+            }
+        }
+    }
+
+    #[test]
+    fn translation_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<h1>{{t "title"}}</h1><p>{{t "greeting" name=user}}</p>"#
+            );
+        }
+        let template = template::test("<b>");
+        assert_eq!(
+            template.render_with_translator(&super::tests::Catalog),
+            "<h1>Panier &amp; paiement</h1><p>Bonjour &lt;b&gt;</p>"
+        );
+        assert_eq!(template.render(), "<h1>title</h1><p>greeting</p>");
+    }
+
+    #[test]
+    fn segmented_output() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<p>{{name}} {{{name}}}</p>{{#if show}}"shown"{{/if}}"#,
+                segments = true
+            );
+        }
+        let template = template::test("<b>", true);
+        let segments = template.render_segments();
+        assert_eq!(
+            segments.segments(),
+            [
+                crate::Segment::Static(b"<p>"),
+                crate::Segment::Owned(b"&lt;b&gt;".to_vec()),
+                crate::Segment::Static(b" "),
+                crate::Segment::Owned(b"<b>".to_vec()),
+                crate::Segment::Static(b"</p>"),
+                crate::Segment::Static(b"\"shown\""),
+            ]
+        );
+        let mut out = Vec::new();
+        segments.write_to(&mut out).unwrap();
+        assert_eq!(out, template.render().into_bytes());
+        assert_eq!(segments.to_vec(), out);
+    }
+
+    #[derive(crate::Template)]
+    #[template(
+        source = "<h1>{{title}}</h1>{{#each tags}}<i>{{this}}</i>{{/each}}{{#if published}}!{{/if}}"
+    )]
+    struct Article {
+        title: String,
+        tags: Vec<&'static str>,
+        published: bool,
+    }
+
+    #[derive(crate::Template)]
+    #[template(path = "inline.hbs")]
+    struct Greeting<'a> {
+        name: &'a str,
+    }
+
+    #[test]
+    fn derived_template() {
+        let article = Article {
+            title: "<T>".to_string(),
+            tags: vec!["a", "b"],
+            published: true,
+        };
+        assert_eq!(article.render(), "<h1>&lt;T&gt;</h1><i>a</i><i>b</i>!");
+        assert_eq!(Greeting { name: "Ann" }.render(), "Hi Ann");
+    }
+
+    #[test]
+    fn shell_and_holes() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"<nav>{{#hole "cart"}}{{count}} items{{/hole}}</nav><main>{{#hole "user"}}Hi {{name}}{{#hole "badge"}}!{{/hole}}{{/hole}}</main>"#,
+                ("count", u32)
+            );
+        }
+        let template = template::test(3, "<b>");
+        assert_eq!(
+            template.render(),
+            "<nav>3 items</nav><main>Hi &lt;b&gt;!</main>"
+        );
+        let shell = template.render_shell();
+        assert_eq!(
+            shell.parts(),
+            [
+                crate::ShellPart::Static("<nav>".to_string()),
+                crate::ShellPart::Hole("cart"),
+                crate::ShellPart::Static("</nav><main>".to_string()),
+                crate::ShellPart::Hole("user"),
+                crate::ShellPart::Static("</main>".to_string()),
+            ]
+        );
+        assert_eq!(shell.holes().collect::<Vec<_>>(), ["cart", "user"]);
+        assert_eq!(
+            shell.to_esi(|name| format!("/holes?name={}&v=1", name)),
+            "<nav><esi:include src=\"/holes?name&#x3D;cart&amp;v&#x3D;1\"/></nav><main><esi:include src=\"/holes?name&#x3D;user&amp;v&#x3D;1\"/></main>"
+        );
+        assert_eq!(template.render_hole("cart"), "3 items");
+        assert_eq!(template.render_hole("user"), "Hi &lt;b&gt;!");
+        assert_eq!(template.render_hole("badge"), "!");
+        assert_eq!(template.render_hole("other"), "");
+        assert_eq!(
+            shell.fill(|name| template.render_hole(name)),
+            template.render()
+        );
+    }
+
+    #[test]
+    fn hole_names_are_strings() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        for name in ["'cart'", "1", "#cart", "cart", ""] {
+            let error = compiler
+                .compile(&format!("{{{{#hole {}}}}}x{{{{/hole}}}}", name))
+                .unwrap_err()
+                .to_string();
+            assert!(
+                error.starts_with("expected a double quoted name after hole"),
+                "{}: {}",
+                name,
+                error
+            );
+        }
+        assert!(compiler.compile("{{#hole \"cart\"}}x{{/hole}}").is_ok());
+    }
+
+    struct Card {
+        title: String,
+        author: Author,
+        published: bool,
+    }
+
+    #[test]
+    fn context_checked() {
+        mod templates {
+            crate::str!(
+                "card",
+                "{{#if published}}<h1>{{title}}</h1>{{/if}}<p>{{author.first_name}}</p>",
+                ("author", super::Author),
+                context = super::Card
+            );
+        }
+        let card = Card {
+            title: "Dub".to_string(),
+            author: Author {
+                first_name: "King".to_string(),
+                last_name: "Tubby".to_string(),
+            },
+            published: true,
+        };
+        assert_eq!(
+            templates::card(card.published, card.title, card.author).render(),
+            "<h1>Dub</h1><p>King</p>"
+        );
+    }
+
+    #[test]
+    fn if_and_each() {
+        mod templates {
+            crate::str!(
+                "test",
+                "{{#if tags}}<ul>{{#each tags}}<li>{{this}}</li>{{/each}}</ul>{{else}}none{{/if}}{{#unless tags}}!{{/unless}}",
+                ("tags", Vec<&'static str>)
+            );
+        }
+        assert_eq!(
+            templates::test(vec!["a", "b"]).render(),
+            "<ul><li>a</li><li>b</li></ul>"
+        );
+        assert_eq!(templates::test(vec![]).render(), "none!");
+    }
+
+    pub struct Product {
+        id: u32,
+        name: &'static str,
+    }
+
+    pub struct Tagged {
+        id: u32,
+        name: &'static str,
+        tags: Vec<&'static str>,
+    }
+
+    #[test]
+    fn cached_each() {
+        mod templates {
+            crate::str!(
+                "test",
+                "{{#each products cache_key=\"id\"}}<li>{{name}}</li>{{else}}none{{/each}}",
+                ("products", Vec<super::Product>)
+            );
+        }
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        let products = vec![Product { id: 1, name: "a" }, Product { id: 2, name: "b" }];
+        let template = templates::test(products);
+        let cache = RefCell::new(HashMap::new());
+        assert_eq!(template.render_with_cache(&cache), "<li>a</li><li>b</li>");
+        assert_eq!(cache.borrow().len(), 2);
+        // a cached item is written as cached, without being rendered again
+        for html in cache.borrow_mut().values_mut() {
+            *html = html.to_uppercase();
+        }
+        assert_eq!(template.render_with_cache(&cache), "<LI>A</LI><LI>B</LI>");
+        assert_eq!(template.render(), "<li>a</li><li>b</li>");
+        assert_eq!(templates::test(vec![]).render_with_cache(&cache), "none");
+    }
+
+    #[test]
+    fn cached_each_second_render() {
+        mod templates {
+            crate::str!(
+                "test",
+                "{{#each products cache_key=\"id\"}}<li>{{name}}\
+                 {{#each tags}}<i id=\"t{{@index}}\">{{this}}</i>{{/each}}</li>{{/each}}",
+                ("products", Vec<super::Tagged>)
+            );
+        }
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        let product = |id, name| Tagged {
+            id,
+            name,
+            tags: vec!["x", "y"],
+        };
+        let cache = RefCell::new(HashMap::new());
+        let template = templates::test(vec![product(1, "a"), product(2, "b")]);
+        let first = template.render_with_cache(&cache);
+        assert_eq!(
+            first,
+            "<li>a<i id=\"t0\">x</i><i id=\"t1\">y</i></li><li>b<i id=\"t0\">x</i><i id=\"t1\">y</i></li>"
+        );
+        assert_eq!(template.render_with_cache(&cache), first);
+        assert_eq!(template.render(), first);
+        // reordered, each item is written as cached and renders as it would uncached
+        let template = templates::test(vec![product(2, "b"), product(1, "a")]);
+        assert_eq!(template.render_with_cache(&cache), template.render());
+        assert_eq!(cache.borrow().len(), 2);
+    }
+
+    #[test]
+    fn cached_each_outside_item() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let error = |src: &str| compiler.compile(src).unwrap_err().to_string();
+        for body in [
+            "{{@index}}",
+            "{{#if @first}}first{{/if}}",
+            "{{#if @last}}last{{/if}}",
+            "{{../title}}",
+            "{{@root.title}}",
+            "{{#each tags}}{{@../index}}{{/each}}",
+            "<p nonce=\"{{nonce}}\"></p>",
+        ] {
+            let message = error(&format!(
+                "{{{{#each products cache_key=\"id\"}}}}{}{{{{/each}}}}",
+                body
+            ));
+            assert!(
+                message.starts_with("cache_key can't cache items reading {{"),
+                "{}: {}",
+                body,
+                message
+            );
+        }
+        assert!(
+            compiler
+                .compile("{{#each products cache_key=\"id\"}}{{#each tags}}{{@index}}{{/each}}{{/each}}{{title}}")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn defer_blocks() {
+        mod templates {
+            crate::str!(
+                "test",
+                "<body>{{#each items}}<a>{{this}}</a>{{#defer}}<dialog>{{this}}</dialog>{{/defer}}{{/each}}<hr>{{deferred}}</body>",
+                ("items", Vec<&'static str>)
+            );
+            crate::str!(
+                "trailing",
+                "{{#defer}}<script>{{src}}</script>{{/defer}}<p>{{name}}</p>"
+            );
+        }
+        assert_eq!(
+            templates::test(vec!["a", "<b>"]).render(),
+            "<body><a>a</a><a>&lt;b&gt;</a><hr><dialog>a</dialog><dialog>&lt;b&gt;</dialog></body>"
+        );
+        assert_eq!(
+            templates::trailing("x", "y").render(),
+            "<p>y</p><script>x</script>"
+        );
+    }
+
+    pub struct Markup(String);
+
+    impl crate::RenderableFragment for Markup {
+        fn render_fragment(&self, f: &mut dyn std::fmt::Write) -> std::fmt::Result {
+            f.write_str(&self.0)
+        }
+    }
+
+    #[test]
+    fn fragment_values() {
+        mod templates {
+            crate::str!(
+                "test",
+                "<aside>{{sidebar}}</aside>{{{sidebar}}}<p>{{name}}</p>",
+                ("sidebar", super::Markup => fragment)
+            );
+        }
+        assert_eq!(
+            templates::test(Markup("<ul><li>a</li></ul>".to_string()), "<b>").render(),
+            "<aside><ul><li>a</li></ul></aside><ul><li>a</li></ul><p>&lt;b&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn each_else() {
+        mod templates {
+            crate::str!(
+                "indexed",
+                "{{#each items}}{{! an item }}{{@index}}:{{this}} {{else}}none{{/each}}|{{#each items}}{{@index}}{{/each}}",
+                ("items", Vec<&'static str>)
+            );
+            crate::str!(
+                "nested",
+                "{{#each rows as |row|}}[{{#each row}}{{@index}}{{this}}{{else}}-{{/each}}]{{else}}empty{{/each}}",
+                ("rows", Vec<Vec<u32>>)
+            );
+            crate::str!(
+                "inner",
+                "{{#each items}}{{#if (is_empty this)}}?{{else}}{{this}}{{/if}}{{else}}{{#each fallback}}{{@index}}{{this}}{{/each}}{{/each}}",
+                ("items", Vec<&'static str>),
+                ("fallback", Vec<&'static str>)
+            );
+        }
+        assert_eq!(templates::indexed(vec!["a", "b"]).render(), "0:a 1:b |01");
+        assert_eq!(templates::indexed(vec![]).render(), "none|");
+        assert_eq!(
+            templates::nested(vec![vec![1, 2], vec![]]).render(),
+            "[0112][-]"
+        );
+        assert_eq!(templates::nested(vec![]).render(), "empty");
+        assert_eq!(templates::inner(vec!["a", ""], vec![]).render(), "a?");
+        assert_eq!(templates::inner(vec![], vec!["x", "y"]).render(), "0x1y");
+    }
+
+    #[test]
+    fn configured_names() {
+        mod templates {
+            crate::str!(
+                "test",
+                "<p>{{name}}</p>{{#each items}}{{this}}{{/each}}",
+                ("items", Vec<u32>),
+                root_var_name = ctx,
+                write_var_name = out,
+                escape = "none",
+                segments = true
+            );
+        }
+        let template = templates::test("<b>", vec![1, 2]);
+        assert_eq!(template.render(), "<p><b></p>12");
+        assert_eq!(template.render_segments().to_vec(), b"<p><b></p>12");
+    }
+
+    #[test]
+    fn visibility() {
+        mod templates {
+            // a private type can be a field of a template no more visible than it
+            pub(super) struct Badge;
+
+            impl std::fmt::Display for Badge {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    f.write_str("*")
+                }
+            }
+
+            crate::str!("test", "{{badge}} {{name}}", ("badge", Badge), vis = pub(super));
+        }
+        assert_eq!(templates::test(templates::Badge, "Jo").render(), "* Jo");
+    }
+
+    #[test]
+    fn optional_condition() {
+        mod templates {
+            crate::str!(
+                "test",
+                "{{#if nickname}}aka {{nickname}}{{else}}{{name}}{{/if}}{{#unless title}}!{{/unless}}",
+                ("nickname", Option<String>),
+                ("title", Option<&'static str>),
+                infer_bool = false
+            );
+        }
+        assert_eq!(
+            templates::test(Some("<Ace>".to_string()), "Jo", None).render(),
+            "aka &lt;Ace&gt;!"
+        );
+        assert_eq!(templates::test(None, "Jo", Some("Dr")).render(), "Jo");
+    }
+
+    #[test]
+    fn partial_indentation() {
+        mod templates {
+            crate::file!("tests/templates/page.hbs");
+            crate::str!(
+                "unindented",
+                "<ul>\n  {{> tests/templates/item}}\n</ul>",
+                partial_indent = false
+            );
+        }
+        assert_eq!(
+            templates::page("King").render(),
+            "<ul>\n  <li>King</li>\n  <li>two</li>\n</ul>\n<p>Hi King</p>\n"
+        );
+        assert_eq!(
+            templates::unindented("King").render(),
+            "<ul>\n<li>King</li>\n<li>two</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn preformatted_whitespace() {
+        mod templates {
+            crate::str!(
+                "minified",
+                "<div>\n  <pre>\n  <b>{{a}}</b>\n  <i>x</i>\n</pre>\n  <textarea>\n  <p>\n  </textarea>\n</div>",
+                minify = true
+            );
+            crate::str!(
+                "indented",
+                "<section>\n  {{> tests/preformatted/listing}}\n  <pre>\n  {{> tests/preformatted/lines}}\n</pre>\n</section>"
+            );
+        }
+        assert_eq!(
+            templates::minified("a").render(),
+            "<div><pre>\n  <b>a</b>\n  <i>x</i>\n</pre><textarea>\n  <p>\n  </textarea></div>"
+        );
+        assert_eq!(
+            templates::indented("run();").render(),
+            "<section>\n  <pre>\nfn main() {\n    run();\n}\n</pre>\n  <pre>\n    line one\n\tline two\n\n</pre>\n</section>"
+        );
+    }
+
+    #[test]
+    fn template_constants() {
+        mod templates {
+            crate::str!(
+                "footer",
+                "{{!-- @const brand = \"Acme & Co\" --}}{{! @const year = 2024 }}<p>{{brand}} {{year}}</p><b>{{{brand}}}</b><i>{{upper brand}}</i>{{name}}"
+            );
+        }
+        assert_eq!(
+            templates::footer("Zed").render(),
+            "<p>Acme &amp; Co 2024</p><b>Acme & Co</b><i>ACME &amp; CO</i>Zed"
+        );
+    }
+
+    #[test]
+    fn nl2br_helper() {
+        mod templates {
+            crate::str!("comment", "<p>{{nl2br comment}}</p>");
+        }
+        assert_eq!(
+            templates::comment("a < b\nc\r\n\r\nd\re").render(),
+            "<p>a &lt; b<br>c<br><br>d<br>e</p>"
+        );
+    }
+
+    #[test]
+    fn configured_template_root() {
+        mod templates {
+            // found through template_roots in dry-handlebars.toml
+            crate::file!("inline.hbs");
+        }
+        assert_eq!(templates::inline("King").render(), "Hi King");
+    }
+
+    #[test]
+    fn repeat_block() {
+        mod templates {
+            crate::str!(
+                "rating",
+                "{{#repeat stars}}★{{/repeat}}{{#repeat 3 as |i|}}<b>{{i}}{{@index}}</b>{{/repeat}}",
+                ("stars", u8)
+            );
+        }
+        assert_eq!(
+            templates::rating(2).render(),
+            "★★<b>00</b><b>11</b><b>22</b>"
+        );
+    }
+
+    #[test]
+    fn chunks_helper() {
+        mod templates {
+            crate::str!(
+                "grid",
+                "{{#each (chunks products 2)}}<tr>{{#each this}}<td>{{this}}</td>{{/each}}</tr>{{/each}}",
+                ("products", Vec<&'static str>)
+            );
+        }
+        assert_eq!(
+            templates::grid(vec!["a", "b", "c"]).render(),
+            "<tr><td>a</td><td>b</td></tr><tr><td>c</td></tr>"
+        );
+    }
+
+    struct Post {
+        title: &'static str,
+        date: u32,
+        published: bool,
+    }
+
+    #[test]
+    fn sort_filter_and_take() {
+        mod templates {
+            crate::str!(
+                "latest",
+                "{{#each (take (sort_by (filter posts \"published\") \"date\" desc=true) 2)}}{{title}} {{/each}}|{{#each (sort_by posts \"title\")}}{{title}} {{/each}}",
+                ("posts", Vec<super::Post>)
+            );
+        }
+        let post = |title, date, published| Post {
+            title,
+            date,
+            published,
+        };
+        let posts = vec![
+            post("b", 2, true),
+            post("d", 4, false),
+            post("a", 1, true),
+            post("c", 3, true),
+        ];
+        assert_eq!(templates::latest(posts).render(), "c b |a b c d ");
+    }
+
+    #[test]
+    fn html_equivalence() {
+        mod templates {
+            crate::str!(
+                "card",
+                "<div class=\"card\" id=\"{{id}}\">\n  <p>{{text}}</p>\n  <pre> {{text}}\n</pre>\n</div>"
+            );
+        }
+        crate::assert_html_eq!(
+            templates::card(1, "Tom & Jerry").render(),
+            "<DIV id=1 class='card'><p>Tom &#38; Jerry</p><pre> Tom &amp; Jerry\n</pre></DIV>"
+        );
+        assert_ne!(
+            crate::html::normalize("<pre>a  b</pre>"),
+            crate::html::normalize("<pre>a b</pre>")
+        );
+        assert_ne!(
+            crate::html::normalize("<p>a</p>"),
+            crate::html::normalize("<p>b</p>")
+        );
+        crate::assert_html_eq!("<p>é</p>", "<p> &#233; </p>");
+        crate::assert_html_eq!("<p>日本 <b>語</b></p>", "<p>日本 <b>&#x8a9e;</b></p>");
+        crate::assert_html_eq!("<p>é<br>é</p>", "<p>&#xe9;<br>é</p>");
+    }
+
+    #[test]
+    fn unsupported_syntax() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{Compiler, Options};
+        let mut block_map = std::collections::HashMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let error = |src: &str| compiler.compile(src).err().unwrap().to_string();
+        assert!(error("{{#*inline \"row\"}}<tr></tr>{{/inline}}").starts_with("inline partials"));
+        assert!(error("{{#> layout}}body{{/layout}}").starts_with("partial blocks"));
+        assert!(error("{{#if a}}a{{else if b}}b{{/if}}").contains("{{else}}{{#if x}}"));
+        assert!(error("{{^items}}none{{/items}}").contains("{{#unless name}}"));
+        assert!(error("{{#if user as |u|}}{{u}}{{/if}}").contains("{{#with value as |name|}}"));
+        assert!(error("{{> header}}").starts_with("partials"));
+        assert!(compiler.compile("{{#if a}}a{{else}}b{{/if}}").is_ok());
+    }
+
+    #[test]
+    fn compile_to_string() {
+        use dry_handlebars_parser::compiler::{Options, compile_to_string};
+        let function = compile_to_string(
+            "<p>{{name}}</p>",
+            Options::default(),
+            "render_into",
+            "&self, f: &mut impl std::fmt::Write",
+        )
+        .unwrap();
+        assert!(function.starts_with(
+            "fn render_into(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {\n"
+        ));
+        assert!(function.contains("use ::dry_handlebars::AsDisplayHtml;"));
+        assert!(function.contains("self.name.as_display_html()"));
+        assert!(function.ends_with("Ok(())\n}\n"));
+        assert!(compile_to_string("{{#if}}", Options::default(), "f", "").is_err());
+    }
+
+    #[test]
+    fn markdown_code() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let rust = compiler
+            .compile("<article>{{markdown body}}</article>")
+            .unwrap();
+        let code = rust.code();
+        assert!(code.starts_with(
+            "write!(f, \"<article>{}</article>\", {let source = self.body.to_string();"
+        ));
+        assert!(code.contains(
+            "::pulldown_cmark::Event::Html(raw) | ::pulldown_cmark::Event::InlineHtml(raw) \
+             => ::pulldown_cmark::Event::Text(raw),"
+        ));
+        for tag in ["Link", "Image"] {
+            assert!(code.contains(&format!(
+                "::pulldown_cmark::Tag::{} {{ link_type, dest_url: \
+                 ::dry_handlebars::safe_url(dest_url.into_string()).into(), title, id }}",
+                tag
+            )));
+        }
+        assert!(rust.top_level_vars().contains("body"));
+    }
+
+    #[test]
+    fn markdown() {
+        mod templates {
+            crate::str!("post", "<article>{{markdown body}}</article>");
+        }
+        assert_eq!(
+            templates::post("# Hi\n\n*there* <b>you</b>").render(),
+            "<article><h1>Hi</h1>\n<p><em>there</em> &lt;b&gt;you&lt;/b&gt;</p>\n</article>"
+        );
+        assert_eq!(
+            templates::post(
+                "[x](javascript:alert(1)) ![y](JavaScript:alert(2)) [z](/a) [m](mailto:a@b.c)"
+            )
+            .render(),
+            "<article><p><a href=\"about:invalid#dry-handlebars\">x</a> \
+             <img src=\"about:invalid#dry-handlebars\" alt=\"y\" /> \
+             <a href=\"/a\">z</a> <a href=\"mailto:a@b.c\">m</a></p>\n</article>"
+        );
+    }
+
+    #[test]
+    fn sanitize_code() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        // rendering needs ammonia, so the generated code is checked instead
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let rust = compiler
+            .compile("<div>{{sanitize user.html}}</div>")
+            .unwrap();
+        assert_eq!(
+            rust.code(),
+            "write!(f, \"<div>{}</div>\", ::ammonia::clean(&self.user.html.to_string()))?;"
+        );
+        assert!(rust.using().is_empty());
+    }
+
+    #[test]
+    fn time_element_code() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        // rendering needs chrono, so the generated code is checked instead
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let code = |src| compiler.compile(src).map(|rust| rust.code().to_string());
+        assert_eq!(
+            code("{{time created_at}}").unwrap(),
+            "write!(f, \"{}\", {let value = &self.created_at;\
+             format!(\"<time datetime=\\\"{}\\\">{}</time>\", \
+             value.format(\"%Y-%m-%dT%H:%M:%S%:z\"), value.format(\"%B %-d, %Y\"))})?;"
+        );
+        assert!(
+            code(r#"{{time created_at "%d/%m/%Y"}}"#)
+                .unwrap()
+                .ends_with("value.format(\"%d/%m/%Y\"))})?;")
+        );
+        assert!(code(r#"{{time created_at "%Q"}}"#).is_err());
+        assert!(code("{{time created_at format}}").is_err());
+    }
+
+    #[test]
+    fn log_code() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        // rendering needs the log crate, so the generated code is checked instead
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let code = |src| compiler.compile(src).map(|rust| rust.code().to_string());
+        assert_eq!(
+            code(r#"{{log "rendering user" user.id level="info"}}"#).unwrap(),
+            "write!(f, \"{}\", {::log::info!(\"rendering user {}\", self.user.id);\"\"}\
+             .as_display_html())?;"
+        );
+        assert!(
+            code(r#"{{log "{x}"}}"#)
+                .unwrap()
+                .contains("::log::debug!(\"{{x}}\")")
+        );
+        assert!(code(r#"{{log "x" level="loud"}}"#).is_err());
+        assert!(code(r#"{{log "x" to="stderr"}}"#).is_err());
+    }
+
+    #[test]
+    fn options_builder() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let options = Options::default()
+            .root_var_name(Some("ctx"))
+            .write_var_name("out")
+            .escape_html(false);
+        let rust = Compiler::new(options.clone(), block_map.clone())
+            .compile("<p>{{name}}</p>")
+            .unwrap();
+        assert_eq!(rust.code(), "write!(out, \"<p>{}</p>\", ctx.name)?;");
+        assert!(rust.top_level_vars().contains("name"));
+        assert_eq!(rust.static_len(), "<p></p>".len());
+        assert_eq!(rust.value_writes(), 1);
+        assert!(!rust.nonce() && !rust.cached());
+        let rust = Compiler::new(options.segmented(true), block_map)
+            .compile("<p>{{name}}</p>")
+            .unwrap();
+        assert!(rust.code().contains("push_static"));
+    }
+
+    #[test]
+    fn scan_report() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{
+            BlockMap, Compiler, Options, UsageKind::*, VariableReport,
+        };
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let report = compiler
+            .scan_report(
+                "{{#if title}}<h1>{{title}}</h1>{{/if}}{{user?.name}}{{lookup scores 0}}\
+                 {{#each prices}}{{@key}}{{this}}{{/each}}{{tags.[0]}}{{try_lookup env \"HOME\"}}",
+            )
+            .unwrap();
+        let kinds = |path: &str| {
+            report
+                .iter()
+                .find(|variable| variable.path == path)
+                .map(|variable: &VariableReport| variable.kinds.clone())
+        };
+        assert_eq!(kinds("title"), Some(vec![Display, Bool]));
+        assert_eq!(kinds("user.name"), Some(vec![Display]));
+        assert_eq!(kinds("user"), Some(vec![Optional]));
+        assert_eq!(kinds("scores"), Some(vec![Display, Index]));
+        assert_eq!(kinds("prices"), Some(vec![Iterable, Map]));
+        assert_eq!(kinds("tags"), Some(vec![Index]));
+        assert_eq!(kinds("env"), Some(vec![Display, Map]));
+        assert_eq!(report[0].path, "title");
+    }
+
+    #[test]
+    fn error_location() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        use dry_handlebars_parser::error::line_column;
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let location = |src: &str| compiler.compile(src).err().unwrap().location(src);
+        assert_eq!(location("<ul>\n  {{#each}}{{/each}}\n</ul>"), Some((2, 3)));
+        assert_eq!(location("<p>\n  é {{lookup x 1.5}}</p>"), Some((2, 5)));
+        assert_eq!(location("line one\n<p>{{#if x}}</p>"), Some((2, 4)));
+        assert_eq!(line_column("ab\ncd", 4), (2, 2));
+    }
+
+    #[test]
+    fn ids_in_branches() {
+        mod templates {
+            crate::str!(
+                "message",
+                r#"{{#if ok}}<p id="msg">ok</p>{{else}}<p id="msg">no</p>{{/if}}"#
+            );
+            crate::str!(
+                "list",
+                r#"{{#each items}}<li id="item">{{this}}</li>{{/each}}"#,
+                check_ids = false
+            );
+        }
+        assert_eq!(templates::message(false).render(), r#"<p id="msg">no</p>"#);
+        assert_eq!(
+            templates::list(vec!["a"]).render(),
+            r#"<li id="item">a</li>"#
+        );
+    }
+
+    #[test]
+    fn pretty_code() {
+        mod templates {
+            // documented with its render code
+            crate::str!("flag", "{{#if on}}'{'{{else}}-{{/if}}", pretty = true);
+        }
+        assert_eq!(templates::flag(true).render(), "'{'");
+        assert_eq!(templates::flag(false).render(), "-");
+    }
+
+    #[test]
+    fn all_errors() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let src = "<p>{{lookup x 1.5}}</p>\n{{#each}}{{this.[}}{{/each}}\n\
+                   <b>{{lookup y}}</b>{{title}}\n{{#if z}}";
+        let locations: Vec<_> = compiler
+            .compile_all(src)
+            .err()
+            .unwrap()
+            .iter()
+            .map(|err| err.location(src))
+            .collect();
+        assert_eq!(
+            locations,
+            vec![Some((1, 4)), Some((2, 1)), Some((3, 4)), Some((4, 1))]
+        );
+        assert!(compiler.compile_all("<p>{{title}}</p>").is_ok());
+    }
+
+    /// Decodes a JSON string of the reference vectors, starting with its opening quote
+    fn json_string(literal: &str) -> String {
+        let mut text = String::new();
+        let mut chars = literal.strip_prefix('"').unwrap().chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return text,
+                '\\' => match chars.next().unwrap() {
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'u' => {
+                        let code: String = chars.by_ref().take(4).collect();
+                        text.push(char::from_u32(u32::from_str_radix(&code, 16).unwrap()).unwrap())
+                    }
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+        panic!("unterminated string {}", literal)
+    }
+
+    #[test]
+    fn reference_vectors() {
+        // each shim compiles the template of a vector and renders it from the vector's data
+        macro_rules! shims {
+            ($($name:ident: $template:tt $(, $mapping:tt)* => $render:expr;)*) => {
+                vec![$({
+                    mod $name {
+                        crate::str!("shim", $template $(, $mapping)*);
+                    }
+                    (stringify!($name), $template, $render.render())
+                }),*]
+            };
+        }
+        let shims: Vec<(&str, &str, String)> = shims! {
+            escape_specials: "<p>{{value}}</p>" => escape_specials::shim("<a href='x'>&\"`=");
+            triple_stash: "<div>{{{html}}}</div>" => triple_stash::shim("<b>bold</b> & \"more\"");
+            attribute_value: "<input value=\"{{value}}\">"
+                => attribute_value::shim("\" onfocus=\"alert(1)");
+            unescaped_characters: "{{value}}" => unescaped_characters::shim("a/b\\c%20#?+");
+            unicode: "<p>{{value}}</p>" => unicode::shim("café ü € 😀");
+            numbers_and_booleans: "{{count}} {{price}} {{flag}}"
+                => numbers_and_booleans::shim(3, 9.5, true);
+            whitespace_control: "<ul>\n  {{~value~}}\n</ul>" => whitespace_control::shim("x");
+            whitespace_control_blocks:
+                "<ul>\n  {{~#each items~}}\n  <li>{{this}}</li>\n  {{~/each~}}\n</ul>",
+                ("items", Vec<&'static str>)
+                => whitespace_control_blocks::shim(vec!["a", "b"]);
+            each_index: "{{#each items}}{{@index}}:{{this}};{{/each}}",
+                ("items", Vec<&'static str>)
+                => each_index::shim(vec!["<a>", "b"]);
+            if_else: "{{#if ok}}yes{{else}}no{{/if}}" => if_else::shim(false);
+            unless: "{{#unless ok}}<em>off</em>{{/unless}}" => unless::shim(false);
+            with_path: "{{#with author}}{{first_name}} & {{last_name}}{{/with}}",
+                ("author", super::Author)
+                => with_path::shim(Author {
+                    first_name: "<Ann>".to_string(),
+                    last_name: "O'Neil".to_string(),
+                });
+            nested_path: "{{person.firstname}}", ("person", super::Person)
+                => nested_path::shim(Person {
+                    firstname: "O'Neil".to_string(),
+                    lastname: String::new(),
+                });
+            escaped_mustache: "\\{{value}} {{value}}" => escaped_mustache::shim("v");
+            comments: "a{{! note }}b{{!-- {{not}} --}}c" => comments::shim();
+            standalone_lines: "<ul>\n  {{#each items}}\n  <li>{{this}}</li>\n  {{/each}}\n</ul>",
+                ("items", Vec<&'static str>)
+                => standalone_lines::shim(vec!["a", "b"]);
+            unsafe_url: "<a href=\"{{url}}\">x</a>" => unsafe_url::shim("javascript:alert(1)");
+        };
+        // differences kept on purpose, checked so the list doesn't outlive them
+        let divergences = [
+            (
+                "standalone_lines",
+                "lines of only a block tag or comment are kept",
+            ),
+            ("unsafe_url", "unsafe URL schemes are replaced"),
+            (
+                "escaped_mustache",
+                "an escaped mustache is written without its braces",
+            ),
+        ];
+        let vectors = include_str!("../tests/reference/vectors.tsv");
+        let mut mismatches = Vec::new();
+        let mut names = Vec::new();
+        for line in vectors.lines().filter(|line| !line.starts_with('#')) {
+            let [name, template, _data, expected] = line.split('\t').collect::<Vec<_>>()[..] else {
+                panic!("expected 4 fields in {:?}", line);
+            };
+            names.push(name);
+            let (_, shim_template, actual) = shims
+                .iter()
+                .find(|(shim, ..)| *shim == name)
+                .unwrap_or_else(|| panic!("no shim for vector {}", name));
+            assert_eq!(
+                *shim_template,
+                json_string(template),
+                "template of {}",
+                name
+            );
+            let expected = json_string(expected);
+            let diverges = divergences.iter().any(|(divergent, _)| *divergent == name);
+            match (*actual == expected, diverges) {
+                (true, true) => mismatches.push(format!("{}: no longer diverges", name)),
+                (false, false) => {
+                    let at = actual
+                        .char_indices()
+                        .zip(expected.chars())
+                        .find(|((_, a), e)| a != e)
+                        .map_or(actual.len().min(expected.len()), |((at, _), _)| at);
+                    mismatches.push(format!(
+                        "{}: differs at byte {}\n  handlebars.js: {:?}\n  dry-handlebars: {:?}",
+                        name, at, expected, actual
+                    ))
+                }
+                _ => (),
+            }
+        }
+        for (shim, ..) in &shims {
+            assert!(names.contains(shim), "no vector for shim {}", shim);
+        }
+        assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+    }
+
+    #[test]
+    fn concat_helper() {
+        mod templates {
+            crate::str!(
+                "buttons",
+                "<b class=\"{{concat \"btn btn-\" variant \" {x}\"}}\"></b>{{#each sizes}}<i id=\"{{concat this \"-\" @index}}\"></i>{{/each}}",
+                ("sizes", Vec<&'static str>)
+            );
+        }
+        assert_eq!(
+            templates::buttons("<a>", vec!["sm", "lg"]).render(),
+            "<b class=\"btn btn-&lt;a&gt; {x}\"></b><i id=\"sm-0\"></i><i id=\"lg-1\"></i>"
+        );
+    }
+
+    #[test]
+    fn class_helper() {
+        mod templates {
+            crate::str!(
+                "card",
+                "<div class=\"{{class \"card\" size active=is_active \"card--error\"=has_error \"card--empty\"=(is_empty tags)}}\"></div>",
+                ("tags", Vec<&'static str>)
+            );
+        }
+        assert_eq!(
+            templates::card("<sm>", true, false, vec![]).render(),
+            "<div class=\"card &lt;sm&gt; active card--empty\"></div>"
+        );
+        assert_eq!(
+            templates::card("lg", false, true, vec!["new"]).render(),
+            "<div class=\"card lg card--error\"></div>"
+        );
+    }
+
+    #[test]
+    fn compact_render() {
+        mod templates {
+            crate::str!(
+                "listing",
+                "{{#if open}}<h1>{{title}}</h1>{{/if}}{{add count 1}}{{#each items}}<li>{{this}}</li>{{/each}}{{debug note}}",
+                ("count", u32),
+                ("items", Vec<String>),
+                compact = true
+            );
+        }
+        let note = match cfg!(debug_assertions) {
+            true => "&quot;n&quot;",
+            false => "",
+        };
+        assert_eq!(
+            templates::listing(true, "<T>", 2, vec!["a".into(), "b".into()], "n").render(),
+            format!("<h1>&lt;T&gt;</h1>3<li>a</li><li>b</li>{}", note)
+        );
+        assert_eq!(
+            templates::listing(false, 1.5, 0, vec![], 'n').render(),
+            match cfg!(debug_assertions) {
+                true => "1&#x27;n&#x27;",
+                false => "1",
+            }
+        );
+    }
+
+    #[test]
+    fn csp_nonce() {
+        mod templates {
+            crate::str!(
+                "page",
+                "<script>go()</script><style nonce=\"{{nonce}}\">p{}</style><p data-n=\"{{nonce}}\">{{t \"title\"}}</p>",
+                csp_nonce = true
+            );
+            crate::str!("plain", "<script src=\"{{src}}\"></script>");
+        }
+        let page = templates::page();
+        assert_eq!(
+            page.render_with_nonce("r4nd"),
+            "<script nonce=\"r4nd\">go()</script><style nonce=\"r4nd\">p{}</style><p data-n=\"r4nd\">title</p>"
+        );
+        assert_eq!(
+            page.render(),
+            "<script nonce=\"\">go()</script><style nonce=\"\">p{}</style><p data-n=\"\">title</p>"
+        );
+        assert_eq!(
+            page.render_with_translator_and_nonce(&super::tests::Catalog, "n"),
+            "<script nonce=\"n\">go()</script><style nonce=\"n\">p{}</style><p data-n=\"n\">Panier &amp; paiement</p>"
+        );
+        assert_eq!(
+            templates::plain("a.js").render(),
+            "<script src=\"a.js\"></script>"
+        );
+    }
+
+    #[test]
+    fn debug_helper() {
+        mod templates {
+            crate::str!(
+                "inspect",
+                "<pre>{{debug items}} {{debug user}}</pre>",
+                ("items", Vec<&'static str>)
+            );
+            crate::str!("released", "{{debug user}}", debug_in_release = true);
+        }
+        let expected = match cfg!(debug_assertions) {
+            true => "<pre>[&quot;a&quot;, &quot;&lt;b&gt;&quot;] &quot;Tom&quot;</pre>",
+            false => "<pre> </pre>",
+        };
+        assert_eq!(
+            templates::inspect(vec!["a", "<b>"], "Tom").render(),
+            expected
+        );
+        assert_eq!(templates::released('x').render(), "&#x27;x&#x27;");
+    }
+
+    // generates the `render_all_with_defaults` test
+    mod smoke {
+        crate::directory!("tests/templates", render_all_with_defaults = true);
+    }
+
+    #[test]
+    fn renamed_templates() {
+        mod templates {
+            crate::file!("tests/named/admin/index.hbs", as = "AdminIndex");
+            crate::directory!("tests/named", prefix = "site_", name_by_path = true);
+        }
+        assert_eq!(
+            templates::admin_index("Users").render(),
+            "<h1>Admin: Users</h1>\n"
+        );
+        let index: templates::AdminIndex<&str> = templates::AdminIndex::new("Users");
+        assert_eq!(index.render(), "<h1>Admin: Users</h1>\n");
+        assert_eq!(templates::site_index("Home").render(), "<h1>Home</h1>\n");
+        assert_eq!(
+            templates::site_admin_index("Users").render(),
+            templates::admin_index("Users").render()
+        );
+    }
+
+    #[test]
+    fn nested_modules() {
+        mod templates {
+            crate::directory!("tests/named");
+        }
+        assert_eq!(templates::index("Home").render(), "<h1>Home</h1>\n");
+        let index: templates::admin::index<&str> = templates::admin::index("Users");
+        assert_eq!(index.render(), "<h1>Admin: Users</h1>\n");
+    }
+
+    #[test]
+    fn directory_patterns() {
+        mod templates {
+            crate::directory!("tests/named/**/*.hbs", exclude = ["**/drafts/**"]);
+            pub mod top {
+                crate::directory!("tests/named/*.hbs");
+            }
+        }
+        assert_eq!(
+            templates::admin::index("Users").render(),
+            "<h1>Admin: Users</h1>\n"
+        );
+        assert_eq!(templates::top::index("Home").render(), "<h1>Home</h1>\n");
+    }
+
+    #[test]
+    fn directory_extensions() {
+        mod templates {
+            crate::directory!("tests/modes", extensions = ["hbs", "handlebars"]);
+        }
+        assert_eq!(templates::welcome("<Ann>").render(), "<p>&lt;Ann&gt;</p>\n");
+        assert_eq!(templates::email("<Ann>").render(), "Hello <Ann>\n");
+        assert_eq!(templates::badge("<Ann>").render(), "<b>&lt;Ann&gt;</b>\n");
+    }
+
+    #[test]
+    fn templates_enum() {
+        mod templates {
+            crate::directory!("tests/named", templates_enum = true);
+        }
+        use templates::Templates;
+        assert_eq!(Templates::NAMES.len(), 3);
+        assert!(Templates::NAMES.contains(&"admin/index"));
+        let admin: Box<dyn std::fmt::Display> = Box::new("Users");
+        let template = Templates::from(templates::admin::index(admin));
+        assert_eq!(template.name(), "admin/index");
+        assert_eq!(template.render(), "<h1>Admin: Users</h1>\n");
+        let data: Box<dyn std::any::Any> = Box::new(templates::index::new(
+            Box::new("Home") as Box<dyn std::fmt::Display>
+        ));
+        assert_eq!(
+            templates::render_by_name("index", data.as_ref()).as_deref(),
+            Some("<h1>Home</h1>\n")
+        );
+        assert_eq!(
+            templates::render_by_name("admin/index", data.as_ref()),
+            None
+        );
+        assert_eq!(templates::render_by_name("missing", data.as_ref()), None);
+    }
+
+    #[test]
+    fn template_trait() {
+        mod templates {
+            crate::directory!("tests/modes", extensions = ["hbs"]);
+            crate::str!("card", "<p>{{title}}</p>");
+        }
+        fn describe<T: crate::Template>(template: &T) -> String {
+            let mut out = String::new();
+            template.render_into(&mut out).unwrap();
+            format!(
+                "{} {:?} {} {}",
+                T::NAME,
+                T::SOURCE_PATH.is_some(),
+                T::MIME_TYPE,
+                out
+            )
+        }
+        assert_eq!(
+            describe(&templates::card("<b>")),
+            "card false text/html; charset=utf-8 <p>&lt;b&gt;</p>"
+        );
+        let email = templates::email("Ann");
+        assert_eq!(
+            describe(&email),
+            "email true text/plain; charset=utf-8 Hello Ann\n"
+        );
+        assert!(
+            <templates::welcome<&str> as crate::Template>::SOURCE_PATH
+                .unwrap()
+                .ends_with("welcome.html.hbs")
+        );
+        // the FNV-1a hash of the source, stable across builds
+        assert_eq!(
+            <templates::card<&str> as crate::Template>::CONTENT_HASH,
+            0xdf47_7fbe_3865_aac6
+        );
+        assert_eq!(
+            <templates::card<&str> as crate::Template>::COMPILED_STATIC_LEN,
+            "<p></p>".len()
+        );
+    }
+
+    #[test]
+    fn render_into_writer() {
+        mod templates {
+            crate::str!("item", "<li>{{name}}</li>");
+            crate::str!("script", "<script nonce=\"{{nonce}}\">{{code}}</script>");
+        }
+        let mut buffer = String::from("<ul>");
+        for name in ["a", "<b>"] {
+            templates::item(name).render_into(&mut buffer).unwrap();
+        }
+        assert_eq!(buffer, "<ul><li>a</li><li>&lt;b&gt;</li>");
+        buffer.clear();
+        let script = templates::script("run()");
+        script.render_into_with_nonce(&mut buffer, "abc").unwrap();
+        assert_eq!(buffer, r#"<script nonce="abc">run()</script>"#);
+        assert_eq!(script.render(), r#"<script nonce="">run()</script>"#);
+    }
+
+    #[test]
+    fn builder() {
+        mod templates {
+            crate::str!(
+                "button",
+                "<button id=\"btn-{{id}}\" class=\"{{class \"btn\" active=active}}\">{{label}}</button>",
+                ("id", u32)
+            );
+        }
+        let button = templates::button::builder()
+            .label("Save")
+            .active(true)
+            .id(42)
+            .build();
+        assert_eq!(
+            button.render(),
+            templates::button(42, true, "Save").render()
+        );
+        assert_eq!(
+            button.render(),
+            "<button id=\"btn-42\" class=\"btn active\">Save</button>"
+        );
+    }
+
+    #[test]
+    fn json_opt_in() {
+        // the tests build the macros with the json feature, templates not given `json = true`
+        // get no render_value, so mappings needn't implement Deserialize
+        mod templates {
+            crate::str!(
+                "page",
+                "<h1>{{title.first_name}}</h1>",
+                ("title", crate::tests::Author)
+            );
+        }
+        let author = Author {
+            first_name: "Ada".to_string(),
+            last_name: "Lovelace".to_string(),
+        };
+        assert_eq!(templates::page(author).render(), "<h1>Ada</h1>");
+    }
+
+    #[test]
+    fn render_value() {
+        use crate::RenderError;
+        use serde_json::json;
+        mod templates {
+            crate::str!(
+                "order",
+                "<h1>{{title}}</h1>{{#if note}}<p>{{note}}</p>{{/if}}\
+                 <ul>{{#each lines}}<li>{{this}}</li>{{/each}}</ul>{{count}}",
+                ("note", Option<String>),
+                ("lines", Vec<String>),
+                ("count", u32),
+                json = true
+            );
+        }
+        assert_eq!(
+            templates::order::render_value(&json!({
+                "title": "Tea & cake",
+                "note": "urgent",
+                "lines": ["tea", "cake"],
+                "count": 2
+            })),
+            Ok(
+                "<h1>Tea &amp; cake</h1><p>urgent</p><ul><li>tea</li><li>cake</li></ul>2"
+                    .to_string()
+            )
+        );
+        // options may be missing, generic fields are written as text
+        assert_eq!(
+            templates::order::render_value(&json!({"title": 7, "lines": [], "count": 0})),
+            Ok("<h1>7</h1><ul></ul>0".to_string())
+        );
+        assert_eq!(
+            templates::order::render_value(&json!({"title": "a", "lines": []})),
+            Err(RenderError::Missing("count".to_string()))
+        );
+        assert_eq!(
+            templates::order::render_value(&json!({"count": 1})),
+            Err(RenderError::Missing("title".to_string()))
+        );
+        match templates::order::render_value(&json!({"title": "a", "lines": [], "count": "two"})) {
+            Err(RenderError::Invalid(name, _)) => assert_eq!(name, "count"),
+            other => panic!("expected an invalid count, got {:?}", other),
+        }
+        match templates::order::render_value(&json!({"title": "a", "count": 1, "lines": "tea"})) {
+            Err(RenderError::Invalid(name, _)) => assert_eq!(name, "lines"),
+            other => panic!("expected invalid lines, got {:?}", other),
+        }
+    }
+
+    /// Stand-in for the axum items the `IntoResponse` impls use, axum isn't a dependency here
+    mod axum {
+        pub mod http {
+            pub mod header {
+                pub struct HeaderName(pub &'static str);
+                pub const CONTENT_TYPE: HeaderName = HeaderName("content-type");
+            }
+            pub struct StatusCode(pub u16);
+            impl StatusCode {
+                pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+            }
+        }
+        pub mod response {
+            use super::http::{StatusCode, header::HeaderName};
+            pub struct Response {
+                pub status: u16,
+                pub content_type: Option<&'static str>,
+                pub body: String,
+            }
+            pub trait IntoResponse {
+                fn into_response(self) -> Response;
+            }
+            impl IntoResponse for StatusCode {
+                fn into_response(self) -> Response {
+                    Response {
+                        status: self.0,
+                        content_type: None,
+                        body: String::new(),
+                    }
+                }
+            }
+            impl IntoResponse for ([(HeaderName, &'static str); 1], String) {
+                fn into_response(self) -> Response {
+                    let [(name, value)] = self.0;
+                    assert_eq!(name.0, "content-type");
+                    Response {
+                        status: 200,
+                        content_type: Some(value),
+                        body: self.1,
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn into_response() {
+        use axum::response::IntoResponse;
+        mod templates {
+            crate::str!("page", "<h1>{{title}}</h1>", axum = crate::tests::axum);
+        }
+        struct Failing;
+        impl std::fmt::Display for Failing {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+        let response = templates::page("Hi").into_response();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type, Some("text/html; charset=utf-8"));
+        assert_eq!(response.body, "<h1>Hi</h1>");
+        assert_eq!(templates::page(Failing).into_response().status, 500);
+    }
+
+    #[test]
+    fn argument_order() {
+        mod templates {
+            crate::str!(
+                "sorted",
+                "<h1>{{title}}</h1><p>{{body}}</p><i>{{author}}</i>",
+                arguments = "sorted"
+            );
+            crate::str!(
+                "named",
+                "<h1>{{title}}</h1>{{#if draft}}<em>draft</em>{{/if}}",
+                ("title", String),
+                arguments = "named"
+            );
+        }
+        assert_eq!(
+            templates::sorted("Ann", "Text", "Title").render(),
+            "<h1>Title</h1><p>Text</p><i>Ann</i>"
+        );
+        let literal = templates::named {
+            title: "Title".to_string(),
+            draft: true,
+        };
+        assert_eq!(literal.render(), "<h1>Title</h1><em>draft</em>");
+        let built = templates::named::builder()
+            .draft(false)
+            .title("Title".to_string())
+            .build();
+        assert_eq!(built.render(), "<h1>Title</h1>");
+    }
+
+    #[test]
+    fn mapping_defaults() {
+        mod templates {
+            crate::str!(
+                "heading",
+                "<h1>{{title}}</h1>{{#if subtitle}}<h2>{{subtitle}}</h2>{{/if}}<p>{{level}}</p>",
+                ("title", String),
+                ("subtitle", Option<String>, default = None),
+                ("level", u8, default = 1)
+            );
+        }
+        let heading = templates::heading("Tea".to_string());
+        assert_eq!(heading.render(), "<h1>Tea</h1><p>1</p>");
+        let heading = templates::heading::builder()
+            .title("Tea".to_string())
+            .subtitle(Some("Green".to_string()))
+            .build();
+        assert_eq!(heading.render(), "<h1>Tea</h1><h2>Green</h2><p>1</p>");
+        let mut heading = templates::heading::new("Tea".to_string());
+        heading.level = 2;
+        assert_eq!(heading.render(), "<h1>Tea</h1><p>2</p>");
+    }
+
+    #[test]
+    fn borrowed_fields() {
+        mod templates {
+            crate::str!(
+                "order",
+                "<h1>{{title}}</h1>{{#if note}}<p>{{note}}</p>{{/if}}<ul>{{#each lines}}<li>{{this}}</li>{{/each}}</ul>{{count}}",
+                ("title", String),
+                ("note", Option<String>),
+                ("lines", Vec<String>),
+                ("count", usize),
+                borrow = true
+            );
+            crate::str!(
+                "user",
+                "{{greeting}} {{user.firstname}} {{user.lastname}}",
+                ("user", &'a crate::tests::Person),
+                borrow = true,
+                compact = true
+            );
+            // nothing to borrow, so no lifetime
+            crate::str!(
+                "plain",
+                "<p>{{name}}</p>{{count}}",
+                ("count", u8),
+                borrow = true
+            );
+        }
+        let plain: templates::plain<&str> = templates::plain("Ada", 1);
+        assert_eq!(plain.render(), "<p>Ada</p>1");
+        let title = String::from("Order");
+        let lines = vec!["tea".to_string(), "cake".to_string()];
+        let order: templates::order<'_> = templates::order(&title, Some("urgent"), &lines, 2);
+        assert_eq!(
+            order.render(),
+            "<h1>Order</h1><p>urgent</p><ul><li>tea</li><li>cake</li></ul>2"
+        );
+        let order = templates::order::builder()
+            .title(&title)
+            .note(None)
+            .lines(&lines[..1])
+            .count(1)
+            .build();
+        assert_eq!(order.render(), "<h1>Order</h1><ul><li>tea</li></ul>1");
+        let person = Person {
+            firstname: "Ada".to_string(),
+            lastname: "Lovelace".to_string(),
+        };
+        assert_eq!(
+            templates::user("Hello", &person).render(),
+            "Hello Ada Lovelace"
+        );
+    }
+
+    #[test]
+    fn block_hash_args() {
+        mod templates {
+            crate::str!(
+                "list",
+                "{{#if admin likely=false}}admin {{/if}}{{#unless items likely=true}}none{{/unless}}{{#each items by_ref=false as |item|}}<b>{{item}}</b>{{/each}}",
+                ("admin", bool),
+                ("items", &'static [u8])
+            );
+        }
+        assert_eq!(templates::list(false, &[1, 2]).render(), "<b>1</b><b>2</b>");
+        assert_eq!(templates::list(true, &[]).render(), "admin none");
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{Compiler, Options};
+        let mut block_map = std::collections::HashMap::new();
+        add_builtins(&mut block_map);
+        let compiler = Compiler::new(Options::default(), block_map);
+        let error = |src: &str| compiler.compile(src).err().unwrap().to_string();
+        assert!(
+            error("{{#each items by_value=true}}{{this}}{{/each}}")
+                .starts_with("unknown argument by_value of each, expected cache_key=, by_ref=")
+        );
+        assert!(error("{{#if a likely=1}}a{{/if}}").starts_with("expected likely=true"));
+    }
+
+    #[test]
+    fn generic_bounds() {
+        mod templates {
+            crate::str!(
+                "tags",
+                "{{#if tags}}<ul>{{#each tags}}<li>{{this}}</li>{{/each}}</ul>{{/if}}"
+            );
+            crate::str!(
+                "badge",
+                "{{#if count}}<b>{{count}}</b>{{else}}-{{/if}}",
+                ("count", impl crate::AsBool + std::fmt::Display)
+            );
+            crate::str!(
+                "rows",
+                "{{#each rows}}{{this}};{{/each}}",
+                ("rows", impl IntoIterator<Item = &'x u8>)
+            );
         }
         assert_eq!(
-            template::test("King", "Tubby").render(),
-            "<p>King Tubby</p>"
+            templates::tags(vec!["a", "<b>"]).render(),
+            "<ul><li>a</li><li>&lt;b&gt;</li></ul>"
+        );
+        assert_eq!(
+            templates::tags([1, 2]).render(),
+            "<ul><li>1</li><li>2</li></ul>"
         );
+        assert_eq!(templates::tags(Vec::<u8>::new()).render(), "");
+        assert_eq!(templates::badge(3).render(), "<b>3</b>");
+        assert_eq!(templates::badge(0).render(), "-");
+        assert_eq!(templates::badge("").render(), "-");
+        assert_eq!(templates::rows(vec![1, 2]).render(), "1;2;");
     }
 
-    struct Person {
-        firstname: String,
-        lastname: String,
+    #[test]
+    fn experiment_variants() {
+        mod templates {
+            crate::directory!("tests/experiments", ("page", crate::tests::Page));
+        }
+        use templates::pricing::{self, Variant};
+        let page = Page {
+            title: "Plans".to_string(),
+            links: vec!["Basic".to_string(), "Pro".to_string()],
+        };
+        assert_eq!(
+            pricing::render_variant(Variant::Control, &page),
+            "<p>Plans: Basic Pro </p>\n"
+        );
+        assert_eq!(
+            pricing::render_variant(Variant::ShortList, &page),
+            "<ul><li>Basic</li><li>Pro</li></ul>\n"
+        );
+        assert_eq!(Variant::ALL, [Variant::Control, Variant::ShortList]);
+        assert_eq!(Variant::from_name("short-list"), Some(Variant::ShortList));
+        assert_eq!(Variant::ShortList.name(), "short-list");
     }
 
     #[test]
-    fn path_expressions() {
-        mod template {
+    fn doc_comment() {
+        mod templates {
             crate::str!(
-                "test",
-                //language=handlebars
-                r#"{{person.firstname}} {{person.lastname}}"#,
-                ("person", super::Person)
+                "header",
+                "{{!-- @doc Renders the page header\n  with its title --}}<h1>{{title}}</h1>"
             );
         }
-        let person = Person {
-            firstname: "King".to_string(),
-            lastname: "Tubby".to_string(),
+        // the comment documents the generated items and renders nothing
+        assert_eq!(templates::header("Home").render(), "<h1>Home</h1>");
+    }
+
+    #[test]
+    fn directory_partials() {
+        mod templates {
+            crate::directory!("tests/partials");
+        }
+        assert_eq!(
+            templates::index("Home").render(),
+            "<h1>Home</h1><p>Home</p>\n"
+        );
+        // `_header` is found in the directory's registry, `_nav` next to the template
+        assert_eq!(
+            templates::admin::settings("Admin", "users").render(),
+            "<h1>Admin</h1><nav>users</nav><p>Settings</p>\n"
+        );
+    }
+
+    #[cfg(feature = "hot-reload")]
+    #[test]
+    fn hot_reload_interpreter() {
+        use crate::hot_reload::{Value, render};
+        let path = std::env::temp_dir().join(format!("hot-reload-{}.hbs", std::process::id()));
+        let path = path.to_str().unwrap();
+        let values = [
+            ("name", Value::Text(&"<Jo>")),
+            ("site", Value::Text(&"javascript:alert(1)")),
+            ("admin", Value::Bool(false)),
+        ];
+        let interpret = |src: &str| {
+            std::fs::write(path, src).unwrap();
+            render(path, &values, true)
         };
-        assert_eq!(template::test(person).render(), "King Tubby");
+        assert_eq!(
+            interpret("Hi {{name}} {{{name}}}{{! note }} {{{{raw}}}}{{name}}{{{{/raw}}}}")
+                .as_deref(),
+            Some("Hi &lt;Jo&gt; <Jo> {{name}}")
+        );
+        assert_eq!(
+            interpret("{{#if admin}}admin{{else}}user{{/if}} {{~#unless admin}} ok{{/unless}}")
+                .as_deref(),
+            Some("user ok")
+        );
+        assert_eq!(
+            interpret(r##"<a href="{{site}}">"##).as_deref(),
+            Some(r##"<a href="about:invalid#dry-handlebars">"##)
+        );
+        // anything else renders with the compiled body
+        assert_eq!(interpret("{{missing}}"), None);
+        assert_eq!(interpret("{{#each name}}{{this}}{{/each}}"), None);
+        assert_eq!(interpret("{{#if name}}{{/if}}"), None);
+        assert_eq!(interpret("{{#if admin}}"), None);
+        assert_eq!(interpret("{{name"), None);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(render(path, &values, true), None);
     }
 
-    struct Author {
-        first_name: String,
-        last_name: String,
+    #[test]
+    fn display_templates() {
+        mod templates {
+            crate::str!("badge", "<b>{{label}}</b>");
+            crate::str!("card", "<div>{{{badge}}} {{title}}</div>");
+        }
+        let badge = templates::badge("<new>");
+        assert_eq!(badge.to_string(), badge.render());
+        assert_eq!(format!("[{}]", badge), "[<b>&lt;new&gt;</b>]");
+        assert_eq!(
+            templates::card(badge, "Tea & cake").render(),
+            "<div><b>&lt;new&gt;</b> Tea &amp; cake</div>"
+        );
     }
 
     #[test]
-    fn if_helper() {
-        mod template {
+    fn render_to_io() {
+        mod templates {
             crate::str!(
-                "test",
-                //language=handlebars
-                r#"<div>{{#if has_author}}<h1>{{first_name}} {{last_name}}</h1>{{/if}}</div>"#
+                "page",
+                "<h1>{{title}}</h1>{{#each items}}<p>{{this}}</p>{{/each}}",
+                ("items", Vec<&'static str>)
             );
         }
+        struct Full(usize);
+        impl std::io::Write for Full {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                match self.0 >= buf.len() {
+                    true => {
+                        self.0 -= buf.len();
+                        Ok(buf.len())
+                    }
+                    false => Err(std::io::ErrorKind::StorageFull.into()),
+                }
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let page = templates::page("Café", vec!["ü", "<"]);
+        let mut out = Vec::new();
+        page.render_to_io(&mut out).unwrap();
         assert_eq!(
-            template::test(true, "King", "Tubby").render(),
-            //language=html
-            "<div><h1>King Tubby</h1></div>"
+            String::from_utf8(out).unwrap(),
+            "<h1>Café</h1><p>ü</p><p>&lt;</p>"
         );
+        let err = page.render_to_io(&mut Full(10)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+    }
+
+    #[test]
+    fn size_hint() {
+        mod templates {
+            crate::str!("card", "<div class=\"card\"><h2>{{title}}</h2></div>");
+            crate::str!("note", "<p>{{text}}</p>", compact = true);
+        }
+        let card = templates::card("Tea");
         assert_eq!(
-            template::test(false, "King", "Tubby").render(),
-            //language=html
-            "<div></div>"
+            card.size_hint(),
+            "<div class=\"card\"><h2></h2></div>".len() + 16
         );
+        assert!(card.render().capacity() >= card.size_hint());
+        assert_eq!(templates::note("a").size_hint(), "<p></p>".len() + 16);
     }
 
     #[test]
-    fn unless_helper() {
-        mod template {
+    fn try_render() {
+        mod templates {
+            crate::str!("status", "<p>{{value}}</p>");
+        }
+        struct Failing;
+        impl std::fmt::Display for Failing {
+            fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                Err(std::fmt::Error)
+            }
+        }
+        assert_eq!(
+            templates::status(Failing).try_render(),
+            Err(std::fmt::Error)
+        );
+        assert_eq!(
+            templates::status("ok").try_render().as_deref(),
+            Ok("<p>ok</p>")
+        );
+        let panicked = std::panic::catch_unwind(|| templates::status(Failing).render());
+        assert!(panicked.is_err());
+    }
+
+    /// A value whose `Display` implementation fails
+    struct Broken;
+
+    impl std::fmt::Display for Broken {
+        fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            Err(std::fmt::Error)
+        }
+    }
+
+    struct BrokenPage {
+        title: Broken,
+    }
+
+    #[test]
+    fn try_render_variants() {
+        mod templates {
+            crate::str!("listing", "<p>{{value}}</p>", segments = true);
+            crate::str!("framed", r#"<nav>{{#hole "user"}}{{value}}{{/hole}}</nav>"#);
             crate::str!(
-                "test",
-                //language=handlebars
-                r#"<div>{{#unless has_author}}<h1>Unknown</h1>{{/unless}}</div>"#
+                "page",
+                "<h1>{{page.title}}</h1>",
+                ("page", super::BrokenPage)
             );
         }
+        assert!(templates::listing(Broken).try_render_segments().is_err());
         assert_eq!(
-            template::test(false).render(),
-            //language=html
-            "<div><h1>Unknown</h1></div>"
+            templates::listing("a").try_render_segments().unwrap().len(),
+            "<p>a</p>".len()
+        );
+        assert!(std::panic::catch_unwind(|| templates::listing(Broken).render_segments()).is_err());
+
+        assert!(templates::framed(Broken).try_render_shell().is_ok());
+        assert!(templates::framed(Broken).try_render_hole("user").is_err());
+        assert_eq!(
+            templates::framed("a").try_render_hole("user").as_deref(),
+            Ok("a")
         );
+        assert!(
+            std::panic::catch_unwind(|| templates::framed(Broken).render_hole("user")).is_err()
+        );
+
+        let page = BrokenPage { title: Broken };
         assert_eq!(
-            template::test(true).render(),
-            //language=html
-            "<div></div>"
+            templates::page::try_render_page(&page),
+            Err(std::fmt::Error)
         );
+        assert!(std::panic::catch_unwind(|| templates::page::render_page(&page)).is_err());
     }
 
     #[test]
-    fn if_else_helper() {
+    fn template_fixtures() {
+        mod templates {
+            crate::directory!("tests/templates");
+            crate::str!(
+                "summary",
+                "{{#if open}}{{title}}: {{count}}{{/if}}{{#each tags}} #{{this}}{{/each}}",
+                ("count", u32),
+                ("tags", Vec<String>)
+            );
+        }
+        assert_eq!(templates::summary::fixture().render(), "title: 1 #tags");
+        let mut fixtures = templates::fixtures();
+        fixtures.sort();
+        assert_eq!(
+            fixtures,
+            vec![
+                ("inline", "Hi name".to_string()),
+                ("item", "<li>name</li>\n<li>two</li>\n".to_string()),
+                (
+                    "page",
+                    "<ul>\n  <li>name</li>\n  <li>two</li>\n</ul>\n<p>Hi name</p>\n".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn style_guide_catalog() {
+        mod templates {
+            crate::directory!("tests/named", catalog = true);
+        }
+        assert_eq!(
+            templates::admin::index::preview(),
+            "<h1>Admin: title</h1>\n"
+        );
+        let catalog = templates::catalog();
+        let entry = crate::CatalogEntry::find(&catalog, "admin/index").unwrap();
+        assert!(entry.source_path.ends_with("index.hbs"));
+        assert_eq!(entry.mime_type, "text/html; charset=utf-8");
+        assert_eq!((entry.preview)(), "<h1>Admin: title</h1>\n");
+        let index = crate::catalog_index(&catalog[..1], |name| format!("/styleguide/{}", name));
+        assert_eq!(
+            index,
+            format!(
+                "<ul><li><a href=\"/styleguide/{0}\">{0}</a></li></ul>",
+                catalog[0].name
+            )
+        );
+    }
+
+    #[test]
+    fn workspace_relative_file() {
+        mod templates {
+            crate::file!("example/template/button2.hbs");
+        }
+        assert_eq!(
+            templates::button2(1, "Save").render().trim(),
+            r#"<button id="btn-1" class="btn btn-light">
+    Save
+</button>"#
+        );
+    }
+
+    #[test]
+    fn test_url_escaping() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"<div>{{#if has_author}}<h1>{{first_name}}</h1>{{else}}<h1>Unknown</h1>{{/if}}</div>"#,
-                ("author", Option<super::Author>)
+                r#"<a href="{{link}}" title="{{link}}">{{link}}</a><a href="/search?q={{urlencode query}}">{{query}}</a>"#
             );
         }
         assert_eq!(
-            template::test(true, "King").render(),
-            //language=html
-            r#"<div><h1>King</h1></div>"#
+            template::test("javascript:alert(1)", "a&b c").render(),
+            r#"<a href="about:invalid#dry-handlebars" title="javascript:alert(1)">javascript:alert(1)</a><a href="/search?q=a%26b%20c">a&amp;b c</a>"#
         );
         assert_eq!(
-            template::test(false, "King").render(),
-            //language=html
-            r#"<div><h1>Unknown</h1></div>"#
+            template::test("https://example.com/?a=1&b=2", "ü").render(),
+            r#"<a href="https://example.com/?a&#x3D;1&amp;b&#x3D;2" title="https://example.com/?a&#x3D;1&amp;b&#x3D;2">https://example.com/?a&#x3D;1&amp;b&#x3D;2</a><a href="/search?q=%C3%BC">ü</a>"#
         );
     }
 
     #[test]
-    fn with_helper_option() {
+    fn each_map_key_value() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"<div>{{#with author}}<h1>{{first_name}} {{last_name}}</h1>{{/with}}</div>"#,
-                ("author", Option<super::Author>)
+                r#"{{#each scores as |name score|}}<li>{{name}}: {{score}} ({{@key}})</li>{{/each}}"#,
+                ("scores", std::collections::BTreeMap<&'static str, u32>)
             );
         }
-        let author = Author {
-            first_name: "King".to_string(),
-            last_name: "Tubby".to_string(),
-        };
+        let scores = [("ann", 3), ("bob", 5)].into_iter().collect();
         assert_eq!(
-            template::test(Some(author)).render(),
-            //language=html
-            "<div><h1>King Tubby</h1></div>"
+            template::test(scores).render(),
+            "<li>ann: 3 (ann)</li><li>bob: 5 (bob)</li>"
         );
+    }
+
+    #[test]
+    fn test_bom_and_newlines() {
+        mod template {
+            crate::str!("bom", "\u{feff}<p>{{name}}</p>\r\n");
+            crate::str!("lf", "<p>\r\n{{name}}\r\n</p>", newlines = "lf");
+            crate::str!("crlf", "<p>\n{{name}}\r\n</p>", newlines = "crlf");
+        }
+        assert_eq!(template::bom("a").render(), "<p>a</p>\r\n");
+        assert_eq!(template::lf("a").render(), "<p>\na\n</p>");
+        assert_eq!(template::crlf("a").render(), "<p>\r\na\r\n</p>");
+    }
+
+    #[test]
+    fn test_default_helper() {
+        mod template {
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{default nickname "anonymous"}} {{default nickname name "<none>"}}"#,
+                ("nickname", Option<&'static str>),
+                ("name", Option<&'static str>)
+            );
+        }
         assert_eq!(
-            template::test(None).render(),
-            //language=html
-            "<div></div>"
+            template::test(None, None).render(),
+            "anonymous &lt;none&gt;"
         );
+        assert_eq!(template::test(None, Some("Tom")).render(), "anonymous Tom");
+        assert_eq!(template::test(Some("T"), Some("Tom")).render(), "T T");
     }
 
     #[test]
-    fn with_helper() {
+    fn test_num_helper() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"<div>{{#with author}}<h1>{{first_name}} {{last_name}}</h1>{{/with}}</div>"#,
-                ("author", super::Author)
+                r#"{{num total}} {{num total sep=" " precision=2}} {{num count precision=1}} {{num small}}"#,
+                ("total", f64),
+                ("count", i64),
+                ("small", u8)
             );
         }
-        let author = Author {
-            first_name: "King".to_string(),
-            last_name: "Tubby".to_string(),
-        };
         assert_eq!(
-            template::test(author).render(),
-            //language=html
-            "<div><h1>King Tubby</h1></div>"
+            template::test(1234567.891, -1234, 12).render(),
+            "1,234,567.891 1 234 567.89 -1,234.0 12"
         );
     }
 
     #[test]
-    fn for_helper() {
+    fn test_arithmetic_helpers() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"<div>{{#each authors}}<p>Hello {{first_name}}</p>{{/each}}</div>"#,
-                ("authors", Vec<super::Author>)
+                r#"{{#each items}}{{add @index 1}}. {{this}} {{/each}}{{mul width 2}} {{div width 4}} {{mod width 3}} {{sub width 1}} {{mul width 0.5}}"#,
+                ("items", Vec<&'static str>),
+                ("width", u32)
             );
         }
-        let author = Author {
-            first_name: "King".to_string(),
-            last_name: "Tubby".to_string(),
-        };
         assert_eq!(
-            template::test(vec![author]).render(),
-            //language=html
-            "<div><p>Hello King</p></div>"
+            template::test(vec!["a", "b"], 10).render(),
+            "1. a 2. b 20 2 1 9 5"
         );
     }
 
     #[test]
-    fn test_comment() {
+    fn typed_arithmetic() {
+        mod templates {
+            crate::str!(
+                "prices",
+                "{{mul price 2}} {{add price quantity}} {{div total quantity}}",
+                ("price", f64),
+                ("quantity", u32),
+                ("total", f32)
+            );
+            crate::str!(
+                "counts",
+                "{{add count 1}} {{div width by}} {{mod width by}}",
+                ("count", u8),
+                ("width", u32),
+                ("by", u32)
+            );
+        }
+        assert_eq!(templates::prices(1.25, 3, 7.5).render(), "2.5 4.25 2.5");
+        assert_eq!(templates::counts(1, 7, 2).render(), "2 3 1");
+        // overflow and division by zero fail the render rather than panicking in it
+        assert!(templates::counts(255, 7, 2).try_render().is_err());
+        assert!(templates::counts(1, 7, 0).try_render().is_err());
+    }
+
+    #[test]
+    fn test_index_arithmetic() {
+        mod templates {
+            crate::str!(
+                "rows",
+                "{{#each items}}<li id=\"item-{{add @index 1}}\" class=\"{{cycle (add @index 1) \"a\" \"b\"}}\">{{mul (add @index ../offset) 2}}{{#if @index}}+{{/if}}</li>{{/each}}",
+                ("items", Vec<&'static str>),
+                ("offset", usize)
+            );
+            crate::str!(
+                "grid",
+                "{{#each rows}}{{#each this}}{{add @../index @index}}{{/each}};{{/each}}",
+                ("rows", Vec<Vec<u8>>)
+            );
+        }
+        assert_eq!(
+            templates::rows(vec!["x", "y"], 10).render(),
+            "<li id=\"item-1\" class=\"b\">20</li><li id=\"item-2\" class=\"a\">22+</li>"
+        );
+        assert_eq!(templates::grid(vec![vec![1, 2], vec![3]]).render(), "01;1;");
+    }
+
+    #[test]
+    fn index_arithmetic_at_zero() {
+        mod templates {
+            crate::str!(
+                "previous",
+                "{{#each items}}{{sub @index 1}}-{{sub @index 2 1}}-{{mul @index 2}};{{/each}}",
+                ("items", Vec<&'static str>)
+            );
+        }
+        assert_eq!(
+            templates::previous(vec!["a", "b", "c"]).render(),
+            "0-0-0;0-0-2;1-0-4;"
+        );
+    }
+
+    #[test]
+    fn test_cycle_helper() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"Note: {{! This is a comment }} and {{!-- {{so is this}} --}}\\{{{{}}"#,
+                r#"{{#each items}}<li class="{{cycle @index "odd" "even"}}">{{this}}</li>{{/each}}{{cycle width "a" "b" "c"}}"#,
+                ("items", Vec<&'static str>),
+                ("width", u32)
             );
         }
-        assert_eq!(template::test().render(), "Note:  and \\{{");
+        assert_eq!(
+            template::test(vec!["a", "b", "c"], 5).render(),
+            r#"<li class="odd">a</li><li class="even">b</li><li class="odd">c</li>c"#
+        );
     }
 
     #[test]
-    fn test_trimming() {
+    fn test_inline_unless() {
         mod template {
             crate::str!(
                 "test",
                 //language=handlebars
-                r#"  {{~#if some ~}}   Hello{{~/if~}}"#,
+                r#"<div class="panel {{unless open "hidden"}}">{{unless admin note}}</div>"#,
+                ("note", &'static str)
             );
         }
-        assert_eq!(template::test(true).render(), "Hello");
+        assert_eq!(
+            template::test(false, true, "a & b").render(),
+            r#"<div class="panel hidden"></div>"#
+        );
+        assert_eq!(
+            template::test(true, false, "a & b").render(),
+            r#"<div class="panel ">a &amp; b</div>"#
+        );
     }
 
-    ///
-    ///
-    ///
-    ///
-    ///
-    ///
-    ///
-    ///
-    ///
-    ///
-
     #[test]
-    fn it_works() {
+    fn inline_unless_numbers_and_options() {
         mod template {
-            crate::str!("test", "Hello {{{name}}}!");
+            crate::str!(
+                "test",
+                "{{unless count \"empty\"}}|{{unless score \"zero\"}}|{{unless nickname \"anonymous\"}}\
+                 {{#unless count}}!{{/unless}}",
+                ("count", u32),
+                ("score", f64),
+                ("nickname", Option<String>)
+            );
         }
-        assert_eq!(template::test("King").render(), "Hello King!");
+        assert_eq!(
+            template::test(2, 0.5, Some("Al".to_string())).render(),
+            "||"
+        );
+        assert_eq!(
+            template::test(0, 0.0, None).render(),
+            "empty|zero|anonymous!"
+        );
     }
 
     #[test]
-    fn test_escaped() {
+    fn test_len_and_is_empty() {
         mod template {
             crate::str!(
                 "test",
-                "{{{{skip}}}}wang doodle {{{{/dandy}}}}{{{{/skip}}}}"
+                //language=handlebars
+                r#"{{#if (is_empty results)}}No results{{else}}{{len results}} results{{/if}}"#,
+                ("results", Vec<&'static str>)
             );
         }
-        assert_eq!(template::test().render(), "wang doodle {{{{/dandy}}}}");
+        assert_eq!(template::test(vec![]).render(), "No results");
+        assert_eq!(template::test(vec!["a", "b", "c"]).render(), "3 results");
     }
 
     #[test]
-    fn test_format_number() {
+    fn test_keyword_literals() {
         mod template {
-            crate::str!("test", "Price: ${{format \"{:.2}\" price}}");
+            #[crate::helper]
+            fn toggle(expanded: bool) -> &'static str {
+                if expanded { "open" } else { "closed" }
+            }
+            fn greet(name: Option<&str>) -> &str {
+                name.unwrap_or("stranger")
+            }
+            crate::str!(
+                "test",
+                //language=handlebars
+                r#"{{toggle expanded=false}} {{toggle true}} {{greet null}}"#,
+            );
         }
-        assert_eq!(template::test(12.2345f64).render(), "Price: $12.23");
+        assert_eq!(template::test().render(), "closed open stranger");
+    }
+
+    #[test]
+    fn hash_arguments() {
+        use dry_handlebars_parser::block::add_builtins;
+        use dry_handlebars_parser::compiler::{BlockMap, Compiler, Options};
+        use std::collections::HashMap;
+        let mut block_map = BlockMap::new();
+        add_builtins(&mut block_map);
+        let options = Options::default()
+            .helpers(HashMap::from([(
+                "toggle".to_string(),
+                "crate::helpers::toggle".to_string(),
+            )]))
+            .block_helpers(HashMap::from([(
+                "card".to_string(),
+                "crate::helpers::card".to_string(),
+            )]));
+        let compiler = Compiler::new(options, block_map);
+        let rust = compiler
+            .compile("{{toggle label expanded=false big=true}}{{#card title wide=true}}x{{/card}}")
+            .unwrap();
+        // the parameters they are passed as, after the writer of a block helper
+        assert_eq!(
+            rust.hash_args(),
+            [
+                (
+                    "crate::helpers::toggle".to_string(),
+                    1,
+                    "expanded".to_string()
+                ),
+                ("crate::helpers::toggle".to_string(), 2, "big".to_string()),
+                ("crate::helpers::card".to_string(), 2, "wide".to_string()),
+            ]
+        );
+        let error = |src: &str| compiler.compile(src).unwrap_err().to_string();
+        assert!(error("{{toggle expanded=false label}}").starts_with(
+            "label follows the hash arguments of crate::helpers::toggle, hash arguments come last"
+        ),);
+        assert!(
+            error("{{toggle label big=true big=false}}")
+                .starts_with("big= is given twice to crate::helpers::toggle")
+        );
+        assert!(
+            error("{{#card wide=true title}}x{{/card}}")
+                .starts_with("title follows the hash arguments of crate::helpers::card")
+        );
     }
 
     // #[test]
@@ -0,0 +1,47 @@
+// Renders the escaping and whitespace test vectors with handlebars.js and writes them to
+// ../dry-handlebars/tests/reference/vectors.tsv, which the `reference_vectors` test of
+// dry-handlebars compares its output with. Run with `npm run vectors` after changing them.
+import { writeFileSync } from 'node:fs';
+import Handlebars from 'handlebars';
+
+const vectors = [
+    ['escape_specials', '<p>{{value}}</p>', { value: "<a href='x'>&\"`=" }],
+    ['triple_stash', '<div>{{{html}}}</div>', { html: '<b>bold</b> & "more"' }],
+    ['attribute_value', '<input value="{{value}}">', { value: '" onfocus="alert(1)' }],
+    ['unescaped_characters', '{{value}}', { value: 'a/b\\c%20#?+' }],
+    ['unicode', '<p>{{value}}</p>', { value: 'café ü € 😀' }],
+    ['numbers_and_booleans', '{{count}} {{price}} {{flag}}', { count: 3, price: 9.5, flag: true }],
+    ['whitespace_control', '<ul>\n  {{~value~}}\n</ul>', { value: 'x' }],
+    [
+        'whitespace_control_blocks',
+        '<ul>\n  {{~#each items~}}\n  <li>{{this}}</li>\n  {{~/each~}}\n</ul>',
+        { items: ['a', 'b'] },
+    ],
+    ['each_index', '{{#each items}}{{@index}}:{{this}};{{/each}}', { items: ['<a>', 'b'] }],
+    ['if_else', '{{#if ok}}yes{{else}}no{{/if}}', { ok: false }],
+    ['unless', '{{#unless ok}}<em>off</em>{{/unless}}', { ok: false }],
+    [
+        'with_path',
+        '{{#with author}}{{first_name}} & {{last_name}}{{/with}}',
+        { author: { first_name: '<Ann>', last_name: "O'Neil" } },
+    ],
+    ['nested_path', '{{person.firstname}}', { person: { firstname: "O'Neil" } }],
+    ['escaped_mustache', '\\{{value}} {{value}}', { value: 'v' }],
+    ['comments', 'a{{! note }}b{{!-- {{not}} --}}c', {}],
+    [
+        'standalone_lines',
+        '<ul>\n  {{#each items}}\n  <li>{{this}}</li>\n  {{/each}}\n</ul>',
+        { items: ['a', 'b'] },
+    ],
+    ['unsafe_url', '<a href="{{url}}">x</a>', { url: 'javascript:alert(1)' }],
+];
+
+const lines = [
+    `# generated by reference-ts/vectors.mjs with handlebars ${Handlebars.VERSION}, do not edit`,
+    '# name, template, data and output separated by tabs, strings JSON encoded',
+];
+for (const [name, template, data] of vectors) {
+    const output = Handlebars.compile(template)(data);
+    lines.push([name, JSON.stringify(template), JSON.stringify(data), JSON.stringify(output)].join('\t'));
+}
+writeFileSync(new URL('../dry-handlebars/tests/reference/vectors.tsv', import.meta.url), lines.join('\n') + '\n');